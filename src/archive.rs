@@ -0,0 +1,130 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{error::Result, manifest, version::BedrockVersion};
+use tracing::{debug, info};
+
+/// Length of the checksum prefix in archive names, enough to tell builds of a version apart
+const CHECKSUM_LENGTH: usize = 16;
+
+/// A server zip kept around after it was installed, so it can be installed again later
+#[derive(Debug)]
+pub struct ArchivedVersion {
+    pub version: BedrockVersion,
+    /// Start of the zip's sha256, missing for archives made by older versions of the updater
+    pub checksum: Option<String>,
+    pub path: PathBuf,
+}
+
+impl ArchivedVersion {
+    /// Whether this is the build with the given checksum, any build of the version matches when there is none
+    fn matches(&self, sha256: Option<&str>) -> bool {
+        match (sha256, &self.checksum) {
+            (Some(sha256), Some(checksum)) => sha256.starts_with(checksum.as_str()),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+}
+
+/// Archives are named like the official downloads with the checksum added, e.g. bedrock-server-1.21.0.3-0123456789abcdef.zip
+fn file_name(version: &BedrockVersion, sha256: &str) -> String {
+    format!(
+        "bedrock-server-{version}-{}.zip",
+        &sha256[..CHECKSUM_LENGTH]
+    )
+}
+
+/// Splits an archive name back into its version and checksum
+fn parse_name(name: &str) -> Option<(BedrockVersion, Option<String>)> {
+    let name = name.strip_prefix("bedrock-server-")?.strip_suffix(".zip")?;
+
+    match name.rsplit_once('-') {
+        Some((version, checksum)) => Some((version.parse().ok()?, Some(checksum.to_owned()))),
+        None => Some((name.parse().ok()?, None)),
+    }
+}
+
+/// Lists every archived version, oldest version first
+pub fn list(archive_dir: &Path) -> Result<Vec<ArchivedVersion>> {
+    if !archive_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut archives = Vec::new();
+    for entry in fs::read_dir(archive_dir)? {
+        let path = entry?.path();
+
+        let parsed = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(parse_name);
+
+        // Anything that doesn't look like an archive was not put there by the updater
+        if let Some((version, checksum)) = parsed {
+            archives.push(ArchivedVersion {
+                version,
+                checksum,
+                path,
+            });
+        }
+    }
+
+    archives.sort_by_key(|archived| archived.version);
+
+    Ok(archives)
+}
+
+/// Finds the archived build of a version, so it doesn't have to be downloaded again
+pub fn find(
+    archive_dir: &Path,
+    version: &BedrockVersion,
+    sha256: Option<&str>,
+) -> Result<Option<ArchivedVersion>> {
+    Ok(list(archive_dir)?
+        .into_iter()
+        .find(|archived| archived.version == *version && archived.matches(sha256)))
+}
+
+/// Moves the zip of an installed version into the archive and removes the oldest archives beyond the retention limit
+/// Other builds of the same version are replaced, since the one just installed is the one worth going back to
+pub fn store(archive_dir: &Path, version: &BedrockVersion, zip: &Path, keep: usize) -> Result<()> {
+    fs::create_dir_all(archive_dir)?;
+
+    let path = archive_dir.join(file_name(version, &manifest::hash_file(zip)?));
+    for archived in list(archive_dir)? {
+        if archived.version == *version && archived.path != path {
+            debug!("Replacing archived build {:?}", archived.path);
+            fs::remove_file(&archived.path)?;
+        }
+    }
+
+    debug!("Archiving server zip to {path:?}");
+    // Renaming fails when the archive is on another filesystem
+    if fs::rename(zip, &path).is_err() {
+        fs::copy(zip, &path)?;
+    }
+
+    prune(archive_dir, keep)
+}
+
+/// Lists the oldest archives beyond the retention limit
+pub fn excess(archive_dir: &Path, keep: usize) -> Result<Vec<ArchivedVersion>> {
+    let mut archives = list(archive_dir)?;
+    let excess = archives.len().saturating_sub(keep);
+
+    archives.truncate(excess);
+    Ok(archives)
+}
+
+/// Removes the oldest archives until at most `keep` remain
+pub fn prune(archive_dir: &Path, keep: usize) -> Result<()> {
+    for archive in excess(archive_dir, keep)? {
+        info!("Removing old archived version {}", archive.version);
+        fs::remove_file(&archive.path)?;
+    }
+
+    Ok(())
+}
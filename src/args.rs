@@ -1,32 +1,480 @@
-use clap::{command, Parser};
+use std::{path::PathBuf, time::Duration};
+
+use clap::{ArgAction, Parser, Subcommand};
+
+use crate::{
+    backup::BackupFormat,
+    error::BedrockUpdaterError,
+    layout::InstallLayout,
+    source::{Channel, HeaderProfile, IpVersion},
+    strategy::PathCase,
+    updater::{ModifiedFiles, UpdatePolicy},
+    version::BedrockVersion,
+};
 
 /// Updates a bedrock server continuously
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Default)]
 #[command(version, about, long_about = None)]
 pub struct Args {
-    
+    /// What to do, runs the updater continuously when left out
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Read settings from this TOML config file, command line flags take precedence over it
+    #[arg(short, long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
     /// Use this server directory
-    #[arg(short, long, value_name = "DIR")]
-    pub server_dir: String,
+    #[arg(short, long, global = true, value_name = "DIR")]
+    pub server_dir: Option<PathBuf>,
+
+    /// Update directory relative to the server directory [default: update]
+    #[arg(short, long, global = true, value_name = "DIR")]
+    pub update_dir: Option<PathBuf>,
+
+    /// Extract updates in the system temp directory instead of the update directory, copying out of it is slower when it is on another filesystem
+    #[arg(long, global = true)]
+    pub stage_in_temp: bool,
+
+    /// How installs are laid out, versioned installs each release into versions/ and switches the current link to it [default: in-place]
+    #[arg(long, global = true, value_name = "LAYOUT")]
+    pub layout: Option<InstallLayout>,
+
+    /// Staging directories left behind by runs that did not finish are removed at startup once they are this old [default: 1h]
+    #[arg(long, global = true, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub stale_staging_after: Option<Duration>,
+
+    /// Plain text version file used by older versions of the updater, migrated into the state file [default: version.txt]
+    #[arg(long, global = true, value_name = "FILE")]
+    pub version_file: Option<PathBuf>,
+
+    /// State file recording the installed version, relative to the server directory [default: .updater-state.json]
+    #[arg(long, global = true, value_name = "FILE")]
+    pub state_file: Option<PathBuf>,
+
+    /// Pause file path relative to the server directory, updates are skipped while it exists [default: .updater-pause]
+    #[arg(long, global = true, value_name = "FILE")]
+    pub pause_file: Option<PathBuf>,
+
+    /// Directory installed server zips are cached in by version and checksum, so reinstalls and rollbacks need no download
+    /// Relative to the server directory [default: .updater-archive]
+    #[arg(long, global = true, value_name = "DIR")]
+    pub archive_dir: Option<PathBuf>,
+
+    /// Number of installed server zips to keep for rollbacks [default: 3]
+    #[arg(long, global = true, value_name = "COUNT")]
+    pub keep_archives: Option<usize>,
+
+    /// Number of version directories the versioned layout keeps for instant rollbacks, the running one included [default: 3]
+    #[arg(long, global = true, value_name = "COUNT")]
+    pub keep_versions: Option<usize>,
+
+    /// Directory backups are written to, relative to the server directory [default: backups]
+    #[arg(long, global = true, value_name = "DIR")]
+    pub backup_dir: Option<PathBuf>,
+
+    /// Archive format used for backups [default: tar-gz]
+    #[arg(long, global = true, value_name = "FORMAT")]
+    pub backup_format: Option<BackupFormat>,
+
+    /// What happens to installed files that were changed since the updater laid them down, when an update replaces them [default: overwrite]
+    #[arg(long, global = true, value_name = "ACTION")]
+    pub modified_files: Option<ModifiedFiles>,
+
+    /// Number of files in-place installs copy at the same time [default: 8]
+    #[arg(long, global = true, value_name = "COUNT")]
+    pub copy_jobs: Option<usize>,
+
+    /// Write files that stay locked by another process next to them, and replace them on a later run once they are free
+    /// Without it, an install fails once retrying a locked file gives up
+    #[arg(long, global = true)]
+    pub defer_locked: bool,
+
+    /// Restore the SELinux contexts of everything an install writes, like restorecon, so a confined server can still read it
+    /// Runs the restorecon command, which comes with policycoreutils, and does nothing on systems without SELinux
+    #[arg(long, global = true)]
+    pub restore_contexts: bool,
+
+    /// Start the new server after every install to check that it runs, and go back to the old version when it does not
+    #[arg(long, global = true)]
+    pub smoke_test: bool,
+
+    /// Check every world's level.dat and database before each install, refusing to install over a corrupt world
+    /// They are checked again once the new version is seen running, with the outcome recorded in the history
+    #[arg(long, global = true)]
+    pub check_worlds: bool,
+
+    /// Emulator the server is started through on systems it is not built for, e.g. box64 on aarch64
+    /// Without it, installs fail early on those systems instead of laying down a server that can never start
+    #[arg(long, global = true, value_name = "COMMAND")]
+    pub emulator: Option<String>,
 
-    /// Update directory relative to the server directory
-    #[arg(short, long, value_name = "DIR", default_value = "update")]
-    pub update_dir: String,
+    /// Number of backups the clean command keeps, all backups are kept when not set
+    #[arg(long, global = true, value_name = "COUNT")]
+    pub keep_backups: Option<usize>,
 
-    /// Version path relative to the server directory
-    #[arg(long, value_name = "FILE", default_value = "version.txt")]
-    pub version_file: String,
+    /// Scrape the minecraft.net download page when the download links API fails
+    #[arg(long, global = true)]
+    pub scrape_fallback: bool,
+
+    /// Install release or preview builds of the server [default: release]
+    #[arg(long, global = true, value_name = "CHANNEL")]
+    pub channel: Option<Channel>,
+
+    /// Download type to install, as named by the download links API, overriding the channel's [default: serverBedrockLinux]
+    #[arg(long, global = true, value_name = "PLATFORM")]
+    pub data_platform: Option<String>,
+
+    /// CSS selector for the download button when scraping [default: a.downloadlink[data-platform=<PLATFORM>]]
+    #[arg(long, global = true, value_name = "SELECTOR")]
+    pub selector: Option<String>,
+
+    /// Where specific versions are downloaded from, with {version} in place of the version
+    /// [default: https://www.minecraft.net/bedrockdedicatedserver/bin-linux/bedrock-server-{version}.zip]
+    #[arg(long, global = true, value_name = "URL")]
+    pub version_url: Option<String>,
+
+    /// Where server zips come from, official or the name of a table under [sources] in the config file [default: official]
+    #[arg(long, global = true, value_name = "SOURCE")]
+    pub download_source: Option<String>,
+
+    /// Save the download page here whenever the download link cannot be found on it
+    #[arg(long, global = true, value_name = "DIR")]
+    pub diagnostics_dir: Option<PathBuf>,
+
+    /// Headers sent to the download page when scraping [default: basic]
+    #[arg(long, global = true, value_name = "PROFILE")]
+    pub header_profile: Option<HeaderProfile>,
+
+    /// User agent sent to the download page, overriding the header profile's
+    #[arg(long, global = true, value_name = "AGENT")]
+    pub user_agent: Option<String>,
+
+    /// Shell command that prints the download page, used instead of fetching it with the built in client
+    /// The page url replaces {url}, or is appended when there is no placeholder, e.g. "curl_chrome116 -s"
+    #[arg(long, global = true, value_name = "COMMAND")]
+    pub fetch_command: Option<String>,
+
+    /// Pretend this version is the latest instead of asking the download links API, to rehearse updates on a staging server
+    #[arg(long, global = true, value_name = "VERSION", value_parser = parse_version)]
+    pub simulate_latest: Option<BedrockVersion>,
+
+    /// Pretend this zip is the latest release and install it instead of downloading one
+    /// The version comes from --simulate-latest, or from the file name like bedrock-server-1.21.44.1.zip
+    #[arg(long, global = true, value_name = "ZIP")]
+    pub simulate_download: Option<PathBuf>,
+
+    /// Install the zip at this url instead of looking up the latest release, e.g. on an internal mirror
+    /// The version is still read from the file name, like bedrock-server-1.21.44.1.zip
+    #[arg(long, global = true, value_name = "URL")]
+    pub download_url: Option<String>,
 
     /// Set the version of the server, generally used for setting the initial version
-    #[arg(long, value_name = "VERSION")]
-    pub set_first_version: Option<String>,
+    #[arg(long, global = true, value_name = "VERSION", value_parser = parse_version)]
+    pub set_first_version: Option<BedrockVersion>,
+
+    /// Rewrite a damaged state or version file, keeping the version found in it, after asking for confirmation
+    #[arg(long, global = true)]
+    pub repair_state: bool,
+
+    /// Time to wait between update checks, e.g. 30m or 6h [default: 30m]
+    #[arg(short, long, global = true, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub interval: Option<Duration>,
+
+    /// Maximum random delay added to each interval so many servers don't check at the same time [default: 0s]
+    #[arg(long, global = true, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub jitter: Option<Duration>,
+
+    /// Only install a new version once it has been the latest version for this many hours [default: 0]
+    #[arg(long, global = true, value_name = "HOURS")]
+    pub delay_hours: Option<u64>,
+
+    /// Which updates the run command installs on its own, the others are only reported [default: all]
+    #[arg(long, global = true, value_name = "POLICY")]
+    pub auto: Option<UpdatePolicy>,
+
+    /// Never install this version, e.g. a broken build that is about to get a hotfix, can be given multiple times
+    #[arg(long, global = true, value_name = "VERSION", value_parser = parse_version)]
+    pub skip_version: Vec<BedrockVersion>,
+
+    /// How often network requests are tried before giving up, 1 disables retries [default: 3]
+    #[arg(long, global = true, value_name = "COUNT")]
+    pub retry_attempts: Option<u32>,
+
+    /// Delay before the first retry, doubled for every retry after it [default: 1s]
+    #[arg(long, global = true, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub retry_delay: Option<Duration>,
+
+    /// Send every request through this http, https, socks5 or socks5h proxy, with credentials in the url if it needs them
+    /// [default: HTTP_PROXY, HTTPS_PROXY or ALL_PROXY from the environment]
+    #[arg(long, global = true, value_name = "URL")]
+    pub proxy: Option<String>,
+
+    /// Only connect to the download servers over IPv4 or IPv6, for hosts where the other one is broken
+    #[arg(long, global = true, value_name = "VERSION")]
+    pub ip_version: Option<IpVersion>,
+
+    /// Connect to host at ip instead of what DNS says, e.g. www.minecraft.net:203.0.113.7, can be given multiple times
+    #[arg(long, global = true, value_name = "HOST:IP")]
+    pub resolve: Vec<String>,
+
+    /// Also allow downloading zips from this host, can be given multiple times
+    /// Links on minecraft.net and azureedge.net, the version url, the download url and mirrors are always allowed
+    #[arg(long, global = true, value_name = "HOST")]
+    pub allow_host: Vec<String>,
+
+    /// Largest download accepted in MiB, bigger ones are aborted [default: 1024]
+    #[arg(long, global = true, value_name = "MIB")]
+    pub max_download_size: Option<u64>,
+
+    /// Free space in MiB that has to be left over after a download or an install, checked before starting either [default: 100]
+    #[arg(long, global = true, value_name = "MIB")]
+    pub free_space_margin: Option<u64>,
+
+    /// Download the zip from this mirror when the official download fails, can be given multiple times and is tried in order
+    /// The file name of the official download is appended, e.g. https://artifacts.example.com/bedrock/
+    #[arg(long, global = true, value_name = "URL")]
+    pub mirror: Vec<String>,
+
+    /// Install downloads even when they don't match the checksum published with them
+    #[arg(long, global = true)]
+    pub skip_checksum: bool,
+
+    /// Check less often after this many network or scraping failures in a row, 0 disables it [default: 5]
+    #[arg(long, global = true, value_name = "COUNT")]
+    pub circuit_threshold: Option<u32>,
+
+    /// Octal permissions the server binary is given after every install [default: 755]
+    #[arg(long, global = true, value_name = "MODE")]
+    pub binary_mode: Option<String>,
+
+    /// User and group everything an install writes is given, as names or ids like minecraft:minecraft
+    /// Without it, replaced files keep the owner and permissions they had
+    #[arg(long, global = true, value_name = "USER:GROUP")]
+    pub owner: Option<String>,
+
+    /// Only install files of the server zip matching this pattern, e.g. "*_packs", can be given multiple times
+    #[arg(long, global = true, value_name = "PATTERN")]
+    pub include: Vec<String>,
+
+    /// Never install files of the server zip matching this pattern, e.g. "*.pdb", can be given multiple times
+    #[arg(long, global = true, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+
+    /// Never overwrite existing files matching this pattern, e.g. "worlds/**" or "*.json", can be given multiple times
+    /// These are kept on top of permissions.json, allowlist.json and server.properties
+    #[arg(long, global = true, value_name = "PATTERN")]
+    pub preserve: Vec<String>,
+
+    /// Never write to or delete anything inside existing directories matching this pattern, e.g. "behavior_packs/custom_*"
+    /// These are protected on top of worlds and the development pack directories, can be given multiple times
+    #[arg(long, global = true, value_name = "PATTERN")]
+    pub protect_dir: Vec<String>,
+
+    /// Whether preserve, strategy and protected directory patterns ignore the case of paths [default: auto]
+    /// Auto ignores case when the filesystem of the server directory does, like on windows and macos
+    #[arg(long, global = true, value_name = "CASE")]
+    pub path_case: Option<PathCase>,
+
+    /// Only keep the files given with --preserve and protect the directories given with --protect-dir
+    /// Without it, permissions.json, allowlist.json, server.properties, worlds and the development pack directories are kept too
+    #[arg(long, global = true)]
+    pub no_default_preserve: bool,
+
+    /// Address of the running server, pinged to check that it runs the version in the state file
+    /// [default: 127.0.0.1 with the server-port from server.properties]
+    #[arg(long, global = true, value_name = "ADDRESS")]
+    pub ping_address: Option<String>,
+
+    /// Run a single update check and exit instead of looping forever
+    #[arg(long, global = true)]
+    pub once: bool,
+
+    /// Show what an update changes and ask for confirmation before installing it
+    #[arg(long, global = true)]
+    pub interactive: bool,
+
+    /// Detach from the terminal and run in the background
+    #[arg(long, global = true, conflicts_with_all = ["once", "stop", "interactive"])]
+    pub daemon: bool,
+
+    /// Stop the running daemon recorded in the pid file
+    #[arg(long, global = true)]
+    pub stop: bool,
+
+    /// Pid file written in daemon mode and read by --stop [default: bedrock-updater.pid]
+    #[arg(long, global = true, value_name = "FILE")]
+    pub pid_file: Option<PathBuf>,
+
+    /// Log file used in daemon mode [default: bedrock-updater.log]
+    #[arg(long, global = true, value_name = "FILE")]
+    pub log_file: Option<PathBuf>,
+
+    /// Switch to this user, and group when given like minecraft:minecraft, before doing anything else
+    /// Lets root start the updater without it downloading and installing as root
+    #[arg(long, global = true, value_name = "USER[:GROUP]")]
+    pub run_as: Option<String>,
+
+    /// Only log warnings, or only errors when given twice
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "verbose")]
+    pub quiet: u8,
+
+    /// Log debug messages, or trace messages when given twice
+    /// RUST_LOG takes precedence when set, e.g. RUST_LOG=bedrock_updater::updater=debug,reqwest=warn
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+}
+
+impl Args {
+    /// Whether the command can write to the server or state directory, the ones that only look work without write access
+    pub fn writes_files(&self) -> bool {
+        !matches!(
+            &self.command,
+            Some(
+                Command::Check
+                    | Command::Diff
+                    | Command::Verify
+                    | Command::Status { .. }
+                    | Command::History { .. }
+                    | Command::Man { .. }
+                    | Command::Restore { dry_run: true, .. }
+                    | Command::UndoLastUpdate { dry_run: true, .. }
+                    | Command::Clean { dry_run: true }
+            )
+        )
+    }
+}
+
+/// Actions the updater can perform
+#[derive(Subcommand, Debug, Default, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Check for updates continuously and install them as they come out
+    #[default]
+    Run,
+
+    /// Check once whether an update is available without installing it
+    Check,
+
+    /// Install the latest version right away, ignoring the pause file and stability delay
+    Install {
+        /// Install this version instead of the latest, taken from the archive or downloaded from the version url
+        #[arg(long, value_name = "VERSION", value_parser = parse_version)]
+        target_version: Option<BedrockVersion>,
+
+        /// Install the latest version again even when it is already installed, e.g. to restore deleted default files
+        /// Preserved files are still left alone, and target versions are always installed
+        #[arg(long)]
+        force: bool,
+
+        /// Install this zip from disk without any network access, the version is read from its name
+        #[arg(long, value_name = "ZIP", conflicts_with_all = ["target_version", "force"])]
+        from_zip: Option<PathBuf>,
+    },
+
+    /// Download the latest server zip without installing it
+    Download {
+        /// Directory the zip is saved to
+        #[arg(short, long, value_name = "DIR", default_value = ".")]
+        output: PathBuf,
+    },
+
+    /// Approve the update held back by the update policy, so it gets installed on the next check
+    Approve {
+        /// Only approve the pending update if it is this version
+        #[arg(value_parser = parse_version)]
+        version: Option<BedrockVersion>,
+    },
+
+    /// Reinstall a previously installed version, switching back to its version directory when the versioned layout still has it
+    Rollback {
+        /// Version to roll back to, defaults to the newest archived version older than the current one
+        #[arg(value_parser = parse_version)]
+        version: Option<BedrockVersion>,
+    },
+
+    /// Install a cached build of any version from the archive, without downloading anything
+    Reinstall {
+        /// Version to install, newer or older than the current one
+        #[arg(value_parser = parse_version)]
+        version: BedrockVersion,
+    },
+
+    /// Archive the server directory, or only the given paths inside it, into the backup directory
+    Backup {
+        /// Paths relative to the server directory to back up, e.g. worlds
+        paths: Vec<PathBuf>,
+    },
+
+    /// Unpack a backup archive into the server directory
+    Restore {
+        /// Backup archive to restore, either a path or a file name in the backup directory
+        archive: PathBuf,
+
+        /// Only list what would change without writing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Overwrite existing files without asking
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Put back the files the last in-place install replaced and remove the ones it added, going back to the old version
+    UndoLastUpdate {
+        /// Only list what would change without writing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Undo without asking
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Remove leftover update directories and archives or backups beyond their retention limits
+    Clean {
+        /// Only list what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Check the installed server files against the manifest recorded when they were installed
+    Verify,
+
+    /// Show which installed files the latest release would change, add or leave differing from its defaults, without installing it
+    Diff,
+
+    /// Diagnose the network, permissions and server files and suggest fixes for any problems
+    Doctor,
+
+    /// Show the installed version and what the updater last did
+    Status {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show past update attempts
+    History {
+        /// Print the history as JSON
+        #[arg(long)]
+        json: bool,
 
-    /// Enable quiet mode
-    #[arg(short, long)]
-    pub quiet: bool,
+        /// Only show this many of the most recent updates
+        #[arg(short = 'n', long, value_name = "COUNT")]
+        limit: Option<usize>,
+    },
 
-    /// Enable verbose mode
-    #[arg(short, long)]
-    pub verbose: bool
+    /// Generate man pages for the updater and each of its subcommands
+    Man {
+        /// Directory the man pages are written to
+        #[arg(short, long, value_name = "DIR", default_value = ".")]
+        output: PathBuf,
+    },
+}
 
-}
\ No newline at end of file
+/// Parses versions given on the command line, clap needs an error it can send between threads
+fn parse_version(version: &str) -> Result<BedrockVersion, String> {
+    version
+        .parse()
+        .map_err(|err: BedrockUpdaterError| err.to_string())
+}
@@ -0,0 +1,169 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use clap::ValueEnum;
+use flate2::{write::GzEncoder, Compression};
+use serde::Deserialize;
+use tracing::{debug, info};
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+use crate::{
+    config::Settings,
+    error::{BedrockUpdaterError, Result},
+};
+
+#[derive(ValueEnum, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackupFormat {
+    #[default]
+    TarGz,
+    Zip,
+}
+
+impl BackupFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            BackupFormat::TarGz => "tar.gz",
+            BackupFormat::Zip => "zip",
+        }
+    }
+
+    /// Detects the format of an existing backup from its file name
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let name = path.to_string_lossy();
+
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(BackupFormat::TarGz)
+        } else if name.ends_with(".zip") {
+            Ok(BackupFormat::Zip)
+        } else {
+            Err(BedrockUpdaterError::UnknownBackupFormat(path.to_owned()))
+        }
+    }
+}
+
+/// Archives the given paths of the server directory into a timestamped file in the backup directory
+/// With no paths, the whole server directory is backed up apart from the updater's own directories
+/// Links are stored as links, so whatever they point to is neither archived twice nor taken from outside the server directory
+pub fn create(settings: &Settings, paths: &[PathBuf], format: BackupFormat) -> Result<PathBuf> {
+    fs::create_dir_all(&settings.backup_dir)?;
+
+    let entries = collect_entries(settings, paths)?;
+    let (backup_path, file) = create_file(&settings.backup_dir, format)?;
+
+    info!("Backing up {} entries to {backup_path:?}", entries.len());
+    match format {
+        BackupFormat::TarGz => {
+            let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+            tar.follow_symlinks(false);
+            for relative in &entries {
+                debug!("Adding {relative:?}");
+                tar.append_path_with_name(settings.server_dir.join(relative), relative)?;
+            }
+            tar.into_inner()?.finish()?;
+        }
+        BackupFormat::Zip => {
+            let mut zip = ZipWriter::new(file);
+            let options = SimpleFileOptions::default();
+            for relative in &entries {
+                debug!("Adding {relative:?}");
+                let path = settings.server_dir.join(relative);
+                // Zip entry names always use forward slashes
+                let name = relative.to_string_lossy().replace('\\', "/");
+                let file_type = fs::symlink_metadata(&path)?.file_type();
+                if file_type.is_symlink() {
+                    zip.add_symlink(name, fs::read_link(&path)?.to_string_lossy(), options)?;
+                } else if file_type.is_dir() {
+                    zip.add_directory(name, options)?;
+                } else {
+                    zip.start_file(name, options)?;
+                    io::copy(&mut File::open(&path)?, &mut zip)?;
+                }
+            }
+            zip.finish()?;
+        }
+    }
+
+    Ok(backup_path)
+}
+
+/// Creates the file of a new backup, named after the current time with a random suffix
+/// The name is never one that is taken, so backups started within the same second don't overwrite each other
+fn create_file(backup_dir: &Path, format: BackupFormat) -> Result<(PathBuf, File)> {
+    // Colons are not allowed in Windows file names
+    let timestamp = humantime::format_rfc3339_seconds(SystemTime::now())
+        .to_string()
+        .replace(':', "-");
+
+    loop {
+        let path = backup_dir.join(format!(
+            "backup-{timestamp}-{:08x}.{}",
+            rand::random::<u32>(),
+            format.extension()
+        ));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Lists every file and directory to back up, relative to the server directory
+fn collect_entries(settings: &Settings, paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    // Backing these up would either be recursive or just waste space
+    let excluded = [
+        &settings.backup_dir,
+        &settings.update_dir,
+        &settings.archive_dir,
+    ];
+
+    let roots = if paths.is_empty() {
+        fs::read_dir(&settings.server_dir)?
+            .map(|entry| Ok(PathBuf::from(entry?.file_name())))
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        paths.to_vec()
+    };
+
+    let mut entries = Vec::new();
+    for root in roots {
+        let path = settings.server_dir.join(&root);
+        if fs::symlink_metadata(&path).is_err() {
+            return Err(BedrockUpdaterError::BackupPathNotFound(path));
+        }
+
+        walk(&settings.server_dir, &path, &excluded, &mut entries)?;
+    }
+
+    Ok(entries)
+}
+
+fn walk(
+    server_dir: &Path,
+    path: &Path,
+    excluded: &[&PathBuf],
+    entries: &mut Vec<PathBuf>,
+) -> Result<()> {
+    if excluded.iter().any(|excluded| path.starts_with(excluded)) {
+        return Ok(());
+    }
+
+    let relative = path
+        .strip_prefix(server_dir)
+        .map_err(|_| BedrockUpdaterError::BackupPathNotFound(path.to_owned()))?;
+    entries.push(relative.to_owned());
+
+    // Links are archived as they are, never followed
+    if fs::symlink_metadata(path)?.is_dir() {
+        for entry in fs::read_dir(path)? {
+            walk(server_dir, &entry?.path(), excluded, entries)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use tracing::{debug, error, info};
+
+use crate::error::{BedrockUpdaterError, Result};
+
+/// How much longer the interval gets while the circuit is open
+const OPEN_INTERVAL_FACTOR: u32 = 4;
+
+/// Failures that tend to repeat every cycle until something outside the updater changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FailureKind {
+    Network,
+    Scrape,
+}
+
+impl FailureKind {
+    fn of(err: &BedrockUpdaterError) -> Option<Self> {
+        match err {
+            BedrockUpdaterError::RequestError(_) => Some(FailureKind::Network),
+            BedrockUpdaterError::NoDownloadElement
+            | BedrockUpdaterError::TooManyDownloadElements
+            | BedrockUpdaterError::NoDownloadLinkAttr
+            | BedrockUpdaterError::NoDownloadType(_)
+            | BedrockUpdaterError::NoVersionString
+            | BedrockUpdaterError::UnexpectedPage(..)
+            | BedrockUpdaterError::PageDumped(..)
+            | BedrockUpdaterError::JsonError(_) => Some(FailureKind::Scrape),
+            _ => None,
+        }
+    }
+}
+
+/// Stops repeating the same error every cycle once a failure keeps happening
+/// After enough failures of the same kind in a row, the interval is lengthened and the error is escalated once
+pub struct CircuitBreaker {
+    threshold: u32,
+    kind: Option<FailureKind>,
+    failures: u32,
+}
+
+impl CircuitBreaker {
+    /// A threshold of 0 disables the breaker
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            kind: None,
+            failures: 0,
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.threshold > 0 && self.failures >= self.threshold
+    }
+
+    /// Records the outcome of a cycle, returning whether its error should still be logged as usual
+    pub fn record(&mut self, result: &Result<()>, interval: Duration) -> bool {
+        let Err(err) = result else {
+            if self.is_open() {
+                info!(
+                    "Update checks are working again after {} failures",
+                    self.failures
+                );
+            }
+            self.kind = None;
+            self.failures = 0;
+            return false;
+        };
+
+        let Some(kind) = FailureKind::of(err) else {
+            self.kind = None;
+            self.failures = 0;
+            return true;
+        };
+
+        if self.kind == Some(kind) {
+            self.failures += 1;
+        } else {
+            self.kind = Some(kind);
+            self.failures = 1;
+        }
+
+        if !self.is_open() {
+            return true;
+        }
+
+        if self.failures == self.threshold {
+            error!(
+                "{kind:?} checks failed {} times in a row, checking every {} until they work again: {err}",
+                self.failures,
+                humantime::format_duration(self.interval(interval))
+            );
+        } else {
+            debug!("{kind:?} check failed again: {err}");
+        }
+
+        false
+    }
+
+    /// The interval to wait, which is lengthened while the circuit is open
+    pub fn interval(&self, interval: Duration) -> Duration {
+        match self.is_open() {
+            true => interval.saturating_mul(OPEN_INTERVAL_FACTOR),
+            false => interval,
+        }
+    }
+}
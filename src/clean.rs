@@ -0,0 +1,136 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use tracing::info;
+
+use crate::{archive, config::Settings, error::Result, journal::Journal, layout, staging, updater};
+
+/// Removes artifacts the updater no longer needs and reports how much space was reclaimed
+/// Staging directories of runs that are still going or can still be resumed are left alone, and so is the download while a daemon is running
+pub fn clean(settings: &Settings, daemon_running: bool, dry_run: bool) -> Result<()> {
+    let mut stale: Vec<(PathBuf, &str)> = Vec::new();
+
+    let resumable = Journal::interrupted_staging(&settings.state_path)?;
+    for (staged, _) in staging::stale(
+        &settings.staging_root(),
+        settings.stale_staging_after,
+        resumable.as_deref(),
+    )? {
+        stale.push((staged, "leftover staging directory"));
+    }
+
+    let download = settings.server_dir.join(updater::DOWNLOAD_FILE);
+    if download.exists() {
+        match daemon_running {
+            true => info!("Leaving {download:?} alone since the updater is running"),
+            false => stale.push((download, "leftover download")),
+        }
+    }
+
+    for archived in archive::excess(&settings.archive_dir, settings.keep_archives)? {
+        stale.push((archived.path, "old archived version"));
+    }
+
+    for retained in layout::excess(&settings.server_dir, settings.keep_versions)? {
+        stale.push((retained.path, "old version directory"));
+    }
+
+    if let Some(keep) = settings.keep_backups {
+        for backup in excess_backups(&settings.backup_dir, keep)? {
+            stale.push((backup, "old backup"));
+        }
+    }
+
+    let mut reclaimed = 0;
+    for (path, reason) in &stale {
+        let size = freed_by(path)?;
+        reclaimed += size;
+
+        println!("{reason}: {} ({})", path.display(), format_bytes(size));
+        if !dry_run {
+            if path.is_dir() {
+                fs::remove_dir_all(path)?;
+            } else {
+                fs::remove_file(path)?;
+            }
+        }
+    }
+
+    match (stale.is_empty(), dry_run) {
+        (true, _) => println!("Nothing to clean"),
+        (false, true) => println!("Would reclaim {}", format_bytes(reclaimed)),
+        (false, false) => println!("Reclaimed {}", format_bytes(reclaimed)),
+    }
+
+    Ok(())
+}
+
+/// Lists the oldest backups beyond the retention limit
+/// Backup names start with their timestamp, so sorting them by name sorts them by age
+fn excess_backups(backup_dir: &Path, keep: usize) -> Result<Vec<PathBuf>> {
+    if !backup_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(backup_dir)? {
+        let path = entry?.path();
+        let is_backup = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("backup-"));
+
+        if is_backup && path.is_file() {
+            backups.push(path);
+        }
+    }
+
+    backups.sort();
+    let excess = backups.len().saturating_sub(keep);
+    backups.truncate(excess);
+
+    Ok(backups)
+}
+
+/// The space removing the path frees, which leaves out files that are hard linked from somewhere else
+/// Version directories share most of their files, so their plain size would overstate what cleaning them saves
+fn freed_by(path: &Path) -> Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        let mut size = 0;
+        for entry in fs::read_dir(path)? {
+            size += freed_by(&entry?.path())?;
+        }
+        return Ok(size);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        if metadata.nlink() > 1 {
+            return Ok(0);
+        }
+    }
+
+    Ok(metadata.len())
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
@@ -0,0 +1,260 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use tracing::{debug, info};
+
+use crate::{
+    config::Settings,
+    error::{BedrockUpdaterError, Result},
+    source::DownloadSource,
+};
+
+/// Architecture the official server builds are made for
+const OFFICIAL_ARCH: &str = "x86_64";
+
+/// Section of an ELF file listing the symbol versions it needs from each shared library
+const SHT_GNU_VERNEED: u32 = 0x6fff_fffe;
+
+/// Size of a section header of a 64 bit ELF file
+const SECTION_HEADER_SIZE: usize = 64;
+
+/// A glibc version like 2.35, compared part by part
+type GlibcVersion = (u32, u32);
+
+/// Fails before anything is downloaded when the official builds can't run here and nothing else was set up
+/// An emulator, a custom download source, or a download type or url other than the channel's, means the user has a way to run the server
+pub fn check_host(settings: &Settings) -> Result<()> {
+    let source = &settings.source;
+    let official = source.download_source == DownloadSource::Official
+        && source.data_platform == source.channel.data_platform()
+        && source.version_url == crate::source::default_version_url(&source.data_platform);
+    if std::env::consts::ARCH == OFFICIAL_ARCH || settings.emulator.is_some() || !official {
+        return Ok(());
+    }
+
+    Err(BedrockUpdaterError::UnsupportedArchitecture(
+        std::env::consts::ARCH.to_owned(),
+    ))
+}
+
+/// Makes sure this system can run the server binary, before anything is swapped for it
+/// Binaries that aren't linux executables, and systems without glibc, are left to the smoke test
+pub fn check(binary: &Path, emulator: Option<&str>) -> Result<()> {
+    check_arch(binary, emulator)?;
+    check_glibc(binary)
+}
+
+/// A binary built for another architecture only runs through an emulator
+fn check_arch(binary: &Path, emulator: Option<&str>) -> Result<()> {
+    let machine = match elf_machine(binary) {
+        Ok(Some(machine)) => machine,
+        Ok(None) => return Ok(()),
+        Err(err) => {
+            debug!("Could not read the architecture of {binary:?}: {err}");
+            return Ok(());
+        }
+    };
+
+    let host = std::env::consts::ARCH;
+    let arch = arch_name(machine);
+    if arch == Some(host) {
+        return Ok(());
+    }
+    let arch = arch.map_or_else(|| format!("machine type {machine}"), str::to_owned);
+    match emulator {
+        Some(emulator) => {
+            info!("The new server is built for {arch}, it is started through {emulator}");
+            Ok(())
+        }
+        None => Err(BedrockUpdaterError::ForeignArchitecture(
+            arch,
+            host.to_owned(),
+        )),
+    }
+}
+
+/// Names the ELF machine types the way Rust names architectures, so they compare with this system's
+fn arch_name(machine: u16) -> Option<&'static str> {
+    match machine {
+        3 => Some("x86"),
+        40 => Some("arm"),
+        62 => Some("x86_64"),
+        183 => Some("aarch64"),
+        243 => Some("riscv64"),
+        _ => None,
+    }
+}
+
+/// The machine type of a little endian ELF file, which is at the same place for 32 and 64 bit files
+fn elf_machine(binary: &Path) -> io::Result<Option<u16>> {
+    let mut file = File::open(binary)?;
+    let header = read_at(&mut file, 0, 20)?;
+    if header[..4] != *b"\x7fELF" || header[5] != 1 {
+        return Ok(None);
+    }
+
+    Ok(Some(u16_at(&header, 18)))
+}
+
+/// Makes sure the C library of this system is new enough for the server binary
+fn check_glibc(binary: &Path) -> Result<()> {
+    let Some(host) = host_glibc() else {
+        debug!("This system does not use glibc, skipping the compatibility check");
+        return Ok(());
+    };
+    let required = match required_glibc(binary) {
+        Ok(Some(required)) => required,
+        Ok(None) => {
+            debug!("{binary:?} does not need any glibc version");
+            return Ok(());
+        }
+        Err(err) => {
+            debug!("Could not read the glibc versions {binary:?} needs: {err}");
+            return Ok(());
+        }
+    };
+
+    debug!(
+        "{binary:?} needs glibc {}.{}, this system has {}.{}",
+        required.0, required.1, host.0, host.1
+    );
+    if required > host {
+        return Err(BedrockUpdaterError::IncompatibleGlibc(
+            format!("{}.{}", required.0, required.1),
+            format!("{}.{}", host.0, host.1),
+        ));
+    }
+
+    Ok(())
+}
+
+/// The glibc this process runs with, which is the one the server would get too
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn host_glibc() -> Option<GlibcVersion> {
+    // SAFETY: the version is a static string owned by glibc
+    let version = unsafe { std::ffi::CStr::from_ptr(libc::gnu_get_libc_version()) };
+    parse_version(version.to_str().ok()?)
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+fn host_glibc() -> Option<GlibcVersion> {
+    None
+}
+
+/// Parses the 2.35 of GLIBC_2.35, any part after the second is ignored
+fn parse_version(version: &str) -> Option<GlibcVersion> {
+    let mut parts = version.split('.').map(|part| part.parse::<u32>());
+    match (parts.next(), parts.next()) {
+        (Some(Ok(major)), Some(Ok(minor))) => Some((major, minor)),
+        (Some(Ok(major)), None) => Some((major, 0)),
+        _ => None,
+    }
+}
+
+/// The newest GLIBC_ symbol version the binary needs, read from its version needs section
+/// Returns nothing for files that aren't little endian 64 bit ELF files
+fn required_glibc(binary: &Path) -> io::Result<Option<GlibcVersion>> {
+    let mut file = File::open(binary)?;
+    let header = read_at(&mut file, 0, 64)?;
+    // 64 bit little endian, which is what the linux server is built as
+    if header[..4] != *b"\x7fELF" || header[4] != 2 || header[5] != 1 {
+        return Ok(None);
+    }
+
+    let section_offset = u64_at(&header, 0x28);
+    let section_count = usize::from(u16_at(&header, 0x3c));
+    let sections = read_at(
+        &mut file,
+        section_offset,
+        section_count * SECTION_HEADER_SIZE,
+    )?;
+    let section =
+        |index: usize| &sections[index * SECTION_HEADER_SIZE..(index + 1) * SECTION_HEADER_SIZE];
+
+    let Some(verneed) = (0..section_count)
+        .map(section)
+        .find(|header| u32_at(header, 4) == SHT_GNU_VERNEED)
+    else {
+        return Ok(None);
+    };
+    let strings_index = u32_at(verneed, 40) as usize;
+    if strings_index >= section_count {
+        return Ok(None);
+    }
+    let strings_header = section(strings_index);
+    let strings = read_at(
+        &mut file,
+        u64_at(strings_header, 24),
+        u64_at(strings_header, 32) as usize,
+    )?;
+    let needs = read_at(&mut file, u64_at(verneed, 24), u64_at(verneed, 32) as usize)?;
+
+    // Each library entry is followed by the versions needed from it, both linked by offsets
+    let at = |offset: usize| needs.get(offset..).unwrap_or_default();
+    let mut newest = None;
+    let mut entry = 0;
+    for _ in 0..u32_at(verneed, 44) {
+        let count = u16_at(at(entry), 2);
+        let mut aux = entry + u32_at(at(entry), 8) as usize;
+        for _ in 0..count {
+            let name = string_at(&strings, u32_at(at(aux), 8) as usize);
+            if let Some(version) = name.strip_prefix("GLIBC_").and_then(parse_version) {
+                newest = newest.max(Some(version));
+            }
+            aux += u32_at(at(aux), 12) as usize;
+        }
+
+        match u32_at(at(entry), 12) {
+            0 => break,
+            next => entry += next as usize,
+        }
+    }
+
+    Ok(newest)
+}
+
+/// Reads part of the file, refusing ranges past its end so a damaged header can't ask for gigabytes
+fn read_at(file: &mut File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    if offset.saturating_add(len as u64) > file.metadata()?.len() {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    let mut buffer = vec![0; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buffer)?;
+
+    Ok(buffer)
+}
+
+/// Offsets past the end read as zero, a damaged file then just needs nothing
+fn bytes_at<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    bytes
+        .get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .unwrap_or([0; N])
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes_at(bytes, offset))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes_at(bytes, offset))
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes_at(bytes, offset))
+}
+
+fn string_at(strings: &[u8], offset: usize) -> &str {
+    let rest = strings.get(offset..).unwrap_or_default();
+    let end = rest
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(rest.len());
+
+    std::str::from_utf8(&rest[..end]).unwrap_or_default()
+}
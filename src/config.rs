@@ -0,0 +1,1024 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    net::IpAddr,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use reqwest::Url;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{
+    args::Args,
+    backup::BackupFormat,
+    error::{BedrockUpdaterError, Result},
+    glob::FileFilter,
+    layout::InstallLayout,
+    owner::Owner,
+    ping,
+    retry::{self, RetryPolicy},
+    source::{self, Channel, CustomSource, DownloadSource, HeaderProfile, IpVersion},
+    staging,
+    strategy::{FileModes, PathCase, ProtectedDirs, Strategies, Strategy},
+    updater::{ElseErr, ModifiedFiles, UpdatePolicy},
+    version::BedrockVersion,
+};
+
+pub const DEFAULT_UPDATE_DIR: &str = "update";
+pub const DEFAULT_VERSION_FILE: &str = "version.txt";
+pub const DEFAULT_STATE_FILE: &str = ".updater-state.json";
+pub const DEFAULT_PAUSE_FILE: &str = ".updater-pause";
+pub const DEFAULT_STALE_STAGING_AFTER: Duration = Duration::from_secs(60 * 60);
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30 * 60);
+pub const DEFAULT_ARCHIVE_DIR: &str = ".updater-archive";
+pub const DEFAULT_KEEP_ARCHIVES: usize = 3;
+pub const DEFAULT_KEEP_VERSIONS: usize = 3;
+pub const DEFAULT_BACKUP_DIR: &str = "backups";
+/// Pack directories are thousands of small files, copying a few at once keeps the disk busy
+pub const DEFAULT_COPY_JOBS: usize = 8;
+/// Zips don't always keep the permissions, and a server binary that isn't executable won't start
+pub const DEFAULT_BINARY_MODE: u32 = 0o755;
+/// In MiB, server zips are around a tenth of this
+pub const DEFAULT_MAX_DOWNLOAD_SIZE: u64 = 1024;
+/// In MiB
+pub const DEFAULT_FREE_SPACE_MARGIN: u64 = 100;
+pub const DEFAULT_PID_FILE: &str = "bedrock-updater.pid";
+pub const DEFAULT_LOG_FILE: &str = "bedrock-updater.log";
+pub const DEFAULT_CIRCUIT_THRESHOLD: u32 = 5;
+
+/// Files that are never overwritten by an update once they exist in the server directory, unless default preserving is turned off
+pub const DEFAULT_BLACKLIST: [&str; 3] =
+    ["permissions.json", "allowlist.json", "server.properties"];
+
+/// Directories whose contents are never touched by an update once they exist, unless default preserving is turned off
+pub const DEFAULT_PROTECTED_DIRS: [&str; 4] = [
+    "worlds",
+    "development_behavior_packs",
+    "development_resource_packs",
+    "development_skin_packs",
+];
+
+/// Logs each of the given fields that differ between two values of the same type
+macro_rules! log_changes {
+    ($old:expr, $new:expr, $($field:ident),* $(,)?) => {
+        $(
+            if $old.$field != $new.$field {
+                info!("{}: {:?} -> {:?}", stringify!($field), $old.$field, $new.$field);
+            }
+        )*
+    };
+}
+
+/// Fills each of the given fields that is unset in the first value from the second
+macro_rules! inherit {
+    ($child:expr, $parent:expr, $($field:ident),* $(,)?) => {
+        $(
+            if $child.$field.is_none() {
+                $child.$field = $parent.$field.clone();
+            }
+        )*
+    };
+}
+
+/// The [install] table of the config file
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct InstallConfig {
+    /// Only install files matching these patterns, e.g. ["bedrock_server", "*_packs", "*.json"]
+    pub include: Option<Vec<String>>,
+    /// Never install files matching these patterns, e.g. ["*.pdb", "bedrock_server_how_to.html"]
+    pub exclude: Option<Vec<String>>,
+}
+
+/// A table under [sources] of the config file, a place other than minecraft.net that server zips come from
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SourceConfig {
+    /// Serves the latest release as JSON, e.g. {"version": "1.21.44.01", "url": "https://...", "sha256": "..."}
+    pub latest_url: String,
+    /// Where specific versions are downloaded from, with {version} in place of the version
+    pub version_url: Option<String>,
+}
+
+/// Settings as they are written in the TOML config file
+/// Every field is optional, since anything given on the command line takes precedence
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server_dir: Option<PathBuf>,
+    pub update_dir: Option<PathBuf>,
+    pub stage_in_temp: Option<bool>,
+    pub layout: Option<InstallLayout>,
+    #[serde(with = "humantime_serde")]
+    pub stale_staging_after: Option<Duration>,
+    pub version_file: Option<PathBuf>,
+    pub state_file: Option<PathBuf>,
+    pub pause_file: Option<PathBuf>,
+    pub archive_dir: Option<PathBuf>,
+    pub keep_archives: Option<usize>,
+    pub keep_versions: Option<usize>,
+    pub backup_dir: Option<PathBuf>,
+    pub backup_format: Option<BackupFormat>,
+    pub keep_backups: Option<usize>,
+    pub modified_files: Option<ModifiedFiles>,
+    pub copy_jobs: Option<usize>,
+    pub defer_locked: Option<bool>,
+    pub restore_contexts: Option<bool>,
+    pub smoke_test: Option<bool>,
+    pub check_worlds: Option<bool>,
+    pub emulator: Option<String>,
+    #[serde(with = "humantime_serde")]
+    pub interval: Option<Duration>,
+    #[serde(with = "humantime_serde")]
+    pub jitter: Option<Duration>,
+    pub delay_hours: Option<u64>,
+    pub auto: Option<UpdatePolicy>,
+    pub circuit_threshold: Option<u32>,
+    pub ping_address: Option<String>,
+    pub scrape_fallback: Option<bool>,
+    pub channel: Option<Channel>,
+    pub data_platform: Option<String>,
+    pub selector: Option<String>,
+    pub version_url: Option<String>,
+    /// Where server zips come from, "official" or the name of one of the sources
+    pub download_source: Option<String>,
+    /// Download sources by name, for mirrors and builds minecraft.net doesn't publish
+    pub sources: Option<BTreeMap<String, SourceConfig>>,
+    pub diagnostics_dir: Option<PathBuf>,
+    pub header_profile: Option<HeaderProfile>,
+    pub user_agent: Option<String>,
+    pub fetch_command: Option<String>,
+    pub retry_attempts: Option<u32>,
+    #[serde(with = "humantime_serde")]
+    pub retry_delay: Option<Duration>,
+    pub skip_checksum: Option<bool>,
+    pub mirrors: Option<Vec<String>>,
+    pub proxy: Option<String>,
+    pub ip_version: Option<IpVersion>,
+    pub resolve: Option<Vec<String>>,
+    pub download_url: Option<String>,
+    pub allowed_hosts: Option<Vec<String>>,
+    /// SHA-256 fingerprints of the certificates each download host may present
+    pub tls_pins: Option<BTreeMap<String, Vec<String>>>,
+    pub max_download_size: Option<u64>,
+    pub free_space_margin: Option<u64>,
+    /// Octal mode of the server binary after an install, e.g. "750"
+    pub binary_mode: Option<String>,
+    /// User and group installed files are given, e.g. "minecraft:minecraft"
+    pub owner: Option<String>,
+    pub pid_file: Option<PathBuf>,
+    pub log_file: Option<PathBuf>,
+    /// User the updater switches to once started, e.g. "minecraft"
+    pub run_as: Option<String>,
+    /// Replaces the default preserved files, kept for configs of older versions of the updater
+    pub blacklist: Option<Vec<String>>,
+    /// Patterns of files that are never overwritten once they exist, on top of the defaults
+    pub preserve: Option<Vec<String>>,
+    /// Patterns of directories whose contents are never touched once they exist, on top of the defaults
+    pub protected_dirs: Option<Vec<String>>,
+    /// Whether the patterns of preserved files, strategies and protected directories ignore case
+    pub path_case: Option<PathCase>,
+    /// Whether the default files are preserved and the default directories protected, the blacklist replaces the default files
+    pub default_preserve: Option<bool>,
+    /// Strategies by path pattern, taking precedence over the preserved files
+    pub strategies: Option<BTreeMap<String, Strategy>>,
+    /// Octal modes installed files are given by path pattern, e.g. {"*.json" = "640"}
+    pub modes: Option<BTreeMap<String, String>>,
+    pub skip_versions: Option<Vec<BedrockVersion>>,
+    /// Which files of the server zip are installed
+    pub install: Option<InstallConfig>,
+    /// A preview server kept up to date next to the main one
+    pub preview: Option<Box<Config>>,
+}
+
+impl Config {
+    /// Reads and parses the config file
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| BedrockUpdaterError::ConfigReadError(path.to_owned(), err))?;
+
+        Ok(toml::from_str(&contents)?)
+    }
+
+    /// The config of the preview server, where anything it leaves out is taken from the main config
+    /// Only the server directory, the channel and the download source are never inherited, since sources publish one channel
+    fn preview_config(&self) -> Result<Config> {
+        let mut preview = *self
+            .preview
+            .clone()
+            .ok_or(BedrockUpdaterError::NoPreviewConfig)?;
+        preview
+            .preview
+            .is_none()
+            .else_err(BedrockUpdaterError::NoPreviewConfig)?;
+
+        preview.channel.get_or_insert(Channel::Preview);
+        inherit!(
+            preview,
+            self,
+            update_dir,
+            stage_in_temp,
+            stale_staging_after,
+            layout,
+            version_file,
+            state_file,
+            pause_file,
+            archive_dir,
+            keep_archives,
+            keep_versions,
+            backup_dir,
+            backup_format,
+            keep_backups,
+            modified_files,
+            copy_jobs,
+            defer_locked,
+            restore_contexts,
+            smoke_test,
+            check_worlds,
+            emulator,
+            interval,
+            jitter,
+            delay_hours,
+            auto,
+            circuit_threshold,
+            scrape_fallback,
+            version_url,
+            sources,
+            diagnostics_dir,
+            header_profile,
+            user_agent,
+            fetch_command,
+            retry_attempts,
+            retry_delay,
+            skip_checksum,
+            mirrors,
+            allowed_hosts,
+            tls_pins,
+            max_download_size,
+            free_space_margin,
+            binary_mode,
+            owner,
+            blacklist,
+            preserve,
+            protected_dirs,
+            path_case,
+            default_preserve,
+            strategies,
+            modes,
+            skip_versions,
+            install,
+        );
+
+        Ok(preview)
+    }
+}
+
+/// The servers a single updater can keep up to date
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Installation {
+    /// The server given on the command line or at the top of the config file
+    Main,
+    /// The server in the [preview] table of the config file
+    /// Command line flags are meant for the main server, so only the config file applies to it
+    Preview,
+}
+
+impl Installation {
+    pub fn resolve(self, args: &Args, config: Config) -> Result<Settings> {
+        let settings = match self {
+            Installation::Main => Settings::merge(args, config)?,
+            Installation::Preview => Settings::merge(&Args::default(), config.preview_config()?)?,
+        };
+        settings.validate(args.writes_files())?;
+
+        Ok(settings)
+    }
+}
+
+/// The settings the updater actually runs with, after merging the command line and the config file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub server_dir: PathBuf,
+    /// Every install extracts into its own directory inside this one
+    pub update_dir: PathBuf,
+    /// Extract into the system temp directory instead of the update directory
+    pub stage_in_temp: bool,
+    /// Age after which staging directories of runs that are gone are removed
+    pub stale_staging_after: Duration,
+    pub layout: InstallLayout,
+    /// Plain text version file of older versions of the updater, only read to migrate it
+    pub version_path: PathBuf,
+    pub state_path: PathBuf,
+    pub pause_path: PathBuf,
+    pub archive_dir: PathBuf,
+    pub keep_archives: usize,
+    /// Version directories kept by the versioned layout, the running one included
+    pub keep_versions: usize,
+    pub backup_dir: PathBuf,
+    pub backup_format: BackupFormat,
+    pub keep_backups: Option<usize>,
+    /// What installs do with files changed since they were laid down
+    pub modified_files: ModifiedFiles,
+    /// Files copied at the same time by in-place installs
+    pub copy_jobs: usize,
+    /// Locked files are replaced on a later run instead of failing the install
+    pub defer_locked: bool,
+    /// Installed files get the SELinux contexts of where they end up
+    pub restore_contexts: bool,
+    /// Installs are rolled back when the new server does not start
+    pub smoke_test: bool,
+    /// Installs refuse corrupt worlds, and check them again after the new version started
+    pub check_worlds: bool,
+    /// Command the server binary is started through, for systems it is not built for
+    pub emulator: Option<String>,
+    pub interval: Duration,
+    pub jitter: Duration,
+    pub stability_delay: Duration,
+    pub update_policy: UpdatePolicy,
+    pub circuit_threshold: u32,
+    pub ping_address: String,
+    /// Permissions the server binary gets after every install
+    pub binary_mode: u32,
+    /// Who the installed files belong to, the replaced files' owners are kept when not set
+    pub owner: Option<Owner>,
+    pub source: SourceSettings,
+    pub filter: FileFilter,
+    /// What happens to existing files, the preserved ones are kept
+    pub strategies: Strategies,
+    /// Permissions installed files are given, the server binary always gets the binary mode
+    pub modes: FileModes,
+    /// Directories installs never write into once they exist
+    pub protected_dirs: ProtectedDirs,
+    pub skip_versions: BTreeSet<BedrockVersion>,
+}
+
+impl Settings {
+    /// Merges the command line arguments over the config file values, falling back to the defaults
+    pub fn resolve(args: &Args, config: Config) -> Result<Self> {
+        let settings = Self::merge(args, config)?;
+        settings.validate(args.writes_files())?;
+
+        Ok(settings)
+    }
+
+    /// Merges the settings without validating the paths, so problems can be reported one by one
+    pub fn merge(args: &Args, config: Config) -> Result<Self> {
+        let source = SourceSettings::resolve(args, &config)?;
+
+        let server_dir = args
+            .server_dir
+            .clone()
+            .or(config.server_dir)
+            .ok_or(BedrockUpdaterError::NoServerDir)?;
+
+        // Canonicalizing makes sure the server directory exists and gives reliable paths to compare against
+        let server_dir = server_dir
+            .canonicalize()
+            .map_err(|err| BedrockUpdaterError::InvalidServerDir(server_dir, err))?;
+
+        // These paths are all relative to the server directory
+        let update_dir = args.update_dir.clone().or(config.update_dir);
+        let version_file = args.version_file.clone().or(config.version_file);
+        let state_file = args.state_file.clone().or(config.state_file);
+        let pause_file = args.pause_file.clone().or(config.pause_file);
+        let archive_dir = args.archive_dir.clone().or(config.archive_dir);
+        let backup_dir = args.backup_dir.clone().or(config.backup_dir);
+
+        let delay_hours = args.delay_hours.or(config.delay_hours).unwrap_or(0);
+
+        let default_preserve = !args.no_default_preserve && config.default_preserve.unwrap_or(true);
+        let defaults = match config.blacklist {
+            Some(blacklist) => blacklist,
+            None if default_preserve => DEFAULT_BLACKLIST.into_iter().map(String::from).collect(),
+            None => Vec::new(),
+        };
+        // Patterns from the command line are added to the ones in the config file
+        let preserved: BTreeSet<String> = defaults
+            .into_iter()
+            .chain(args.preserve.iter().cloned())
+            .chain(config.preserve.unwrap_or_default())
+            .collect();
+        let path_case = args.path_case.or(config.path_case).unwrap_or_default();
+        let ignore_case = path_case.ignores_case(&server_dir);
+        let strategies = Strategies::new(
+            &preserved,
+            &config.strategies.unwrap_or_default(),
+            ignore_case,
+        )?;
+        let modes = config
+            .modes
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(pattern, mode)| Ok((pattern, parse_mode(&mode)?)))
+            .collect::<Result<_>>()?;
+        let modes = FileModes::new(&modes, ignore_case)?;
+        let default_protected: &[&str] = match default_preserve {
+            true => &DEFAULT_PROTECTED_DIRS,
+            false => &[],
+        };
+        let protected_dirs: Vec<String> = default_protected
+            .iter()
+            .map(|dir| String::from(*dir))
+            .chain(args.protect_dir.iter().cloned())
+            .chain(config.protected_dirs.unwrap_or_default())
+            .collect();
+        let protected_dirs = ProtectedDirs::new(&protected_dirs, ignore_case)?;
+
+        // Versions given on the command line are skipped on top of the ones in the config file
+        let skip_versions = args
+            .skip_version
+            .iter()
+            .cloned()
+            .chain(config.skip_versions.unwrap_or_default())
+            .collect();
+
+        let update_dir = update_dir.unwrap_or_else(|| PathBuf::from(DEFAULT_UPDATE_DIR));
+        let version_file = version_file.unwrap_or_else(|| PathBuf::from(DEFAULT_VERSION_FILE));
+        let state_file = state_file.unwrap_or_else(|| PathBuf::from(DEFAULT_STATE_FILE));
+        let pause_file = pause_file.unwrap_or_else(|| PathBuf::from(DEFAULT_PAUSE_FILE));
+        let archive_dir = archive_dir.unwrap_or_else(|| PathBuf::from(DEFAULT_ARCHIVE_DIR));
+        let backup_dir = backup_dir.unwrap_or_else(|| PathBuf::from(DEFAULT_BACKUP_DIR));
+        let layout = args.layout.or(config.layout).unwrap_or_default();
+        let ping_address = args
+            .ping_address
+            .clone()
+            .or(config.ping_address)
+            .unwrap_or_else(|| ping::local_address(&layout.live_dir(&server_dir)));
+        // Patterns from the command line are added to the ones in the config file
+        let install = config.install.unwrap_or_default();
+        let patterns = |args: &[String], config: Option<Vec<String>>| -> Vec<String> {
+            args.iter()
+                .cloned()
+                .chain(config.unwrap_or_default())
+                .collect()
+        };
+        let filter = FileFilter::new(
+            &patterns(&args.include, install.include),
+            &patterns(&args.exclude, install.exclude),
+        )?;
+        let binary_mode = match args
+            .binary_mode
+            .as_deref()
+            .or(config.binary_mode.as_deref())
+        {
+            Some(mode) => parse_mode(mode)?,
+            None => DEFAULT_BINARY_MODE,
+        };
+        let owner = args
+            .owner
+            .as_deref()
+            .or(config.owner.as_deref())
+            .map(Owner::parse)
+            .transpose()?;
+
+        Ok(Self {
+            update_dir: normalize(&server_dir.join(update_dir)),
+            stage_in_temp: args.stage_in_temp || config.stage_in_temp.unwrap_or(false),
+            stale_staging_after: args
+                .stale_staging_after
+                .or(config.stale_staging_after)
+                .unwrap_or(DEFAULT_STALE_STAGING_AFTER),
+            layout,
+            version_path: normalize(&server_dir.join(version_file)),
+            state_path: normalize(&server_dir.join(state_file)),
+            pause_path: normalize(&server_dir.join(pause_file)),
+            archive_dir: normalize(&server_dir.join(archive_dir)),
+            keep_archives: args
+                .keep_archives
+                .or(config.keep_archives)
+                .unwrap_or(DEFAULT_KEEP_ARCHIVES),
+            keep_versions: args
+                .keep_versions
+                .or(config.keep_versions)
+                .unwrap_or(DEFAULT_KEEP_VERSIONS),
+            backup_dir: normalize(&server_dir.join(backup_dir)),
+            backup_format: args
+                .backup_format
+                .or(config.backup_format)
+                .unwrap_or_default(),
+            keep_backups: args.keep_backups.or(config.keep_backups),
+            modified_files: args
+                .modified_files
+                .or(config.modified_files)
+                .unwrap_or_default(),
+            copy_jobs: args
+                .copy_jobs
+                .or(config.copy_jobs)
+                .unwrap_or(DEFAULT_COPY_JOBS)
+                .max(1),
+            defer_locked: args.defer_locked || config.defer_locked.unwrap_or(false),
+            restore_contexts: args.restore_contexts || config.restore_contexts.unwrap_or(false),
+            smoke_test: args.smoke_test || config.smoke_test.unwrap_or(false),
+            check_worlds: args.check_worlds || config.check_worlds.unwrap_or(false),
+            emulator: args.emulator.clone().or(config.emulator),
+            server_dir,
+            interval: args
+                .interval
+                .or(config.interval)
+                .unwrap_or(DEFAULT_INTERVAL),
+            jitter: args.jitter.or(config.jitter).unwrap_or_default(),
+            stability_delay: Duration::from_secs(delay_hours * 60 * 60),
+            update_policy: args.auto.or(config.auto).unwrap_or_default(),
+            circuit_threshold: args
+                .circuit_threshold
+                .or(config.circuit_threshold)
+                .unwrap_or(DEFAULT_CIRCUIT_THRESHOLD),
+            ping_address,
+            binary_mode,
+            owner,
+            source,
+            filter,
+            strategies,
+            modes,
+            protected_dirs,
+            skip_versions,
+        })
+    }
+
+    /// Checks that the paths are usable before anything is downloaded
+    /// Problems are much easier to fix when they are found at startup instead of halfway through an install
+    /// Write access is only checked when the command can write, the probe file would be a change of its own
+    pub fn validate(&self, writes: bool) -> Result<()> {
+        self.server_dir
+            .is_dir()
+            .else_err(BedrockUpdaterError::NotADirectory(self.server_dir.clone()))?;
+        if writes {
+            check_writable(&self.server_dir)?;
+        }
+
+        // The update directory gets deleted after every install, so it must never hold anything else
+        let update_relative = self
+            .update_dir
+            .strip_prefix(&self.server_dir)
+            .map_err(|_| BedrockUpdaterError::UnsafeUpdateDir(self.update_dir.clone()))?;
+
+        let preserved = update_relative.components().next().is_none_or(|first| {
+            self.strategies.for_path(Path::new(first.as_os_str())) != Strategy::Overwrite
+        });
+        (!preserved).else_err(BedrockUpdaterError::UnsafeUpdateDir(
+            self.update_dir.clone(),
+        ))?;
+
+        if self.update_dir.exists() {
+            self.update_dir
+                .is_dir()
+                .else_err(BedrockUpdaterError::NotADirectory(self.update_dir.clone()))?;
+        }
+
+        // Worlds and packs in protected directories would be deleted along with the update directory
+        let protected = self.protected_dirs.covers(update_relative)
+            || (self.update_dir.is_dir()
+                && !self
+                    .protected_dirs
+                    .find(&self.server_dir, update_relative)?
+                    .is_empty());
+        (!protected).else_err(BedrockUpdaterError::UnsafeUpdateDir(
+            self.update_dir.clone(),
+        ))?;
+
+        // Archives and backups have to survive the update directory being cleaned up
+        (!self.archive_dir.starts_with(&self.update_dir)
+            && !self.backup_dir.starts_with(&self.update_dir))
+        .else_err(BedrockUpdaterError::UnsafeUpdateDir(
+            self.update_dir.clone(),
+        ))?;
+
+        let state_dir = self
+            .state_path
+            .parent()
+            .ok_or(BedrockUpdaterError::NoFileName)?;
+        state_dir
+            .is_dir()
+            .else_err(BedrockUpdaterError::NotADirectory(state_dir.to_owned()))?;
+        if writes {
+            check_writable(state_dir)?;
+        }
+
+        for path in [&self.state_path, &self.version_path] {
+            if path.exists() {
+                path.is_file()
+                    .else_err(BedrockUpdaterError::NotAFile(path.clone()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The directory the server runs from, which is where new files end up
+    pub fn live_dir(&self) -> PathBuf {
+        self.layout.live_dir(&self.server_dir)
+    }
+
+    /// Where the staging directories of installs are created
+    pub fn staging_root(&self) -> PathBuf {
+        match self.stage_in_temp {
+            true => staging::temp_root(),
+            false => self.update_dir.clone(),
+        }
+    }
+
+    /// Logs every setting that changed, used when the config file is reloaded
+    pub fn log_changes(&self, new: &Settings) {
+        if self == new {
+            info!("Settings are unchanged");
+            return;
+        }
+
+        log_changes!(
+            self,
+            new,
+            server_dir,
+            update_dir,
+            stage_in_temp,
+            stale_staging_after,
+            layout,
+            version_path,
+            state_path,
+            pause_path,
+            archive_dir,
+            keep_archives,
+            keep_versions,
+            backup_dir,
+            backup_format,
+            keep_backups,
+            modified_files,
+            copy_jobs,
+            defer_locked,
+            restore_contexts,
+            smoke_test,
+            check_worlds,
+            emulator,
+            interval,
+            jitter,
+            stability_delay,
+            update_policy,
+            circuit_threshold,
+            ping_address,
+            binary_mode,
+            owner,
+            source,
+            filter,
+            strategies,
+            modes,
+            protected_dirs,
+            skip_versions,
+        );
+    }
+}
+
+/// Settings for finding the latest server download
+/// These don't need a server directory, so downloading on its own works without one
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceSettings {
+    pub scrape_fallback: bool,
+    pub channel: Channel,
+    pub data_platform: String,
+    pub selector: String,
+    pub version_url: String,
+    pub download_source: DownloadSource,
+    pub diagnostics_dir: Option<PathBuf>,
+    pub header_profile: HeaderProfile,
+    pub user_agent: Option<String>,
+    pub fetch_command: Option<String>,
+    pub simulate_latest: Option<BedrockVersion>,
+    pub simulate_download: Option<PathBuf>,
+    pub retry: RetryPolicy,
+    pub skip_checksum: bool,
+    pub mirrors: Vec<Url>,
+    pub download_url: Option<Url>,
+    /// Hosts zips may be downloaded from, subdomains included
+    pub allowed_hosts: Vec<String>,
+    /// Certificate fingerprints by host, as lowercase hex without separators
+    pub tls_pins: BTreeMap<String, Vec<String>>,
+    /// Largest download accepted, in bytes
+    pub max_download_size: u64,
+    /// Free space left over after downloads and installs, in bytes
+    pub free_space_margin: u64,
+}
+
+impl SourceSettings {
+    pub fn resolve(args: &Args, config: &Config) -> Result<Self> {
+        let channel = args.channel.or(config.channel).unwrap_or_default();
+
+        // An explicit data platform wins over the channel's
+        let data_platform = args
+            .data_platform
+            .clone()
+            .or(config.data_platform.clone())
+            .unwrap_or_else(|| String::from(channel.data_platform()));
+
+        // The default selector follows the data platform, so changing only the platform is enough
+        let selector = args
+            .selector
+            .clone()
+            .or(config.selector.clone())
+            .unwrap_or_else(|| format!("a.downloadlink[data-platform={data_platform}]"));
+        source::parse_selector(&selector)?;
+
+        let version_url = args
+            .version_url
+            .clone()
+            .or(config.version_url.clone())
+            .unwrap_or_else(|| source::default_version_url(&data_platform));
+        version_url
+            .contains("{version}")
+            .else_err(BedrockUpdaterError::InvalidVersionUrl(version_url.clone()))?;
+
+        let download_source = match args
+            .download_source
+            .as_deref()
+            .or(config.download_source.as_deref())
+        {
+            None | Some(source::OFFICIAL_SOURCE) => DownloadSource::Official,
+            Some(name) => {
+                let custom = config
+                    .sources
+                    .as_ref()
+                    .and_then(|sources| sources.get(name))
+                    .ok_or_else(|| BedrockUpdaterError::UnknownDownloadSource(name.to_owned()))?;
+                if let Some(version_url) = &custom.version_url {
+                    version_url
+                        .contains("{version}")
+                        .else_err(BedrockUpdaterError::InvalidVersionUrl(version_url.clone()))?;
+                }
+
+                DownloadSource::Custom(CustomSource {
+                    name: name.to_owned(),
+                    latest_url: Url::parse(&custom.latest_url)?,
+                    version_url: custom.version_url.clone(),
+                })
+            }
+        };
+
+        // The zip is turned into a file url, which has to be absolute
+        let simulate_download = args
+            .simulate_download
+            .as_deref()
+            .map(std::path::absolute)
+            .transpose()?;
+        if let Some(path) = &simulate_download {
+            path.is_file()
+                .else_err(BedrockUpdaterError::NotAFile(path.clone()))?;
+        }
+
+        // Mirrors from the command line are tried before the ones in the config file
+        let mirrors: Vec<Url> = args
+            .mirror
+            .iter()
+            .chain(config.mirrors.iter().flatten())
+            .map(|mirror| Url::parse(mirror))
+            .collect::<std::result::Result<_, _>>()?;
+
+        let download_url = args
+            .download_url
+            .as_deref()
+            .or(config.download_url.as_deref())
+            .map(Url::parse)
+            .transpose()?;
+
+        // Urls given on purpose are trusted, only links found by a lookup are held to the defaults
+        let source_urls = match &download_source {
+            DownloadSource::Official => Vec::new(),
+            DownloadSource::Custom(custom) => custom
+                .version_url
+                .iter()
+                .filter_map(|url| Url::parse(&url.replace("{version}", "0")).ok())
+                .chain([custom.latest_url.clone()])
+                .collect(),
+        };
+        let configured = Url::parse(&version_url.replace("{version}", "0"))
+            .ok()
+            .into_iter()
+            .chain(source_urls)
+            .chain(download_url.clone())
+            .chain(mirrors.iter().cloned());
+        let allowed_hosts = source::DEFAULT_ALLOWED_HOSTS
+            .iter()
+            .map(|host| host.to_string())
+            .chain(args.allow_host.iter().cloned())
+            .chain(config.allowed_hosts.iter().flatten().cloned())
+            .chain(configured.filter_map(|url| url.host_str().map(String::from)))
+            .collect();
+
+        Ok(Self {
+            scrape_fallback: args.scrape_fallback || config.scrape_fallback.unwrap_or(false),
+            channel,
+            data_platform,
+            selector,
+            version_url,
+            download_source,
+            diagnostics_dir: args
+                .diagnostics_dir
+                .clone()
+                .or(config.diagnostics_dir.clone()),
+            header_profile: args
+                .header_profile
+                .or(config.header_profile)
+                .unwrap_or_default(),
+            user_agent: args.user_agent.clone().or(config.user_agent.clone()),
+            fetch_command: args.fetch_command.clone().or(config.fetch_command.clone()),
+            simulate_latest: args.simulate_latest,
+            simulate_download,
+            retry: RetryPolicy {
+                attempts: args
+                    .retry_attempts
+                    .or(config.retry_attempts)
+                    .unwrap_or(retry::DEFAULT_RETRY_ATTEMPTS)
+                    .max(1),
+                base_delay: args
+                    .retry_delay
+                    .or(config.retry_delay)
+                    .unwrap_or(retry::DEFAULT_RETRY_DELAY),
+            },
+            skip_checksum: args.skip_checksum || config.skip_checksum.unwrap_or(false),
+            mirrors,
+            download_url,
+            allowed_hosts,
+            tls_pins: config
+                .tls_pins
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(host, pins)| {
+                    let pins = pins
+                        .iter()
+                        .map(|pin| pin.replace(':', "").to_ascii_lowercase())
+                        .collect();
+                    (host.to_ascii_lowercase(), pins)
+                })
+                .collect(),
+            max_download_size: args
+                .max_download_size
+                .or(config.max_download_size)
+                .unwrap_or(DEFAULT_MAX_DOWNLOAD_SIZE)
+                .saturating_mul(1024 * 1024),
+            free_space_margin: args
+                .free_space_margin
+                .or(config.free_space_margin)
+                .unwrap_or(DEFAULT_FREE_SPACE_MARGIN)
+                .saturating_mul(1024 * 1024),
+        })
+    }
+}
+
+/// How the client reaches the download servers, shared by every installation
+#[derive(Debug, Clone)]
+pub struct ClientSettings {
+    pub proxy: Option<String>,
+    pub ip_version: Option<IpVersion>,
+    pub resolve: Vec<(String, IpAddr)>,
+}
+
+impl ClientSettings {
+    pub fn resolve(args: &Args, config: &Config) -> Result<Self> {
+        let resolve = args
+            .resolve
+            .iter()
+            .chain(config.resolve.iter().flatten())
+            .map(|item| parse_resolve(item))
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            proxy: args.proxy.clone().or(config.proxy.clone()),
+            ip_version: args.ip_version.or(config.ip_version),
+            resolve,
+        })
+    }
+}
+
+/// Parses an octal file mode like 755
+fn parse_mode(mode: &str) -> Result<u32> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| BedrockUpdaterError::InvalidMode(mode.to_owned()))
+}
+
+/// Splits a host:ip override, the ip may be an ipv6 address in brackets or not
+fn parse_resolve(item: &str) -> Result<(String, IpAddr)> {
+    let invalid = || BedrockUpdaterError::InvalidResolve(item.to_owned());
+
+    let (host, ip) = item.split_once(':').ok_or_else(invalid)?;
+    let ip = ip.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok((host.to_owned(), ip.parse().map_err(|_| invalid())?))
+}
+
+/// Resolves `.` and `..` without touching the filesystem, since some of the paths may not exist yet
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+/// Writes and removes a probe file, which is the only reliable way to know if a directory is writable
+pub fn check_writable(dir: &Path) -> Result<()> {
+    let probe = dir.join(".bedrock-updater-write-test");
+
+    fs::write(&probe, [])
+        .and_then(|()| fs::remove_file(&probe))
+        .map_err(|err| BedrockUpdaterError::NotWritable(dir.to_owned(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_overrides_take_ipv4_and_ipv6() {
+        assert_eq!(
+            parse_resolve("www.minecraft.net:203.0.113.7").unwrap(),
+            (
+                String::from("www.minecraft.net"),
+                "203.0.113.7".parse().unwrap()
+            )
+        );
+        for ipv6 in [
+            "www.minecraft.net:2001:db8::1",
+            "www.minecraft.net:[2001:db8::1]",
+        ] {
+            assert_eq!(
+                parse_resolve(ipv6).unwrap().1,
+                "2001:db8::1".parse::<IpAddr>().unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_overrides_need_a_host_and_an_ip() {
+        for invalid in [
+            "www.minecraft.net",
+            ":203.0.113.7",
+            "www.minecraft.net:",
+            "www.minecraft.net:example.com",
+        ] {
+            assert!(parse_resolve(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn modes_are_octal() {
+        assert_eq!(parse_mode("755").unwrap(), 0o755);
+        assert_eq!(parse_mode("4755").unwrap(), 0o4755);
+        for invalid in ["", "789", "17777", "rwx"] {
+            assert!(parse_mode(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn paths_are_normalized_without_the_filesystem() {
+        assert_eq!(
+            normalize(Path::new("/srv/bedrock/./update/../archive")),
+            PathBuf::from("/srv/bedrock/archive")
+        );
+    }
+
+    #[test]
+    fn update_dirs_stay_out_of_protected_dirs() {
+        let server_dir = std::env::temp_dir().join(format!(
+            "bedrock-updater-config-test-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(server_dir.join("staging/worlds")).unwrap();
+        let validate = |update_dir: &str| {
+            let args = Args {
+                server_dir: Some(server_dir.clone()),
+                update_dir: Some(PathBuf::from(update_dir)),
+                ..Default::default()
+            };
+            Settings::merge(&args, Config::default())
+                .unwrap()
+                .validate(false)
+        };
+
+        let inside = validate("worlds/update");
+        let around = validate("staging");
+        let apart = validate("update");
+        fs::remove_dir_all(&server_dir).unwrap();
+
+        assert!(matches!(
+            inside,
+            Err(BedrockUpdaterError::UnsafeUpdateDir(_))
+        ));
+        assert!(matches!(
+            around,
+            Err(BedrockUpdaterError::UnsafeUpdateDir(_))
+        ));
+        apart.unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use crate::error::{BedrockUpdaterError, Result};
+
+/// Detaches the process from the terminal and records its pid in the pid file
+/// This has to happen before the async runtime is started, since forking only keeps the calling thread
+#[cfg(unix)]
+pub fn detach(pid_file: &Path) -> Result<()> {
+    // Relative paths given on the command line should keep working after detaching
+    let working_dir = std::env::current_dir()?;
+
+    daemonize::Daemonize::new()
+        .pid_file(pid_file)
+        .working_directory(working_dir)
+        .start()?;
+
+    Ok(())
+}
+
+/// Asks the instance recorded in the pid file to shut down gracefully
+#[cfg(unix)]
+pub fn stop(pid_file: &Path) -> Result<()> {
+    let contents = std::fs::read_to_string(pid_file)?;
+
+    let pid: libc::pid_t = contents
+        .trim()
+        .parse()
+        .map_err(|_| BedrockUpdaterError::InvalidPidFile)?;
+
+    tracing::info!("Sending SIGTERM to updater with pid {pid}");
+    // SAFETY: kill only sends a signal and has no memory safety requirements
+    let res = unsafe { libc::kill(pid, libc::SIGTERM) };
+
+    if res != 0 {
+        return Err(BedrockUpdaterError::StopFailed(
+            std::io::Error::last_os_error(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn detach(_pid_file: &Path) -> Result<()> {
+    Err(BedrockUpdaterError::DaemonUnsupported)
+}
+
+#[cfg(not(unix))]
+pub fn stop(_pid_file: &Path) -> Result<()> {
+    Err(BedrockUpdaterError::DaemonUnsupported)
+}
+
+/// Gets the pid of the running daemon, if the pid file points to a live process
+#[cfg(unix)]
+pub fn running(pid_file: &Path) -> Option<u32> {
+    let pid: u32 = std::fs::read_to_string(pid_file)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+
+    alive(pid).then_some(pid)
+}
+
+#[cfg(not(unix))]
+pub fn running(_pid_file: &Path) -> Option<u32> {
+    None
+}
+
+/// Whether a process with this pid exists
+#[cfg(unix)]
+pub fn alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+
+    // SAFETY: signal 0 only checks whether the process exists and can be signalled
+    unsafe { libc::kill(pid, 0) == 0 }
+}
+
+/// Processes can't be looked up here, so they are assumed to have exited
+#[cfg(not(unix))]
+pub fn alive(_pid: u32) -> bool {
+    false
+}
+
+/// When the process with this pid started, as the boot it is running in and its start time in clock ticks since then
+/// Recorded with the pid, it tells the process apart from a later one that was given the same pid
+#[cfg(target_os = "linux")]
+pub fn started(pid: u32) -> Option<String> {
+    let boot = std::fs::read_to_string("/proc/sys/kernel/random/boot_id").ok()?;
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The name before the fields can have spaces and parentheses of its own, the start time is the 20th field after it
+    let ticks = stat.rsplit_once(')')?.1.split_whitespace().nth(19)?;
+
+    Some(format!("{}.{ticks}", boot.trim().get(..8)?))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn started(_pid: u32) -> Option<String> {
+    None
+}
+
+/// Whether the process that recorded its pid and start is still running
+/// Without a start, any process with the pid counts, since it can't be told apart from one that reused it
+pub fn still_running(pid: u32, started: Option<&str>) -> bool {
+    alive(pid) && started.is_none_or(|started| self::started(pid).as_deref() == Some(started))
+}
@@ -0,0 +1,201 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::{atomic, error::Result};
+
+/// Replacements that are still waiting for their files to be unlocked, stored next to the state file
+const DEFERRED_FILE: &str = ".updater-deferred.json";
+
+/// A new file written next to a locked one, which replaces it once nothing holds it open anymore
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Replacement {
+    pub pending: PathBuf,
+    pub destination: PathBuf,
+}
+
+fn path(state_path: &Path) -> PathBuf {
+    state_path.with_file_name(DEFERRED_FILE)
+}
+
+/// Where the new file waits until the one it replaces is unlocked
+pub fn pending_path(destination: &Path) -> PathBuf {
+    let name = destination
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    destination.with_file_name(format!(".{name}.updater-pending"))
+}
+
+/// Removes the pending file of a replacement the destination no longer needs, if there is one
+pub fn discard_pending(destination: &Path) -> io::Result<()> {
+    match fs::remove_file(pending_path(destination)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Whether the file could not be written because another process has it open
+/// Windows refuses while the server or a virus scanner uses it, unix only refuses to write over a running binary
+pub fn is_locked(err: &io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+    #[cfg(windows)]
+    return matches!(err.raw_os_error(), Some(32 | 33));
+    #[cfg(unix)]
+    return err.raw_os_error() == Some(libc::ETXTBSY);
+    #[cfg(not(any(windows, unix)))]
+    {
+        let _ = err;
+        false
+    }
+}
+
+fn load(state_path: &Path) -> Result<Vec<Replacement>> {
+    match fs::read(path(state_path)) {
+        Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn save(state_path: &Path, replacements: &[Replacement]) -> Result<()> {
+    if replacements.is_empty() {
+        return match fs::remove_file(path(state_path)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        };
+    }
+
+    Ok(atomic::write(
+        &path(state_path),
+        &serde_json::to_vec_pretty(replacements)?,
+    )?)
+}
+
+/// Records replacements to make once their files are unlocked, replacing older ones of the same files
+/// Older replacements of files the install wrote are dropped, the written files are newer than theirs
+pub fn schedule<'a>(
+    state_path: &Path,
+    written: impl IntoIterator<Item = &'a Path>,
+    replacements: Vec<Replacement>,
+) -> Result<()> {
+    let mut scheduled = load(state_path)?;
+    if scheduled.is_empty() && replacements.is_empty() {
+        return Ok(());
+    }
+
+    let written: Vec<&Path> = written.into_iter().collect();
+    scheduled.retain(|old| !written.contains(&old.destination.as_path()));
+    for replacement in &replacements {
+        warn!(
+            "{:?} is in use, it is replaced with {:?} once it is unlocked",
+            replacement.destination, replacement.pending
+        );
+    }
+    scheduled.extend(replacements);
+
+    save(state_path, &scheduled)
+}
+
+/// Makes the replacements whose files are not locked anymore, keeping the rest for the next run
+pub fn apply(state_path: &Path) -> Result<()> {
+    let scheduled = load(state_path)?;
+    if scheduled.is_empty() {
+        return Ok(());
+    }
+
+    let mut waiting = Vec::new();
+    for replacement in scheduled {
+        match fs::rename(&replacement.pending, &replacement.destination) {
+            Ok(()) => info!(
+                "Replaced {:?}, which was in use during the last install",
+                replacement.destination
+            ),
+            // The install after it replaced the file another way
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                debug!(
+                    "The pending {:?} is gone, nothing to replace",
+                    replacement.pending
+                );
+            }
+            Err(err) if is_locked(&err) => {
+                debug!("{:?} is still in use", replacement.destination);
+                waiting.push(replacement);
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    save(state_path, &waiting)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "bedrock-updater-deferred-{test}-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn replacement(dir: &Path, name: &str) -> Replacement {
+        let destination = dir.join(name);
+        Replacement {
+            pending: pending_path(&destination),
+            destination,
+        }
+    }
+
+    #[test]
+    fn pending_files_are_hidden_next_to_their_destination() {
+        assert_eq!(
+            pending_path(Path::new("server/bedrock_server")),
+            Path::new("server/.bedrock_server.updater-pending")
+        );
+    }
+
+    #[test]
+    fn replacements_of_written_files_are_dropped() {
+        let dir = dir("written");
+        let state_path = dir.join("state.json");
+        let binary = replacement(&dir, "bedrock_server");
+        let library = replacement(&dir, "libCrypto.so");
+        schedule(&state_path, [], vec![binary.clone(), library.clone()]).unwrap();
+
+        schedule(&state_path, [binary.destination.as_path()], Vec::new()).unwrap();
+        let scheduled = load(&state_path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(scheduled, vec![library]);
+    }
+
+    #[test]
+    fn replacements_without_their_pending_file_are_skipped() {
+        let dir = dir("missing");
+        let state_path = dir.join("state.json");
+        let missing = replacement(&dir, "bedrock_server");
+        let waiting = replacement(&dir, "libCrypto.so");
+        fs::write(&waiting.pending, "new").unwrap();
+        schedule(&state_path, [], vec![missing.clone(), waiting.clone()]).unwrap();
+
+        let applied = apply(&state_path);
+        let replaced = fs::read_to_string(&waiting.destination).unwrap();
+        let exists = missing.destination.exists();
+        let left = path(&state_path).exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        applied.unwrap();
+        assert_eq!(replaced, "new");
+        assert!(!exists);
+        // Nothing is waiting anymore
+        assert!(!left);
+    }
+}
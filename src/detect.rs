@@ -0,0 +1,77 @@
+use std::{path::Path, process::Stdio, time::Duration};
+
+use regex::Regex;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+};
+use tracing::{debug, trace};
+
+use crate::{error::Result, version::BedrockVersion};
+
+/// Name of the server executable inside the server directory
+pub const SERVER_BINARY: &str = if cfg!(windows) {
+    "bedrock_server.exe"
+} else {
+    "bedrock_server"
+};
+
+/// How long the server gets to print its version before detection gives up
+const DETECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Starts the server just long enough to read the version it prints on startup, e.g. "[INFO] Version: 1.21.44.01"
+/// The server is killed as soon as the version shows up, before it loads the world
+/// With an emulator, the server is started through it
+pub async fn server_version(
+    server_dir: &Path,
+    emulator: Option<&str>,
+) -> Result<Option<BedrockVersion>> {
+    let pattern = Regex::new(r"Version:?\s+(\d+(?:\.\d+){3,4})")?;
+
+    debug!("Starting {SERVER_BINARY} to detect its version");
+    let binary = server_dir.join(SERVER_BINARY);
+    let mut command = match emulator {
+        Some(emulator) => {
+            let mut command = Command::new(emulator);
+            command.arg(binary);
+            command
+        }
+        None => Command::new(binary),
+    };
+    let mut child = command
+        .current_dir(server_dir)
+        // The linux server ships its libraries next to the binary
+        .env("LD_LIBRARY_PATH", ".")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let Some(stdout) = child.stdout.take() else {
+        return Ok(None);
+    };
+    let mut lines = BufReader::new(stdout).lines();
+
+    let found = tokio::time::timeout(DETECT_TIMEOUT, async {
+        while let Some(line) = lines.next_line().await? {
+            trace!("Server printed: {line}");
+            if let Some(captures) = pattern.captures(&line) {
+                return Ok(captures[1].parse().ok());
+            }
+        }
+
+        Ok::<_, std::io::Error>(None)
+    })
+    .await;
+
+    child.kill().await?;
+
+    match found {
+        Ok(version) => Ok(version?),
+        Err(_) => {
+            debug!("Server did not print its version within {DETECT_TIMEOUT:?}");
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::{collections::BTreeSet, path::Path};
+
+use tracing::info;
+
+use crate::{
+    config::Settings,
+    error::Result,
+    manifest::{self, Manifest},
+    strategy::Strategy,
+    version::BedrockVersion,
+};
+
+/// Compares the extracted files of a release with the installed server, without changing either
+/// Files an install leaves alone are reported when they differ from the release's defaults
+pub fn report(settings: &Settings, extracted: &Path, version: &BedrockVersion) -> Result<()> {
+    let live_dir = settings.live_dir();
+    info!("Comparing version {version} with the files in {live_dir:?}");
+
+    let mut changed = Vec::new();
+    let mut new = Vec::new();
+    let mut diverging = Vec::new();
+    let mut unchanged = 0;
+    let mut shipped = BTreeSet::new();
+    for file in manifest::files_below(extracted)? {
+        let relative = file.strip_prefix(extracted).unwrap_or(&file);
+        let key = manifest::manifest_key(relative);
+        shipped.insert(key.clone());
+
+        let live = live_dir.join(relative);
+        if !live.is_file() {
+            new.push(key);
+            continue;
+        }
+        if manifest::hash_file(&live)? == manifest::hash_file(&file)? {
+            unchanged += 1;
+            continue;
+        }
+
+        let kept = settings
+            .protected_dirs
+            .containing(&live_dir, relative)
+            .is_some()
+            || matches!(
+                settings.strategies.for_path(relative),
+                Strategy::Keep | Strategy::Merge
+            );
+        match kept {
+            true => diverging.push(key),
+            false => changed.push(key),
+        }
+    }
+
+    // Only files the updater recorded installing are known to be the release's
+    let dropped: Vec<String> = Manifest::load(&settings.state_path)?
+        .map(|manifest| {
+            manifest
+                .files
+                .into_keys()
+                .filter(|key| !shipped.contains(key))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    for relative in &changed {
+        println!("  changed  {relative}");
+    }
+    for relative in &new {
+        println!("  new      {relative}");
+    }
+    for relative in &diverging {
+        println!("  kept     {relative}");
+    }
+    for relative in &dropped {
+        println!("  dropped  {relative}");
+    }
+
+    println!(
+        "{} changed, {} new, {} kept that differ from the new defaults, {} no longer shipped, {unchanged} unchanged",
+        changed.len(),
+        new.len(),
+        diverging.len(),
+        dropped.len(),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use tracing::debug;
+
+use crate::{
+    clean::format_bytes,
+    error::{BedrockUpdaterError, Result},
+};
+
+/// Makes sure there is room for everything about to be written, plus the margin, before any of it is written
+/// Writes to directories on the same filesystem are added up, since they take from the same free space
+pub fn ensure_space(writes: &[(&Path, u64)], margin: u64) -> Result<()> {
+    let mut filesystems: BTreeMap<u64, (PathBuf, u64)> = BTreeMap::new();
+    for (dir, bytes) in writes {
+        let dir = nearest_existing(dir);
+        let (_, needed) = filesystems
+            .entry(device(&dir)?)
+            .or_insert_with(|| (dir.clone(), margin));
+        *needed += bytes;
+    }
+
+    for (dir, needed) in filesystems.into_values() {
+        let Some(free) = free_space(&dir)? else {
+            debug!("Free space cannot be checked on this platform");
+            return Ok(());
+        };
+
+        if free < needed {
+            return Err(BedrockUpdaterError::InsufficientDiskSpace(
+                dir, needed, free,
+            ));
+        }
+        debug!(
+            "{} of the {} free on the filesystem of {dir:?} are needed",
+            format_bytes(needed),
+            format_bytes(free)
+        );
+    }
+
+    Ok(())
+}
+
+/// Walks up from the path until it finds something that exists
+pub fn nearest_existing(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(path)
+        .to_owned()
+}
+
+/// Whether both paths are on one filesystem, so files can be linked from one to the other
+pub fn same_filesystem(a: &Path, b: &Path) -> bool {
+    match (device(a), device(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Identifies the filesystem holding the path
+#[cfg(unix)]
+fn device(path: &Path) -> io::Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(path.metadata()?.dev())
+}
+
+/// Everything is treated as one filesystem, the free space can't be checked here anyway
+#[cfg(not(unix))]
+fn device(_path: &Path) -> io::Result<u64> {
+    Ok(0)
+}
+
+/// Bytes available to the updater on the filesystem holding the path
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> io::Result<Option<u64>> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: statvfs only writes to the struct it is given, and the path is a valid C string
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // The field types differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
@@ -0,0 +1,356 @@
+use std::{path::Path, time::Duration};
+
+use reqwest::Url;
+use tokio::net::{lookup_host, TcpStream};
+
+use crate::{
+    args::Args,
+    clean::format_bytes,
+    compat,
+    config::{self, ClientSettings, Config, Settings},
+    detect::SERVER_BINARY,
+    disk::{free_space, nearest_existing},
+    error::Result,
+    players::PlayerList,
+    source::{self, DOWNLOAD_LINKS_API},
+    state::State,
+};
+
+/// How long each network check may take before it counts as failed
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Extracting an update needs room for the download and the unpacked server next to each other
+const MIN_FREE_SPACE: u64 = 500 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Pass,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    name: &'static str,
+    outcome: Outcome,
+    detail: String,
+    hint: Option<&'static str>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: &'static str) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Warn,
+            detail: detail.into(),
+            hint: Some(hint),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: &'static str) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Fail,
+            detail: detail.into(),
+            hint: Some(hint),
+        }
+    }
+}
+
+/// Runs every check and prints a report, returning whether nothing failed
+/// Checks keep going after a failure so everything that needs fixing shows up at once
+pub async fn doctor(args: &Args, config: Config) -> Result<bool> {
+    let mut checks = match ClientSettings::resolve(args, &config) {
+        Ok(client) => network_checks(&client).await,
+        Err(err) => vec![Check::fail(
+            "network settings",
+            err.to_string(),
+            "fix --proxy or --resolve, or the same settings in the config file",
+        )],
+    };
+
+    match Settings::merge(args, config) {
+        Ok(settings) => checks.extend(local_checks(&settings)),
+        Err(err) => checks.push(Check::fail(
+            "server directory",
+            err.to_string(),
+            "pass --server-dir or set server_dir in the config file",
+        )),
+    }
+
+    for check in &checks {
+        let label = match check.outcome {
+            Outcome::Pass => "PASS",
+            Outcome::Warn => "WARN",
+            Outcome::Fail => "FAIL",
+        };
+        println!("[{label}] {}: {}", check.name, check.detail);
+        if let Some(hint) = check.hint {
+            println!("       {hint}");
+        }
+    }
+
+    let count = |outcome| {
+        checks
+            .iter()
+            .filter(|check| check.outcome == outcome)
+            .count()
+    };
+    println!(
+        "{} passed, {} warnings, {} failed",
+        count(Outcome::Pass),
+        count(Outcome::Warn),
+        count(Outcome::Fail)
+    );
+
+    Ok(count(Outcome::Fail) == 0)
+}
+
+/// Checks each layer separately, so a failure points at DNS, the network or TLS instead of just "request failed"
+/// Later checks are skipped once one fails, since they could only fail for the same reason
+/// Behind a proxy only the request itself is checked, since the direct route is not used
+async fn network_checks(client: &ClientSettings) -> Vec<Check> {
+    let url = Url::parse(DOWNLOAD_LINKS_API).expect("the download links API url is valid");
+
+    let mut checks = match client.proxy {
+        Some(_) => Vec::new(),
+        None => match direct_checks(&url).await {
+            Ok(checks) => checks,
+            Err(checks) => return checks,
+        },
+    };
+
+    let builder = match source::client_builder(client) {
+        Ok(builder) => builder,
+        Err(err) => {
+            checks.push(Check::fail(
+                "proxy",
+                err.to_string(),
+                "pass an http, https, socks5 or socks5h proxy url to --proxy",
+            ));
+            return checks;
+        }
+    };
+    let client = match builder.timeout(NETWORK_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(err) => {
+            checks.push(Check::fail(
+                "tls",
+                err.to_string(),
+                "the TLS backend could not be initialized",
+            ));
+            return checks;
+        }
+    };
+
+    match client.get(url).send().await {
+        Ok(response) if response.status().is_success() => {
+            checks.push(Check::pass("tls", format!("download links API answered with {}", response.status())))
+        }
+        Ok(response) => checks.push(Check::fail(
+            "tls",
+            format!("download links API answered with {}", response.status()),
+            "the connection works but the API refused the request, this usually means rate limiting",
+        )),
+        Err(err) => checks.push(Check::fail(
+            "tls",
+            format!("request failed: {err}"),
+            "check the system clock and that the CA certificates are installed and up to date",
+        )),
+    }
+
+    checks
+}
+
+/// Resolves and connects to the API host directly, giving back the checks so far when one fails
+async fn direct_checks(url: &Url) -> std::result::Result<Vec<Check>, Vec<Check>> {
+    let host = url.host_str().unwrap_or_default();
+
+    let dns_hint =
+        "check the DNS servers in /etc/resolv.conf and that the machine has network access";
+    let address = match tokio::time::timeout(NETWORK_TIMEOUT, lookup_host((host, 443))).await {
+        Ok(Ok(mut addresses)) => match addresses.next() {
+            Some(address) => address,
+            None => {
+                return Err(vec![Check::fail(
+                    "dns",
+                    format!("{host} has no addresses"),
+                    dns_hint,
+                )])
+            }
+        },
+        Ok(Err(err)) => {
+            return Err(vec![Check::fail(
+                "dns",
+                format!("could not resolve {host}: {err}"),
+                dns_hint,
+            )])
+        }
+        Err(_) => {
+            return Err(vec![Check::fail(
+                "dns",
+                format!("resolving {host} timed out"),
+                dns_hint,
+            )])
+        }
+    };
+    let mut checks = vec![Check::pass(
+        "dns",
+        format!("{host} resolves to {}", address.ip()),
+    )];
+
+    let connect_hint =
+        "check that outgoing connections to port 443 are allowed by the firewall, or use --proxy";
+    match tokio::time::timeout(NETWORK_TIMEOUT, TcpStream::connect(address)).await {
+        Ok(Ok(_)) => checks.push(Check::pass(
+            "connectivity",
+            format!("connected to {address}"),
+        )),
+        Ok(Err(err)) => {
+            checks.push(Check::fail(
+                "connectivity",
+                format!("could not connect to {address}: {err}"),
+                connect_hint,
+            ));
+            return Err(checks);
+        }
+        Err(_) => {
+            checks.push(Check::fail(
+                "connectivity",
+                format!("connecting to {address} timed out"),
+                connect_hint,
+            ));
+            return Err(checks);
+        }
+    }
+
+    Ok(checks)
+}
+
+fn local_checks(settings: &Settings) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    match settings.validate(true) {
+        Ok(()) => checks.push(Check::pass("settings", "all paths are usable")),
+        Err(err) => checks.push(Check::fail(
+            "settings",
+            err.to_string(),
+            "fix the paths given on the command line or in the config file",
+        )),
+    }
+
+    let writable_hint =
+        "run the updater as the user that owns the server files, or fix the directory permissions";
+    // The update directory may not exist between installs, in which case it gets created in its parent
+    let update_dir = nearest_existing(&settings.update_dir);
+    for (name, dir) in [
+        ("server directory", &settings.server_dir),
+        ("update directory", &update_dir),
+    ] {
+        match config::check_writable(dir) {
+            Ok(()) => checks.push(Check::pass(name, format!("{} is writable", dir.display()))),
+            Err(err) => checks.push(Check::fail(name, err.to_string(), writable_hint)),
+        }
+    }
+
+    checks.push(disk_space_check(&settings.server_dir));
+
+    let binary = settings.live_dir().join(SERVER_BINARY);
+    let emulator_hint = "set --emulator to start the server through an emulator like box64";
+    if binary.is_file() {
+        checks.push(Check::pass(
+            "server binary",
+            format!("found {}", binary.display()),
+        ));
+        match compat::check(&binary, settings.emulator.as_deref()) {
+            Ok(()) => checks.push(Check::pass("platform", "this system can run the server")),
+            Err(err) => checks.push(Check::fail("platform", err.to_string(), emulator_hint)),
+        }
+    } else {
+        checks.push(Check::warn(
+            "server binary",
+            format!("{} does not exist", binary.display()),
+            "make sure --server-dir points at the directory the server was unpacked in",
+        ));
+    }
+
+    let list_hint = "fix or remove these entries, the server skips them";
+    for list in PlayerList::ALL {
+        match list.problems(&settings.live_dir()) {
+            Ok(problems) if problems.is_empty() => {
+                checks.push(Check::pass(list.file_name(), "all entries are readable"))
+            }
+            Ok(problems) => checks.push(Check::warn(
+                list.file_name(),
+                problems.join("; "),
+                list_hint,
+            )),
+            Err(err) => checks.push(Check::warn(
+                list.file_name(),
+                format!("could not read it: {err}"),
+                list_hint,
+            )),
+        }
+    }
+
+    if let Err(err) = compat::check_host(settings) {
+        checks.push(Check::fail("architecture", err.to_string(), emulator_hint));
+    }
+
+    let version_hint = "pass --set-first-version with the installed version";
+    match State::load(settings).map(|state| state.current_version) {
+        Ok(Some(version)) => checks.push(Check::pass(
+            "state file",
+            format!("installed version is {version}"),
+        )),
+        Ok(None) => checks.push(Check::fail(
+            "state file",
+            "no installed version is recorded",
+            version_hint,
+        )),
+        Err(err) => checks.push(Check::fail(
+            "state file",
+            format!("could not read {}: {err}", settings.state_path.display()),
+            version_hint,
+        )),
+    }
+
+    checks
+}
+
+fn disk_space_check(dir: &Path) -> Check {
+    let hint = "free up space, the clean subcommand removes old archives and backups";
+
+    match free_space(dir) {
+        Ok(Some(free)) if free >= MIN_FREE_SPACE => {
+            Check::pass("disk space", format!("{} free", format_bytes(free)))
+        }
+        Ok(Some(free)) => Check::fail(
+            "disk space",
+            format!(
+                "only {} free, at least {} is needed",
+                format_bytes(free),
+                format_bytes(MIN_FREE_SPACE)
+            ),
+            hint,
+        ),
+        Ok(None) => Check::warn(
+            "disk space",
+            "free space cannot be checked on this platform",
+            "make sure there is enough room for the download and the unpacked server",
+        ),
+        Err(err) => Check::warn(
+            "disk space",
+            format!("could not check free space: {err}"),
+            hint,
+        ),
+    }
+}
@@ -0,0 +1,734 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use reqwest::{
+    header::{CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, LOCATION, RANGE},
+    redirect::Policy,
+    tls::TlsInfo,
+    Client, Response, StatusCode, Url,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::{
+    clean::format_bytes,
+    config::SourceSettings,
+    disk,
+    error::{BedrockUpdaterError, Result},
+    manifest,
+    progress::Progress,
+    quarantine, source,
+    updater::ElseErr,
+};
+
+/// Server zips are well over a hundred megabytes, anything this small is an error page or a cut off transfer
+const MIN_ZIP_SIZE: u64 = 64 * 1024;
+
+tokio::task_local! {
+    /// The source of the download being streamed, so its redirects are held to the same hosts and pins
+    static DOWNLOADING: SourceSettings;
+}
+
+/// What a partial download belongs to, so it is only resumed from the same file
+#[derive(Serialize, Deserialize, Debug)]
+struct Partial {
+    url: String,
+    /// ETag or Last-Modified of the first response, sent as If-Range so a changed file is downloaded again
+    validator: Option<String>,
+}
+
+impl Partial {
+    fn path(destination: &Path) -> PathBuf {
+        destination.with_extension("resume.json")
+    }
+
+    /// The number of bytes that can be resumed from, removing partial downloads of other files
+    fn resumable(destination: &Path, url: &Url) -> Result<(u64, Option<String>)> {
+        let partial: Option<Partial> = match fs::read(Self::path(destination)) {
+            Ok(contents) => serde_json::from_slice(&contents).ok(),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        match (partial, fs::metadata(destination)) {
+            (Some(partial), Ok(metadata)) if partial.url == url.as_str() && metadata.len() > 0 => {
+                Ok((metadata.len(), partial.validator))
+            }
+            _ => {
+                Self::discard(destination)?;
+                Ok((0, None))
+            }
+        }
+    }
+
+    fn discard(destination: &Path) -> Result<()> {
+        for path in [destination.to_owned(), Self::path(destination)] {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Streams the server zip into the destination file, giving up as soon as a shutdown is requested
+/// Only a chunk is held in memory at a time, since the zip is hundreds of megabytes
+/// An interrupted download is kept and resumed with a Range request the next time the same url is downloaded
+pub async fn download(
+    client: &Client,
+    source: &SourceSettings,
+    download_link: Url,
+    destination: &Path,
+    shutdown: &CancellationToken,
+) -> Result<u64> {
+    // Simulated releases point at a zip on disk
+    if download_link.scheme() == "file" {
+        let path = download_link
+            .to_file_path()
+            .map_err(|_| BedrockUpdaterError::NoFileName)?;
+        return Ok(tokio::fs::copy(path, destination).await?);
+    }
+
+    let result = tokio::select! {
+        written = stream(client, source, &download_link, destination) => written,
+        _ = shutdown.cancelled() => Err(BedrockUpdaterError::Cancelled),
+    };
+
+    match &result {
+        Ok(_) => fs::remove_file(Partial::path(destination))?,
+        // Only an interrupted transfer is worth resuming, anything else starts over next time
+        Err(BedrockUpdaterError::RequestError(err)) if !err.is_status() => {
+            keep_partial(destination)
+        }
+        Err(BedrockUpdaterError::Cancelled) => keep_partial(destination),
+        Err(BedrockUpdaterError::TruncatedDownload(written, total)) if written < total => {
+            keep_partial(destination)
+        }
+        Err(_) => Partial::discard(destination)?,
+    }
+
+    result
+}
+
+/// Downloads the zip from the download link, or from the first mirror that has it when that fails
+/// Every link is retried on its own, and a zip that doesn't match the published checksum counts as a failure
+/// Zips that don't match are moved into the quarantine directory when there is one
+/// Returns the link the zip was downloaded from
+pub async fn fetch(
+    client: &Client,
+    source: &SourceSettings,
+    download_link: &Url,
+    sha256: Option<&str>,
+    destination: &Path,
+    quarantine_dir: Option<&Path>,
+    shutdown: &CancellationToken,
+) -> Result<Url> {
+    check_host(download_link, source)?;
+
+    let mut links = vec![download_link.clone()];
+    // Simulated releases stay on disk
+    if download_link.scheme() != "file" {
+        links.extend(
+            source
+                .mirrors
+                .iter()
+                .filter_map(|mirror| mirror_link(mirror, download_link)),
+        );
+    }
+
+    let mut links = links.into_iter().peekable();
+    while let Some(link) = links.next() {
+        let result = source
+            .retry
+            .run("Download", || {
+                download(client, source, link.clone(), destination, shutdown)
+            })
+            .await
+            .and_then(|_| verify_checksum(destination, sha256, source));
+
+        if let (Err(err @ BedrockUpdaterError::ChecksumMismatch(..)), Some(dir)) =
+            (&result, quarantine_dir)
+        {
+            quarantine::store_or_remove(Some(dir), destination, &link, err)?;
+        }
+
+        match (result, links.peek()) {
+            (Ok(()), _) => return Ok(link),
+            (Err(BedrockUpdaterError::Cancelled), _) => return Err(BedrockUpdaterError::Cancelled),
+            (Err(err), Some(next)) => {
+                warn!("Download from {link} failed, trying the mirror {next}: {err}")
+            }
+            (Err(err), None) => return Err(err),
+        }
+    }
+
+    unreachable!("there is always at least the download link")
+}
+
+/// Follows redirects the way reqwest does, except a download is only redirected to allowed hosts
+/// A pinned host may only redirect to another pinned host, or the pins would be left behind with it
+/// A refused redirect is handed back as the response, the download then fails with the reason
+pub fn redirect_policy() -> Policy {
+    let default = Policy::default();
+
+    Policy::custom(move |attempt| {
+        let refused = DOWNLOADING
+            .try_with(|source| {
+                let from = attempt.previous().last();
+                from.is_some_and(|from| check_redirect(from, attempt.url(), source).is_err())
+            })
+            .unwrap_or(false);
+
+        match refused {
+            true => attempt.stop(),
+            false => default.redirect(attempt),
+        }
+    })
+}
+
+/// The error for a redirect the policy refused to follow, if the response is one
+fn refused_redirect(response: &Response, source: &SourceSettings) -> Result<()> {
+    if !response.status().is_redirection() {
+        return Ok(());
+    }
+    let location = response
+        .headers()
+        .get(LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .and_then(|location| response.url().join(location).ok());
+
+    match location {
+        Some(location) => check_redirect(response.url(), &location, source),
+        None => Ok(()),
+    }
+}
+
+/// Whether a download may follow a redirect from one url to the other
+fn check_redirect(from: &Url, to: &Url, source: &SourceSettings) -> Result<()> {
+    check_host(to, source)?;
+
+    match (pins(from, source), pins(to, source)) {
+        (Some(_), None) => Err(BedrockUpdaterError::UnpinnedRedirect(
+            from.host_str().unwrap_or_default().to_owned(),
+            to.to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Refuses links to unexpected hosts, in case a lookup ever picks up a link that was injected into the page
+fn check_host(link: &Url, source: &SourceSettings) -> Result<()> {
+    if link.scheme() == "file" {
+        return Ok(());
+    }
+
+    let host = link.host_str().unwrap_or_default().to_ascii_lowercase();
+    let allowed = source.allowed_hosts.iter().any(|allowed| {
+        let allowed = allowed.to_ascii_lowercase();
+        host == allowed || host.ends_with(&format!(".{allowed}"))
+    });
+
+    allowed.else_err(BedrockUpdaterError::DisallowedHost(link.to_string(), host))
+}
+
+/// The pinned fingerprints of the host of the url
+/// Pins cover subdomains too, so one entry is enough for a CDN with many edge hosts
+fn pins<'a>(url: &Url, source: &'a SourceSettings) -> Option<&'a Vec<String>> {
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+
+    source.tls_pins.iter().find_map(|(pinned, pins)| {
+        (host == *pinned || host.ends_with(&format!(".{pinned}"))).then_some(pins)
+    })
+}
+
+/// Compares the certificate of the host against its pinned fingerprints, hosts without pins are not checked
+fn check_pinned(response: &Response, source: &SourceSettings) -> Result<()> {
+    let Some(pins) = pins(response.url(), source) else {
+        return Ok(());
+    };
+    let host = response
+        .url()
+        .host_str()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    // Plain http has no certificate at all, which can't match a pin either
+    let fingerprint = response
+        .extensions()
+        .get::<TlsInfo>()
+        .and_then(|info| info.peer_certificate())
+        .map(|certificate| format!("{:x}", Sha256::digest(certificate)))
+        .unwrap_or_else(|| String::from("(none, the download was plain http)"));
+
+    match pins.contains(&fingerprint) {
+        true => {
+            debug!("Certificate of {host} matches its pin");
+            Ok(())
+        }
+        false => Err(BedrockUpdaterError::CertificateNotPinned(host, fingerprint)),
+    }
+}
+
+/// The same zip on a mirror, found by appending the file name of the download link to the mirror url
+fn mirror_link(mirror: &Url, download_link: &Url) -> Option<Url> {
+    let file_name = download_link.path_segments()?.next_back()?;
+
+    let mut base = mirror.clone();
+    if !base.path().ends_with('/') {
+        base.set_path(&format!("{}/", base.path()));
+    }
+
+    base.join(file_name).ok()
+}
+
+fn keep_partial(destination: &Path) {
+    if let Ok(metadata) = fs::metadata(destination) {
+        info!(
+            "Keeping {} of the download to resume it later",
+            format_bytes(metadata.len())
+        );
+    }
+}
+
+async fn stream(
+    client: &Client,
+    source: &SourceSettings,
+    download_link: &Url,
+    destination: &Path,
+) -> Result<u64> {
+    let (offset, validator) = Partial::resumable(destination, download_link)?;
+
+    let mut request = client.get(download_link.clone());
+    if offset > 0 {
+        debug!("Resuming download from byte {offset}");
+        request = request.header(RANGE, format!("bytes={offset}-"));
+        if let Some(validator) = &validator {
+            request = request.header(IF_RANGE, validator);
+        }
+    }
+
+    // Every hop of a redirect is checked against the allowed hosts, not only the first link
+    let response = DOWNLOADING.scope(source.clone(), request.send()).await?;
+    refused_redirect(&response, source)?;
+    let response = source::check_throttled(response)?;
+    // The partial file is already complete, or the server does not know the range after all
+    if offset > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        warn!("The server refused to resume the download, starting over");
+        Partial::discard(destination)?;
+        return Box::pin(stream(client, source, download_link, destination)).await;
+    }
+    // A missing version would otherwise get extracted as a zip
+    let mut response = response.error_for_status()?;
+    check_pinned(&response, source)?;
+
+    let resumed = offset > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+    let range = content_range(&response);
+    // Only a part starting where the download was cut off, of a file longer than that, can be stitched onto it
+    let continues = range
+        .is_some_and(|(start, total)| start == offset && total.is_none_or(|total| total > offset));
+    if resumed && !continues {
+        warn!("The server sent a part that doesn't continue the download, starting over");
+        Partial::discard(destination)?;
+        return Box::pin(stream(client, source, download_link, destination)).await;
+    }
+    let (mut written, total) = match resumed {
+        true => (offset, range.and_then(|(_, total)| total)),
+        false => {
+            if offset > 0 {
+                info!("The file changed since the download was interrupted, starting over");
+            }
+            (0, response.content_length())
+        }
+    };
+    if let Some(total) = total.filter(|total| *total > source.max_download_size) {
+        return Err(BedrockUpdaterError::DownloadTooLarge(
+            total,
+            source.max_download_size,
+        ));
+    }
+    if let (Some(total), Some(dir)) = (total, destination.parent()) {
+        disk::ensure_space(&[(dir, total - written)], source.free_space_margin)?;
+    }
+
+    let partial = Partial {
+        url: download_link.to_string(),
+        validator: response
+            .headers()
+            .get(ETAG)
+            .or(response.headers().get(LAST_MODIFIED))
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+            .or(validator),
+    };
+    fs::write(Partial::path(destination), serde_json::to_vec(&partial)?)?;
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(destination)
+        .await?;
+
+    let mut progress = Progress::new("Downloading", total);
+    progress.advance(written);
+    let streamed: Result<()> = async {
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+            // Servers can leave out the length or lie about it
+            if written > source.max_download_size {
+                return Err(BedrockUpdaterError::DownloadTooLarge(
+                    written,
+                    source.max_download_size,
+                ));
+            }
+            progress.advance(chunk.len() as u64);
+        }
+        Ok(())
+    }
+    .await;
+    // Whatever arrived is flushed even when the connection broke, so it can be resumed from
+    file.flush().await?;
+    streamed?;
+    progress.finish();
+
+    // A connection that closes early looks like a finished download, so the length is checked before extracting
+    // Stitching parts together also only works if the result is exactly as long as the whole file
+    if let Some(total) = total {
+        if written != total {
+            return Err(BedrockUpdaterError::TruncatedDownload(written, total));
+        }
+    }
+    if written < MIN_ZIP_SIZE {
+        return Err(BedrockUpdaterError::DownloadTooSmall(written));
+    }
+
+    Ok(written)
+}
+
+/// The first byte and the full size from a header like "bytes 1000-4999/5000"
+/// The full size is unknown for "bytes 1000-4999/*"
+fn content_range(response: &Response) -> Option<(u64, Option<u64>)> {
+    let value = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let start = range.split_once('-')?.0.parse().ok()?;
+
+    Some((start, total.parse().ok()))
+}
+
+/// Compares the zip against the checksum published with it, before any of it is extracted
+pub fn verify_checksum(zip: &Path, expected: Option<&str>, source: &SourceSettings) -> Result<()> {
+    let Some(expected) = expected else {
+        debug!("No checksum was published for the download, it cannot be verified");
+        return Ok(());
+    };
+
+    let actual = manifest::hash_file(zip)?;
+    if actual == expected {
+        debug!("Download matches the published checksum {expected}");
+        Ok(())
+    } else if source.skip_checksum {
+        warn!("Download has checksum {actual} instead of the published {expected}, installing it anyway");
+        Ok(())
+    } else {
+        Err(BedrockUpdaterError::ChecksumMismatch(
+            expected.to_owned(),
+            actual,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        task::JoinHandle,
+    };
+
+    use super::*;
+    use crate::{
+        args::Args,
+        config::{ClientSettings, Config},
+    };
+
+    /// Large enough to pass for a server zip
+    const ZIP: usize = MIN_ZIP_SIZE as usize + 1000;
+
+    fn zip() -> Vec<u8> {
+        (0..ZIP).map(|index| (index % 251) as u8).collect()
+    }
+
+    fn response(status: &str, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+        let mut response = format!(
+            "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            body.len()
+        );
+        for (name, value) in headers {
+            response.push_str(&format!("{name}: {value}\r\n"));
+        }
+        response.push_str("\r\n");
+
+        [response.as_bytes(), body].concat()
+    }
+
+    /// Answers one connection after another with the responses, handing back the requests it got
+    async fn serve(responses: Vec<Vec<u8>>) -> (Url, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://{}/bedrock-server.zip",
+            listener.local_addr().unwrap()
+        );
+
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut connection, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    let mut buffer = [0; 1024];
+                    let read = connection.read(&mut buffer).await.unwrap();
+                    assert!(read > 0, "the client hung up");
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                requests.push(String::from_utf8(request).unwrap().to_ascii_lowercase());
+                connection.write_all(&response).await.unwrap();
+            }
+            requests
+        });
+
+        (Url::parse(&url).unwrap(), server)
+    }
+
+    fn client() -> Client {
+        let settings = ClientSettings {
+            proxy: None,
+            ip_version: None,
+            resolve: Vec::new(),
+        };
+        source::client_builder(&settings)
+            .unwrap()
+            .no_proxy()
+            .build()
+            .unwrap()
+    }
+
+    fn source() -> SourceSettings {
+        let mut source = SourceSettings::resolve(&Args::default(), &Config::default()).unwrap();
+        source.allowed_hosts = vec![String::from("127.0.0.1")];
+        source.free_space_margin = 0;
+        source
+    }
+
+    /// Where a test downloads to, in a directory of its own
+    fn destination(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "bedrock-updater-download-{test}-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("bedrock-server.zip")
+    }
+
+    /// Leaves the first bytes of the zip behind like an interrupted download of the url
+    fn interrupted(destination: &Path, url: &Url, written: usize, validator: Option<&str>) {
+        fs::write(destination, &zip()[..written]).unwrap();
+        let partial = Partial {
+            url: url.to_string(),
+            validator: validator.map(String::from),
+        };
+        fs::write(
+            Partial::path(destination),
+            serde_json::to_vec(&partial).unwrap(),
+        )
+        .unwrap();
+    }
+
+    fn clean_up(destination: &Path) {
+        fs::remove_dir_all(destination.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn redirects_within_the_allowed_hosts_are_followed() {
+        let destination = destination("redirect");
+        let (url, server) = serve(vec![
+            response("302 Found", &[("Location", "/elsewhere.zip")], b""),
+            response("200 OK", &[], &zip()),
+        ])
+        .await;
+
+        let written = stream(&client(), &source(), &url, &destination).await;
+        let requests = server.await.unwrap();
+        let contents = fs::read(&destination).unwrap();
+        clean_up(&destination);
+
+        assert_eq!(written.unwrap(), ZIP as u64);
+        assert!(requests[1].starts_with("get /elsewhere.zip "));
+        assert_eq!(contents, zip());
+    }
+
+    #[tokio::test]
+    async fn redirects_to_other_hosts_are_refused() {
+        let destination = destination("disallowed");
+        let (url, server) = serve(vec![response(
+            "302 Found",
+            &[("Location", "http://localhost:1/bedrock-server.zip")],
+            b"",
+        )])
+        .await;
+
+        let written = stream(&client(), &source(), &url, &destination).await;
+        server.await.unwrap();
+        let created = destination.exists();
+        clean_up(&destination);
+
+        assert!(matches!(
+            written,
+            Err(BedrockUpdaterError::DisallowedHost(_, host)) if host == "localhost"
+        ));
+        assert!(!created);
+    }
+
+    #[tokio::test]
+    async fn pinned_hosts_can_not_redirect_to_hosts_without_pins() {
+        let destination = destination("unpinned");
+        let (url, server) = serve(vec![response(
+            "302 Found",
+            &[("Location", "http://localhost:1/bedrock-server.zip")],
+            b"",
+        )])
+        .await;
+        let mut source = source();
+        source.allowed_hosts.push(String::from("localhost"));
+        source
+            .tls_pins
+            .insert(String::from("127.0.0.1"), vec![String::from("00")]);
+
+        let written = stream(&client(), &source, &url, &destination).await;
+        server.await.unwrap();
+        clean_up(&destination);
+
+        assert!(matches!(
+            written,
+            Err(BedrockUpdaterError::UnpinnedRedirect(host, _)) if host == "127.0.0.1"
+        ));
+    }
+
+    #[tokio::test]
+    async fn parts_of_a_smaller_file_start_over() {
+        let destination = destination("smaller");
+        let (url, server) = serve(vec![
+            response(
+                "206 Partial Content",
+                &[("Content-Range", "bytes 60000-60999/50000")],
+                b"",
+            ),
+            response("200 OK", &[], &zip()),
+        ])
+        .await;
+        interrupted(&destination, &url, 60000, None);
+
+        let written = stream(&client(), &source(), &url, &destination).await;
+        let requests = server.await.unwrap();
+        let contents = fs::read(&destination).unwrap();
+        clean_up(&destination);
+
+        assert_eq!(written.unwrap(), ZIP as u64);
+        assert!(!requests[1].contains("range:"));
+        assert_eq!(contents, zip());
+    }
+
+    #[tokio::test]
+    async fn interrupted_downloads_resume_where_they_stopped() {
+        let destination = destination("resume");
+        let zip = zip();
+        let range = format!("bytes 40000-{}/{ZIP}", ZIP - 1);
+        let (url, server) = serve(vec![response(
+            "206 Partial Content",
+            &[("Content-Range", &range)],
+            &zip[40000..],
+        )])
+        .await;
+        interrupted(&destination, &url, 40000, Some("\"v1\""));
+
+        let written = stream(&client(), &source(), &url, &destination).await;
+        let requests = server.await.unwrap();
+        let contents = fs::read(&destination).unwrap();
+        clean_up(&destination);
+
+        assert_eq!(written.unwrap(), ZIP as u64);
+        assert!(requests[0].contains("\r\nrange: bytes=40000-\r\n"));
+        assert!(requests[0].contains("\r\nif-range: \"v1\"\r\n"));
+        assert_eq!(contents, zip);
+    }
+
+    #[tokio::test]
+    async fn refused_ranges_start_over() {
+        let destination = destination("refused");
+        let (url, server) = serve(vec![
+            response("416 Range Not Satisfiable", &[], b""),
+            response("200 OK", &[], &zip()),
+        ])
+        .await;
+        interrupted(&destination, &url, 40000, None);
+
+        let written = stream(&client(), &source(), &url, &destination).await;
+        let requests = server.await.unwrap();
+        let contents = fs::read(&destination).unwrap();
+        clean_up(&destination);
+
+        assert_eq!(written.unwrap(), ZIP as u64);
+        assert!(requests[0].contains("\r\nrange: bytes=40000-\r\n"));
+        assert!(!requests[1].contains("range:"));
+        assert_eq!(contents, zip());
+    }
+
+    #[tokio::test]
+    async fn changed_files_are_downloaded_again() {
+        let destination = destination("changed");
+        // Servers send the whole file instead of the part when If-Range doesn't match
+        let (url, server) = serve(vec![response("200 OK", &[("ETag", "\"v2\"")], &zip())]).await;
+        interrupted(&destination, &url, 40000, Some("\"v1\""));
+
+        let written = stream(&client(), &source(), &url, &destination).await;
+        server.await.unwrap();
+        let contents = fs::read(&destination).unwrap();
+        let partial: Partial =
+            serde_json::from_slice(&fs::read(Partial::path(&destination)).unwrap()).unwrap();
+        clean_up(&destination);
+
+        assert_eq!(written.unwrap(), ZIP as u64);
+        assert_eq!(contents, zip());
+        assert_eq!(partial.validator.as_deref(), Some("\"v2\""));
+    }
+
+    #[tokio::test]
+    async fn parts_starting_elsewhere_start_over() {
+        let destination = destination("elsewhere");
+        let range = format!("bytes 0-{}/{ZIP}", ZIP - 1);
+        let (url, server) = serve(vec![
+            response("206 Partial Content", &[("Content-Range", &range)], b""),
+            response("200 OK", &[], &zip()),
+        ])
+        .await;
+        interrupted(&destination, &url, 40000, None);
+
+        let written = stream(&client(), &source(), &url, &destination).await;
+        let requests = server.await.unwrap();
+        let contents = fs::read(&destination).unwrap();
+        clean_up(&destination);
+
+        assert_eq!(written.unwrap(), ZIP as u64);
+        assert!(!requests[1].contains("range:"));
+        assert_eq!(contents, zip());
+    }
+}
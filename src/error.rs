@@ -1,10 +1,9 @@
-use std::{str::Utf8Error, string::FromUtf8Error};
+use std::{path::PathBuf, str::Utf8Error, string::FromUtf8Error};
 
 use tracing::subscriber::SetGlobalDefaultError;
 use url::ParseError;
 
 use scraper::error::SelectorErrorKind;
-use zip_extract::ZipExtractError;
 
 pub(crate) type Result<T> = ::std::result::Result<T, BedrockUpdaterError>;
 
@@ -20,6 +19,28 @@ pub enum BedrockUpdaterError {
     TooManyDownloadElements,
     #[error("no href attribute found")]
     NoDownloadLinkAttr,
+    #[error("download links API has no {0} download, check --data-platform")]
+    NoDownloadType(String),
+    #[error("{0}, the download page was saved to {1:?}")]
+    PageDumped(Box<BedrockUpdaterError>, PathBuf),
+    #[error("got {1:?} from {0} instead of the download page")]
+    UnexpectedPage(String, String),
+    #[error("fetch command exited with {0}: {1}")]
+    FetchCommandFailed(String, String),
+    #[error("proxy {0} is not an http, https, socks5 or socks5h proxy")]
+    UnsupportedProxy(String),
+    #[error("{0:?} is not a host:ip override like www.minecraft.net:203.0.113.7")]
+    InvalidResolve(String),
+    #[error("version url {0:?} has no {{version}} placeholder")]
+    InvalidVersionUrl(String),
+    #[error(
+        "download source {0:?} is neither official nor a table under [sources] in the config file"
+    )]
+    UnknownDownloadSource(String),
+    #[error("download source {0:?} has no version_url, so it can't download specific versions")]
+    NoVersionUrl(String),
+    #[error("invalid selector {0:?}: {1}")]
+    InvalidSelector(String, String),
     #[error(transparent)]
     CannotParseUrl(#[from] ParseError),
     #[error("file name terminates in ..")]
@@ -28,22 +49,141 @@ pub enum BedrockUpdaterError {
     PatternError(#[from] regex::Error),
     #[error("could not find version string in filename")]
     NoVersionString,
-    #[error("could not parse version number")]
-    UnparseableVersion,
+    #[error("{0:?} is not a version like 1.21.44.1")]
+    UnparseableVersion(String),
     #[error("file not found")]
     FileNotFound(#[from] std::io::Error),
     #[error("string is not valid utf-8")]
     FromUtf8Error(#[from] FromUtf8Error),
     #[error("string slice is not valid utf-8")]
     Utf8Error(#[from] Utf8Error),
-    #[error("unable to find version in file, use --set-first-version")]
+    #[error("{0:?} is damaged: {1}, run with --repair-state to fix it")]
+    DamagedState(PathBuf, String),
+    #[error("repair declined, the state file was left as it is")]
+    RepairDeclined,
+    #[error("the server answered the ping with an invalid pong")]
+    InvalidPong,
+    #[error("unable to find or detect the installed version, use --set-first-version")]
     NoCurrentVersion,
+    #[error("{0:?} is not an octal file mode like 755")]
+    InvalidMode(String),
+    #[error("{0:?} is not an owner like minecraft:minecraft, or the user or group does not exist")]
+    InvalidOwner(String),
+    #[error("could not switch to running as {0}")]
+    DropPrivileges(String, #[source] std::io::Error),
+    #[error("could not run restorecon to restore SELinux contexts, install policycoreutils or turn off --restore-contexts")]
+    RestoreContexts(#[source] std::io::Error),
+    #[error("restorecon failed with {0}: {1}")]
+    RestoreContextsFailed(String, String),
+    #[error("no server directory given, use --server-dir")]
+    NoServerDir,
+    #[error("server directory {0:?} cannot be used, check that it exists")]
+    InvalidServerDir(PathBuf, #[source] std::io::Error),
+    #[error("{0:?} is not a directory")]
+    NotADirectory(PathBuf),
+    #[error("{0:?} is not a file")]
+    NotAFile(PathBuf),
+    #[error("{0:?} is not writable by the updater, check its owner and permissions")]
+    NotWritable(PathBuf, #[source] std::io::Error),
+    #[error("update directory {0:?} must be its own directory inside the server directory, not a preserved file and not in or around a protected directory")]
+    UnsafeUpdateDir(PathBuf),
+    #[error("version {0} is not in the archive")]
+    NotArchived(String),
+    #[error("no update is waiting for approval")]
+    NoPendingUpdate,
+    #[error("the update waiting for approval is {0}, not {1}")]
+    PendingVersionMismatch(String, String),
+    #[error("the new server needs glibc {0} or newer, but this system has {1}, upgrade the system or install an older version")]
+    IncompatibleGlibc(String, String),
+    #[error("the official server only runs on x86_64, not on this {0} system, set --emulator to start it through an emulator like box64, or point --data-platform or --version-url at {0} builds")]
+    UnsupportedArchitecture(String),
+    #[error("the server binary is built for {0} and can't run on this {1} system, set --emulator to start it through an emulator like box64")]
+    ForeignArchitecture(String, String),
+    #[error("refusing to install, {0}, restore it from a backup or turn off --check-worlds")]
+    CorruptWorlds(String),
+    #[error("version {0} failed the smoke test and was rolled back to {1}: {2}")]
+    SmokeTestFailed(String, String, String),
+    #[error("no update backup to undo, they are written by in-place installs")]
+    NoUpdateBackup,
+    #[error("the last update backup is of the update to {0}, but {1} is installed")]
+    UndoVersionMismatch(String, String),
+    #[error("no archived version older than the current version to roll back to")]
+    NoRollbackTarget,
+    #[error("{0:?} does not exist, so it cannot be backed up")]
+    BackupPathNotFound(PathBuf),
+    #[error("{0:?} is not a .tar.gz or .zip backup")]
+    UnknownBackupFormat(PathBuf),
+    #[error("backup entry {0:?} points outside of the server directory")]
+    UnsafeBackupEntry(PathBuf),
+    #[error(transparent)]
+    ZipError(#[from] zip::result::ZipError),
+    #[error(
+        "no install manifest found, it is written the next time the updater installs a version"
+    )]
+    NoManifest,
     #[error("server path does not exist")]
     NoServerPath,
     #[error("setting global default tracing subscriber failed")]
     GlobalSubscriberFailed(#[from] SetGlobalDefaultError),
+    #[error("refusing to download {0}, {1} is not an allowed download host, use --allow-host if it is expected")]
+    DisallowedHost(String, String),
+    #[error("certificate fingerprint {1} of {0} is not one of the pins for it")]
+    CertificateNotPinned(String, String),
+    #[error("refusing to follow the redirect from {0} to {1}, {0} has pinned certificates and the new host none")]
+    UnpinnedRedirect(String, String),
+    #[error("download is {0} bytes, more than the maximum of {1} bytes, raise it with --max-download-size if this is expected")]
+    DownloadTooLarge(u64, u64),
+    #[error("not enough free space on the filesystem of {0:?}, {1} bytes are needed but only {2} bytes are free, the clean subcommand removes old archives and backups")]
+    InsufficientDiskSpace(PathBuf, u64, u64),
+    #[error("download ended after {0} of {1} bytes")]
+    TruncatedDownload(u64, u64),
+    #[error("download is only {0} bytes, which is too small to be a server zip")]
+    DownloadTooSmall(u64),
+    #[error("downloaded zip has checksum {1} instead of the published {0}, use --skip-checksum to install it anyway")]
+    ChecksumMismatch(String, String),
+    #[error(
+        "archive entry {0:?} points outside of the update directory, refusing to extract the zip"
+    )]
+    MaliciousArchiveEntry(String),
+    #[error("archive entry {0:?} is corrupt, the download is damaged rather than the disk: {1}")]
+    CorruptArchiveEntry(String, #[source] std::io::Error),
+    #[error("the install of {0} was interrupted while copying and its extracted files in {1:?} are gone, install it again with install --target-version {0}")]
+    InterruptedInstall(String, PathBuf),
+    #[error("the zip has no {0}, so it is not a bedrock server and was not installed")]
+    UnexpectedArchive(String),
     #[error("server zip extraction failed. did the download link download the correct file?")]
-    ServerZipExtractFailed(#[from] ZipExtractError),
+    ServerZipExtractFailed(zip::result::ZipError),
+    #[error("server tar.gz extraction failed. did the download link download the correct file?")]
+    ServerTarExtractFailed(#[source] std::io::Error),
     #[error("could not copy contents of update files")]
-    UpdateCopyError(#[from] fs_extra::error::Error)
-}
\ No newline at end of file
+    UpdateCopyError(#[from] fs_extra::error::Error),
+    #[error("background task failed")]
+    TaskFailed(#[from] tokio::task::JoinError),
+    #[error("rate limited by the download server")]
+    Throttled(Option<std::time::Duration>),
+    #[error("update cancelled by shutdown signal")]
+    Cancelled,
+    #[cfg(unix)]
+    #[error("could not start daemon")]
+    DaemonizeFailed(#[from] daemonize::Error),
+    #[cfg(not(unix))]
+    #[error("daemon mode is only supported on unix")]
+    DaemonUnsupported,
+    #[cfg(not(unix))]
+    #[error("the versioned layout needs symlinks and is only supported on unix")]
+    LayoutUnsupported,
+    #[cfg(unix)]
+    #[error("pid file does not contain a process id")]
+    InvalidPidFile,
+    #[cfg(unix)]
+    #[error("could not signal the running updater")]
+    StopFailed(std::io::Error),
+    #[error("the config file has no [preview] table, or it has one nested in it")]
+    NoPreviewConfig,
+    #[error("could not read config file {0:?}")]
+    ConfigReadError(PathBuf, #[source] std::io::Error),
+    #[error("invalid json: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("invalid config file: {0}")]
+    ConfigParseError(#[from] toml::de::Error),
+}
@@ -0,0 +1,679 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use tar::EntryType;
+use tracing::{debug, trace};
+use zip::{result::ZipError, ZipArchive};
+
+use crate::{
+    detect::SERVER_BINARY,
+    error::{BedrockUpdaterError, Result},
+    glob::FileFilter,
+    progress::Progress,
+    properties::PROPERTIES_FILE,
+};
+
+/// Every gzip file starts with these bytes
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// What went wrong on the blocking thread, which can only send errors that are safe to move between threads
+#[derive(Debug)]
+enum Failure {
+    /// The file is not a zip at all
+    NotAZip(ZipError),
+    /// The file is gzipped, but not a tar.gz
+    NotATar(io::Error),
+    /// An entry that would end up outside of the destination
+    Malicious(String),
+    /// An entry that can't be decompressed or doesn't match its CRC
+    Corrupt(String, io::Error),
+    Zip(ZipError),
+    Io(io::Error),
+}
+
+impl From<ZipError> for Failure {
+    fn from(err: ZipError) -> Self {
+        Failure::Zip(err)
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Self {
+        Failure::Io(err)
+    }
+}
+
+impl From<Failure> for BedrockUpdaterError {
+    fn from(failure: Failure) -> Self {
+        match failure {
+            Failure::NotAZip(err) => BedrockUpdaterError::ServerZipExtractFailed(err),
+            Failure::NotATar(err) => BedrockUpdaterError::ServerTarExtractFailed(err),
+            Failure::Malicious(name) => BedrockUpdaterError::MaliciousArchiveEntry(name),
+            Failure::Corrupt(name, err) => BedrockUpdaterError::CorruptArchiveEntry(name, err),
+            Failure::Zip(err) => err.into(),
+            Failure::Io(err) => err.into(),
+        }
+    }
+}
+
+/// Formats the server can be packaged in
+/// Mirrors may repackage it, so the format is told apart by the first bytes instead of the file name or content type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// Anything that isn't gzipped is opened as a zip, which reports when it is neither
+    fn detect(path: &Path) -> io::Result<Self> {
+        let mut magic = Vec::with_capacity(GZIP_MAGIC.len());
+        File::open(path)?
+            .take(GZIP_MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+
+        Ok(match magic == GZIP_MAGIC {
+            true => ArchiveFormat::TarGz,
+            false => ArchiveFormat::Zip,
+        })
+    }
+}
+
+impl fmt::Display for ArchiveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        })
+    }
+}
+
+/// An entry as it is listed in the archive, before anything is extracted
+struct Listed {
+    name: String,
+    /// None when the name is absolute or climbs out with ..
+    enclosed: Option<PathBuf>,
+    size: u64,
+}
+
+/// What an entry turns into once it is extracted
+enum Kind {
+    Dir,
+    File,
+    Symlink(String),
+    /// A tar entry sharing the contents of an earlier entry, named relative to the root of the archive
+    HardLink(PathBuf),
+}
+
+/// Unpacks the server zip on a blocking thread, so a slow disk doesn't hold up the rest of the updater
+pub async fn extract(zip_path: &Path, destination: &Path, filter: &FileFilter) -> Result<()> {
+    let (zip_path, destination, filter) =
+        (zip_path.to_owned(), destination.to_owned(), filter.clone());
+    // Logs from the thread stay in the span of the install
+    let span = tracing::Span::current();
+
+    Ok(tokio::task::spawn_blocking(move || {
+        span.in_scope(|| unpack(&zip_path, &destination, &filter))
+    })
+    .await??)
+}
+
+/// Bytes the zip takes up once the entries the filter allows are unpacked
+/// Only the central directory of a zip is read, a tar.gz has to be decompressed to list it
+pub async fn unpacked_size(zip_path: &Path, filter: &FileFilter) -> Result<u64> {
+    let (zip_path, filter) = (zip_path.to_owned(), filter.clone());
+
+    let size = tokio::task::spawn_blocking(move || -> std::result::Result<u64, Failure> {
+        let listed = list(&zip_path, ArchiveFormat::detect(&zip_path)?)?;
+        let toplevel = toplevel(&listed);
+
+        Ok(listed
+            .iter()
+            .filter(|entry| {
+                entry
+                    .enclosed
+                    .clone()
+                    .map(|name| strip_toplevel(name, &toplevel))
+                    .is_some_and(|relative| filter.allows(&relative))
+            })
+            .map(|entry| entry.size)
+            .sum())
+    });
+
+    Ok(size.await??)
+}
+
+fn open_zip(zip_path: &Path) -> std::result::Result<ZipArchive<File>, Failure> {
+    ZipArchive::new(File::open(zip_path)?).map_err(Failure::NotAZip)
+}
+
+fn open_tar(tar_path: &Path) -> io::Result<tar::Archive<GzDecoder<BufReader<File>>>> {
+    Ok(tar::Archive::new(GzDecoder::new(BufReader::new(
+        File::open(tar_path)?,
+    ))))
+}
+
+/// Lists every entry of the archive without extracting anything
+fn list(archive_path: &Path, format: ArchiveFormat) -> std::result::Result<Vec<Listed>, Failure> {
+    let mut listed: Vec<Listed> = Vec::new();
+
+    match format {
+        ArchiveFormat::Zip => {
+            let mut zip = open_zip(archive_path)?;
+            for index in 0..zip.len() {
+                let entry = zip.by_index_raw(index)?;
+                listed.push(Listed {
+                    name: entry.name().to_owned(),
+                    enclosed: entry.enclosed_name(),
+                    size: entry.size(),
+                });
+            }
+        }
+        ArchiveFormat::TarGz => {
+            let mut tar = open_tar(archive_path)?;
+            for entry in tar.entries().map_err(Failure::NotATar)? {
+                // Getting to the next header reads through the previous entry, which is where a damaged download shows up
+                let entry = entry.map_err(|err| match listed.last() {
+                    Some(previous) => Failure::Corrupt(previous.name.clone(), err),
+                    None => Failure::NotATar(err),
+                })?;
+                let path = entry.path()?;
+                listed.push(Listed {
+                    name: path.display().to_string(),
+                    enclosed: enclosed_name(&path),
+                    size: entry.size(),
+                });
+            }
+
+            // Tar stops at its end marker, but the CRC of a gzip is only checked after reading all of it
+            let last = listed
+                .last()
+                .map_or_else(String::new, |entry| entry.name.clone());
+            io::copy(&mut tar.into_inner(), &mut io::sink())
+                .map_err(|err| Failure::Corrupt(last, err))?;
+        }
+    }
+
+    Ok(listed)
+}
+
+/// Unpacks the archive into the directory one entry at a time, logging progress along the way
+/// When everything sits in a single top level directory, that directory is stripped
+/// Entries the filter doesn't allow are skipped
+/// Zip64 archives are read the same way, so entries and zips past 4 GiB work too
+fn unpack(
+    zip_path: &Path,
+    destination: &Path,
+    filter: &FileFilter,
+) -> std::result::Result<(), Failure> {
+    fs::create_dir_all(destination)?;
+
+    let format = ArchiveFormat::detect(zip_path)?;
+    // A tar.gz can only be read front to back, so it is listed in a pass of its own first
+    let listed = list(zip_path, format)?;
+    let total = listed.iter().map(|entry| entry.size).sum();
+
+    let mut unpacker = Unpacker {
+        destination,
+        filter,
+        toplevel: toplevel(&listed),
+        progress: Progress::new("Extracting", Some(total)).with_items(listed.len() as u64),
+    };
+
+    debug!("Extracting {format} to {destination:?}");
+    match format {
+        ArchiveFormat::Zip => {
+            let mut zip = open_zip(zip_path)?;
+            for index in 0..zip.len() {
+                let mut entry = zip.by_index(index)?;
+                let name = entry.name().to_owned();
+                let kind = if entry.is_dir() {
+                    Kind::Dir
+                } else if is_symlink(entry.unix_mode()) {
+                    // Zips store the target of a link as its contents
+                    let mut target = Vec::new();
+                    copy_entry(&name, &mut entry, &mut target)?;
+                    Kind::Symlink(String::from_utf8_lossy(&target).into_owned())
+                } else {
+                    Kind::File
+                };
+
+                let (enclosed, mode) = (entry.enclosed_name(), entry.unix_mode());
+                unpacker.place(&name, enclosed, kind, mode, &mut entry)?;
+            }
+        }
+        ArchiveFormat::TarGz => {
+            let mut tar = open_tar(zip_path)?;
+            for entry in tar.entries()? {
+                let mut entry = entry?;
+                let path = entry.path()?.into_owned();
+                let name = path.display().to_string();
+                let link = entry.link_name()?.map(|link| link.into_owned());
+
+                let kind = match (entry.header().entry_type(), link) {
+                    (EntryType::Directory, _) => Kind::Dir,
+                    (EntryType::Regular | EntryType::Continuous, _) => Kind::File,
+                    (EntryType::Symlink, Some(target)) => {
+                        Kind::Symlink(target.to_string_lossy().into_owned())
+                    }
+                    (EntryType::Link, Some(target)) => Kind::HardLink(target),
+                    // Devices, fifos and the like have no business in a server download
+                    (other, _) => {
+                        debug!("Skipping {name} of type {other:?}");
+                        unpacker.progress.next_item();
+                        continue;
+                    }
+                };
+
+                let mode = entry.header().mode().ok();
+                unpacker.place(&name, enclosed_name(&path), kind, mode, &mut entry)?;
+            }
+        }
+    }
+
+    unpacker.progress.finish();
+    Ok(())
+}
+
+/// Writes the entries of an archive into the destination
+struct Unpacker<'a> {
+    destination: &'a Path,
+    filter: &'a FileFilter,
+    toplevel: Option<PathBuf>,
+    progress: Progress,
+}
+
+impl Unpacker<'_> {
+    /// Extracts a single entry, reading its contents from the reader when it is a file
+    fn place(
+        &mut self,
+        name: &str,
+        enclosed: Option<PathBuf>,
+        kind: Kind,
+        mode: Option<u32>,
+        reader: &mut impl Read,
+    ) -> std::result::Result<(), Failure> {
+        self.progress.next_item();
+
+        // Absolute paths and .. are never trusted, the zip was downloaded from the internet
+        let relative = enclosed
+            .map(|name| strip_toplevel(name, &self.toplevel))
+            .ok_or_else(|| Failure::Malicious(name.to_owned()))?;
+
+        if relative.as_os_str().is_empty() {
+            return Ok(());
+        }
+        if !self.filter.allows(&relative) {
+            trace!("Skipping {name} because of the include and exclude patterns");
+            return Ok(());
+        }
+
+        // Links extracted earlier could otherwise carry this entry outside of the destination
+        if through_link(self.destination, &relative)? {
+            return Err(Failure::Malicious(name.to_owned()));
+        }
+
+        let path = self.destination.join(&relative);
+        trace!("Extracting {name} to {path:?}");
+        if let (false, Some(parent)) = (matches!(kind, Kind::Dir), path.parent()) {
+            fs::create_dir_all(parent)?;
+        }
+
+        match kind {
+            Kind::Dir => fs::create_dir_all(&path)?,
+            Kind::File => {
+                let written = copy_entry(name, reader, &mut File::create(&path)?)?;
+                self.progress.advance(written);
+            }
+            Kind::Symlink(target) => {
+                if escapes(self.destination, &relative, Path::new(&target))? {
+                    return Err(Failure::Malicious(format!("{name} -> {target}")));
+                }
+                symlink(Path::new(&target), &path)?;
+                // The mode of a link is the link's own, setting it would change the target instead
+                return Ok(());
+            }
+            Kind::HardLink(target) => {
+                let source = enclosed_name(&target)
+                    .map(|target| strip_toplevel(target, &self.toplevel))
+                    .ok_or_else(|| Failure::Malicious(format!("{name} -> {}", target.display())))?;
+                if through_link(self.destination, &source)? {
+                    return Err(Failure::Malicious(format!(
+                        "{name} -> {}",
+                        target.display()
+                    )));
+                }
+                fs::copy(self.destination.join(source), &path)?;
+            }
+        }
+
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            use std::os::unix::fs::PermissionsExt;
+            // Setuid, setgid and sticky bits are dropped, only the permissions are taken from the archive
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode & 0o777))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Copies an entry out of the archive, telling errors reading the archive apart from errors writing to the disk
+/// The CRC of the entry is checked once all of it has been read, so a damaged download fails here
+fn copy_entry(
+    name: &str,
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+) -> std::result::Result<u64, Failure> {
+    let mut buffer = [0; 64 * 1024];
+    let mut written = 0;
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(written),
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(Failure::Corrupt(name.to_owned(), err)),
+        };
+        writer.write_all(&buffer[..read])?;
+        written += read as u64;
+    }
+}
+
+/// The path with any . removed, None when it is absolute or climbs out with ..
+fn enclosed_name(path: &Path) -> Option<PathBuf> {
+    let mut enclosed = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => enclosed.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(enclosed)
+}
+
+/// The directory every entry is inside of, if there is exactly one
+/// With at least two entries sharing the first component, it has to be a directory
+fn toplevel(listed: &[Listed]) -> Option<PathBuf> {
+    if listed.len() < 2 {
+        return None;
+    }
+
+    let mut toplevel: Option<PathBuf> = None;
+    for entry in listed {
+        // Unsafe names are rejected while extracting
+        let name = entry.enclosed.as_ref()?;
+        match &toplevel {
+            Some(toplevel) if !name.starts_with(toplevel) => return None,
+            Some(_) => {}
+            None => toplevel = Some(name.components().take(1).collect()),
+        }
+    }
+
+    toplevel.filter(|toplevel| !toplevel.as_os_str().is_empty())
+}
+
+fn strip_toplevel(name: PathBuf, toplevel: &Option<PathBuf>) -> PathBuf {
+    match toplevel {
+        Some(toplevel) => name
+            .strip_prefix(toplevel)
+            .map(Path::to_owned)
+            .unwrap_or(name),
+        None => name,
+    }
+}
+
+fn is_symlink(mode: Option<u32>) -> bool {
+    mode.is_some_and(|mode| mode & 0o170000 == 0o120000)
+}
+
+/// Whether a link at the relative path with this target points outside of the destination
+/// This is worked out from the names, since the target may not have been extracted yet
+/// Targets going through a link extracted earlier escape too, the names alone don't tell where that leads
+fn escapes(destination: &Path, relative: &Path, target: &Path) -> io::Result<bool> {
+    if target.has_root() {
+        return Ok(true);
+    }
+
+    let mut resolved = relative.parent().unwrap_or(Path::new("")).to_owned();
+    let components: Vec<_> = target.components().collect();
+    for (index, component) in components.iter().enumerate() {
+        match component {
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return Ok(true);
+                }
+            }
+            Component::Normal(part) => {
+                resolved.push(part);
+                // Pointing at another link is fine, that one stays inside on its own
+                let last = index + 1 == components.len();
+                if !last && is_link(&destination.join(&resolved))? {
+                    return Ok(true);
+                }
+            }
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(_) => return Ok(true),
+        }
+    }
+
+    Ok(false)
+}
+
+fn is_link(path: &Path) -> io::Result<bool> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) => Ok(metadata.file_type().is_symlink()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Whether anything that already exists on the way to the relative path, or the path itself, is a link
+fn through_link(destination: &Path, relative: &Path) -> io::Result<bool> {
+    let mut path = destination.to_owned();
+
+    for component in relative.components() {
+        path.push(component);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => return Ok(true),
+            Ok(_) => {}
+            // Nothing below a path that doesn't exist yet can be a link
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+/// Without unix links, only files and directories can be created
+#[cfg(not(unix))]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    fs::write(path, target.to_string_lossy().as_bytes())
+}
+
+/// Files and directories every server zip has, the updater refuses to install anything without them
+const EXPECTED_ENTRIES: &[&str] = &[
+    SERVER_BINARY,
+    PROPERTIES_FILE,
+    "behavior_packs",
+    "resource_packs",
+];
+
+/// Checks that the extracted files look like a bedrock server before any of them are copied
+/// A login page or some other zip would otherwise replace a working install
+/// Files left out on purpose by the filter are not expected
+pub fn validate(extracted: &Path, filter: &FileFilter) -> Result<()> {
+    let missing: Vec<&str> = EXPECTED_ENTRIES
+        .iter()
+        .copied()
+        .filter(|entry| filter.allows(Path::new(entry)) && !extracted.join(entry).exists())
+        .collect();
+
+    match missing.is_empty() {
+        true => Ok(()),
+        false => Err(BedrockUpdaterError::UnexpectedArchive(missing.join(", "))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unpacker(destination: &Path) -> Unpacker<'_> {
+        static FILTER: FileFilter = FileFilter {
+            include: Vec::new(),
+            exclude: Vec::new(),
+        };
+
+        Unpacker {
+            destination,
+            filter: &FILTER,
+            toplevel: None,
+            progress: Progress::new("Extracting", None),
+        }
+    }
+
+    fn place(
+        unpacker: &mut Unpacker,
+        name: &str,
+        kind: Kind,
+        contents: &[u8],
+    ) -> std::result::Result<(), Failure> {
+        unpacker.place(
+            name,
+            enclosed_name(Path::new(name)),
+            kind,
+            None,
+            &mut &contents[..],
+        )
+    }
+
+    /// Links in a destination that has nothing extracted yet
+    fn escapes_by_name(relative: &str, target: &str) -> bool {
+        escapes(
+            Path::new("/nonexistent"),
+            Path::new(relative),
+            Path::new(target),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn links_within_the_destination_do_not_escape() {
+        assert!(!escapes_by_name("link", "file"));
+        assert!(!escapes_by_name("a/link", "../file"));
+        assert!(!escapes_by_name("a/b/link", "../../c/./file"));
+        assert!(!escapes_by_name("a/link", "b/../../file"));
+    }
+
+    #[test]
+    fn links_out_of_the_destination_escape() {
+        assert!(escapes_by_name("link", ".."));
+        assert!(escapes_by_name("a/link", "../../file"));
+        assert!(escapes_by_name("a/link", "../../a/file"));
+        assert!(escapes_by_name("link", "/etc/passwd"));
+    }
+
+    #[test]
+    fn enclosed_names_reject_absolute_and_parent_paths() {
+        assert_eq!(
+            enclosed_name(Path::new("./a/./b")),
+            Some(PathBuf::from("a/b"))
+        );
+        assert_eq!(enclosed_name(Path::new("a/../b")), None);
+        assert_eq!(enclosed_name(Path::new("/a")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn chained_links_can_not_write_outside_of_the_destination() {
+        let root = std::env::temp_dir().join(format!(
+            "bedrock-updater-extract-test-{}",
+            std::process::id()
+        ));
+        let destination = root.join("destination");
+        fs::create_dir_all(&destination).unwrap();
+        let mut unpacker = unpacker(&destination);
+
+        place(&mut unpacker, "a/b", Kind::Symlink(String::from("..")), b"").unwrap();
+        place(
+            &mut unpacker,
+            "a/b/c",
+            Kind::Symlink(String::from("..")),
+            b"",
+        )
+        .unwrap_err();
+        let written = place(&mut unpacker, "a/b/c/evil", Kind::File, b"evil");
+        let escaped = root.join("evil").exists();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(matches!(written, Err(Failure::Malicious(_))));
+        assert!(!escaped);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_can_not_point_through_extracted_links() {
+        let destination =
+            std::env::temp_dir().join(format!("bedrock-updater-link-test-{}", std::process::id()));
+        fs::create_dir_all(&destination).unwrap();
+        let mut unpacker = unpacker(&destination);
+
+        place(&mut unpacker, "a/b", Kind::Symlink(String::from("..")), b"").unwrap();
+        let through = place(
+            &mut unpacker,
+            "x",
+            Kind::Symlink(String::from("a/b/../..")),
+            b"",
+        );
+        place(&mut unpacker, "c", Kind::Symlink(String::from("a/b")), b"").unwrap();
+        let created = fs::symlink_metadata(destination.join("x")).is_ok();
+        fs::remove_dir_all(&destination).unwrap();
+
+        assert!(matches!(through, Err(Failure::Malicious(_))));
+        assert!(!created);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn setuid_bits_are_dropped() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let destination =
+            std::env::temp_dir().join(format!("bedrock-updater-mode-test-{}", std::process::id()));
+        fs::create_dir_all(&destination).unwrap();
+        let mut unpacker = unpacker(&destination);
+
+        let mut contents = &b"#!/bin/sh"[..];
+        unpacker
+            .place(
+                "run",
+                Some(PathBuf::from("run")),
+                Kind::File,
+                Some(0o4755),
+                &mut contents,
+            )
+            .unwrap();
+        let mode = fs::metadata(destination.join("run"))
+            .unwrap()
+            .permissions()
+            .mode();
+        fs::remove_dir_all(&destination).unwrap();
+
+        assert_eq!(mode & 0o7777, 0o755);
+    }
+}
@@ -0,0 +1,185 @@
+use std::path::Path;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::{error::Result, manifest::manifest_key};
+
+/// A shell style pattern matched against paths relative to the server directory
+/// `*` and `?` stay within one directory and `**` spans any number of them
+/// Patterns without a slash match a file or directory of that name anywhere, like in a .gitignore
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pattern: String,
+    regex: Regex,
+}
+
+impl PartialEq for Glob {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Glob {
+    /// Patterns ignoring case also match paths that only differ from them in case, like Server.properties
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Self> {
+        let trimmed = pattern.trim_matches('/');
+        let mut regex = String::from(match trimmed.contains('/') {
+            true => "^",
+            false => "^(?:.*/)?",
+        });
+
+        let mut chars = trimmed.chars().peekable();
+        while let Some(char) = chars.next() {
+            match char {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    regex.push_str(".*");
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                char => regex.push_str(&regex::escape(&char.to_string())),
+            }
+        }
+        regex.push('$');
+
+        Ok(Self {
+            pattern: pattern.to_owned(),
+            regex: RegexBuilder::new(&regex)
+                .case_insensitive(ignore_case)
+                .build()?,
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Whether the path or any of the directories it is in matches
+    pub fn matches(&self, relative: &Path) -> bool {
+        relative
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .any(|ancestor| self.is_match(ancestor))
+    }
+
+    /// Whether the path itself matches, ignoring the directories it is in
+    pub fn is_match(&self, relative: &Path) -> bool {
+        self.regex.is_match(&manifest_key(relative))
+    }
+}
+
+/// Which files of the server zip are installed
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FileFilter {
+    /// Only these are installed, everything is when it is empty
+    pub include: Vec<Glob>,
+    /// Never installed, even when included
+    pub exclude: Vec<Glob>,
+}
+
+impl FileFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(Self {
+            // Entries of the server zip are always named the same way
+            include: include
+                .iter()
+                .map(|pattern| Glob::new(pattern, false))
+                .collect::<Result<_>>()?,
+            exclude: exclude
+                .iter()
+                .map(|pattern| Glob::new(pattern, false))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    pub fn allows(&self, relative: &Path) -> bool {
+        let included =
+            self.include.is_empty() || self.include.iter().any(|glob| glob.matches(relative));
+
+        included && !self.exclude.iter().any(|glob| glob.matches(relative))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        Glob::new(pattern, false).unwrap().matches(Path::new(path))
+    }
+
+    #[test]
+    fn patterns_without_a_slash_match_anywhere() {
+        assert!(matches("server.properties", "server.properties"));
+        assert!(matches("*.json", "config/default/permissions.json"));
+        assert!(!matches("*.json", "config/default/permissions.json.bak"));
+    }
+
+    #[test]
+    fn patterns_with_a_slash_match_from_the_server_directory() {
+        assert!(matches("config/*.json", "config/permissions.json"));
+        assert!(!matches(
+            "config/*.json",
+            "behavior_packs/config/permissions.json"
+        ));
+        assert!(matches(
+            "worlds/*/level.dat",
+            "worlds/Bedrock level/level.dat"
+        ));
+        assert!(!matches("worlds/*/level.dat", "backups/worlds/a/level.dat"));
+    }
+
+    #[test]
+    fn stars_stay_within_a_directory_unless_doubled() {
+        assert!(!matches("config/*.json", "config/default/permissions.json"));
+        assert!(matches("config/**.json", "config/default/permissions.json"));
+        assert!(matches(
+            "behavior_packs/**/manifest.json",
+            "behavior_packs/vanilla/manifest.json"
+        ));
+        assert!(matches("level?.dat", "worlds/a/level2.dat"));
+        assert!(!matches("level?.dat", "worlds/a/level.dat"));
+    }
+
+    #[test]
+    fn patterns_match_the_files_inside_matching_directories() {
+        assert!(matches("worlds", "worlds/a/db/CURRENT"));
+        assert!(!Glob::new("worlds", false)
+            .unwrap()
+            .is_match(Path::new("worlds/a/db/CURRENT")));
+    }
+
+    #[test]
+    fn regex_characters_are_literal() {
+        assert!(matches("a+b.txt", "a+b.txt"));
+        assert!(!matches("a+b.txt", "aab.txt"));
+        assert!(!matches("a.txt", "abtxt"));
+    }
+
+    #[test]
+    fn case_is_only_ignored_when_asked() {
+        assert!(!matches("server.properties", "Server.properties"));
+        assert!(Glob::new("server.properties", true)
+            .unwrap()
+            .matches(Path::new("Server.properties")));
+    }
+
+    #[test]
+    fn filters_exclude_over_include() {
+        let everything = FileFilter::default();
+        assert!(everything.allows(Path::new("bedrock_server")));
+
+        let filter = FileFilter::new(
+            &[
+                String::from("behavior_packs"),
+                String::from("bedrock_server"),
+            ],
+            &[String::from("behavior_packs/experimental*")],
+        )
+        .unwrap();
+        assert!(filter.allows(Path::new("bedrock_server")));
+        assert!(filter.allows(Path::new("behavior_packs/vanilla/manifest.json")));
+        assert!(!filter.allows(Path::new("behavior_packs/experimental_x/manifest.json")));
+        assert!(!filter.allows(Path::new("resource_packs/vanilla/manifest.json")));
+    }
+}
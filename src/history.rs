@@ -0,0 +1,113 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{clean::format_bytes, error::Result};
+
+/// History file stored next to the state file, each line is one JSON record
+const HISTORY_FILE: &str = ".updater-history.jsonl";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Installed,
+    Declined,
+    Failed,
+    /// The worlds were checked once the installed version first started, the error lists the corrupt ones
+    Checked,
+}
+
+/// A single attempt at updating the server
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpdateRecord {
+    pub old_version: String,
+    pub new_version: String,
+    #[serde(with = "humantime_serde")]
+    pub started: SystemTime,
+    #[serde(with = "humantime_serde")]
+    pub duration: Duration,
+    /// Size of the server zip, missing when the attempt failed before it was downloaded
+    #[serde(default)]
+    pub bytes: Option<u64>,
+    /// Where the server zip came from, the download link or the mirror that served it
+    #[serde(default)]
+    pub source: Option<String>,
+    pub outcome: Outcome,
+    pub error: Option<String>,
+}
+
+fn path(state_path: &Path) -> PathBuf {
+    state_path.with_file_name(HISTORY_FILE)
+}
+
+/// Adds a record to the end of the history file
+/// The file is only ever appended to, so old records are never rewritten
+pub fn append(state_path: &Path, record: &UpdateRecord) -> Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path(state_path))?
+        .write_all(&line)?;
+
+    Ok(())
+}
+
+/// Reads every record in the history file, oldest first
+pub fn load(state_path: &Path) -> Result<Vec<UpdateRecord>> {
+    let contents = match fs::read_to_string(path(state_path)) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+/// Prints the most recent records, either as a table or as a JSON array
+pub fn print(records: &[UpdateRecord], json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(records)?);
+        return Ok(());
+    }
+
+    if records.is_empty() {
+        println!("No updates recorded yet");
+        return Ok(());
+    }
+
+    println!(
+        "{:<20}  {:<14}  {:<14}  {:>9}  {:>10}  OUTCOME",
+        "STARTED", "OLD", "NEW", "DURATION", "SIZE"
+    );
+    for record in records {
+        let outcome = match (&record.outcome, &record.error) {
+            (Outcome::Failed, Some(error)) => format!("failed: {error}"),
+            (Outcome::Checked, Some(error)) => format!("worlds corrupt after starting: {error}"),
+            (Outcome::Checked, None) => String::from("worlds intact after starting"),
+            (outcome, _) => format!("{outcome:?}").to_lowercase(),
+        };
+
+        println!(
+            "{:<20}  {:<14}  {:<14}  {:>8}s  {:>10}  {outcome}",
+            humantime::format_rfc3339_seconds(record.started).to_string(),
+            record.old_version,
+            record.new_version,
+            record.duration.as_secs(),
+            record.bytes.map_or_else(|| String::from("-"), format_bytes),
+        );
+    }
+
+    Ok(())
+}
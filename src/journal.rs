@@ -0,0 +1,297 @@
+use std::{
+    cell::Cell,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{error::Result, version::BedrockVersion};
+
+/// Journal file stored next to the state file, each line is one step of the install in progress
+const JOURNAL_FILE: &str = ".updater-journal.jsonl";
+
+/// A step of an install, written before the next one begins
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "step", rename_all = "kebab-case")]
+pub enum Step {
+    /// The install began with a downloaded and verified zip
+    Started {
+        pid: u32,
+        /// When the process started, so a later one with the same pid isn't taken for it
+        #[serde(default)]
+        started: Option<String>,
+        from: BedrockVersion,
+        to: BedrockVersion,
+        source: String,
+        checksum: String,
+        staging: PathBuf,
+    },
+    /// The zip was extracted into the staging directory and looks like a server
+    Extracted,
+    /// Files are about to be written over the server directory
+    Copying,
+    Copied {
+        path: String,
+    },
+    /// The current link is about to be pointed at the new version directory
+    Switching {
+        version_dir: PathBuf,
+    },
+    /// The state file records the new version, only cleaning up is left
+    StateWritten,
+}
+
+/// The journal of the install this run is doing
+/// It is removed once the install is done, or when it ends before the server was touched
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    touched: Cell<bool>,
+    resumed: bool,
+}
+
+fn path(state_path: &Path) -> PathBuf {
+    state_path.with_file_name(JOURNAL_FILE)
+}
+
+impl Journal {
+    /// Starts the journal of a new install
+    pub fn start(state_path: &Path, started: &Step) -> Result<Self> {
+        let path = path(state_path);
+        let journal = Self {
+            file: File::create(&path)?,
+            path,
+            touched: Cell::new(false),
+            resumed: false,
+        };
+        journal.record(started)?;
+
+        Ok(journal)
+    }
+
+    /// Continues the journal of an interrupted install, which already touched the server
+    pub fn resume(state_path: &Path) -> Result<Self> {
+        let path = path(state_path);
+
+        Ok(Self {
+            file: OpenOptions::new().append(true).open(&path)?,
+            path,
+            touched: Cell::new(true),
+            resumed: true,
+        })
+    }
+
+    /// Appends a step, making sure it is on disk unless it is one of the many copied files
+    pub fn record(&self, step: &Step) -> Result<()> {
+        let mut line = serde_json::to_vec(step)?;
+        line.push(b'\n');
+        (&self.file).write_all(&line)?;
+
+        if matches!(step, Step::Copying | Step::Switching { .. }) {
+            self.touched.set(true);
+        }
+        if !matches!(step, Step::Copied { .. }) {
+            self.file.sync_data()?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether the install was interrupted and is now being finished
+    pub fn resumed(&self) -> bool {
+        self.resumed
+    }
+
+    /// The install undid everything it wrote, so the journal goes away with it like before the server was touched
+    pub fn rolled_back(&self) {
+        self.touched.set(false);
+    }
+
+    /// Removes the journal, the install has nothing left to recover
+    pub fn finish(self) -> Result<()> {
+        fs::remove_file(&self.path)?;
+
+        Ok(())
+    }
+
+    /// Reads the steps of an install that did not finish, if there is one
+    pub fn interrupted(state_path: &Path) -> Result<Option<Vec<Step>>> {
+        let contents = match fs::read_to_string(path(state_path)) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        // A step that was being written when the power went out is only partly there
+        let steps = contents
+            .lines()
+            .map_while(|line| serde_json::from_str(line).ok())
+            .collect();
+
+        Ok(Some(steps))
+    }
+
+    /// The staging directory of an install that did not finish, which resuming it needs
+    pub fn interrupted_staging(state_path: &Path) -> Result<Option<PathBuf>> {
+        let first = Self::interrupted(state_path)?.and_then(|steps| steps.into_iter().next());
+
+        Ok(match first {
+            Some(Step::Started { staging, .. }) => Some(staging),
+            _ => None,
+        })
+    }
+
+    /// Removes the journal of an interrupted install once it was dealt with
+    pub fn discard(state_path: &Path) -> Result<()> {
+        match fs::remove_file(path(state_path)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for Journal {
+    /// Installs that fail before touching the server leave nothing to recover
+    /// Once they did, the journal stays so the next run can finish them
+    fn drop(&mut self) {
+        if self.touched.get() || !self.path.exists() {
+            return;
+        }
+
+        if let Err(err) = fs::remove_file(&self.path) {
+            warn!(
+                "Could not remove the install journal {:?}: {err}",
+                self.path
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_path(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "bedrock-updater-journal-{test}-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("state.json")
+    }
+
+    fn started() -> Step {
+        Step::Started {
+            pid: std::process::id(),
+            started: None,
+            from: "1.21.44.1".parse().unwrap(),
+            to: "1.21.50.7".parse().unwrap(),
+            source: String::from("https://www.minecraft.net/bedrock-server-1.21.50.7.zip"),
+            checksum: String::new(),
+            staging: PathBuf::from("update/run-1-00000000"),
+        }
+    }
+
+    fn clean_up(state_path: &Path) {
+        fs::remove_dir_all(state_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn journals_go_away_when_the_server_was_not_touched() {
+        let state_path = state_path("untouched");
+        let journal = Journal::start(&state_path, &started()).unwrap();
+        journal.record(&Step::Extracted).unwrap();
+        drop(journal);
+
+        let interrupted = Journal::interrupted(&state_path).unwrap();
+        clean_up(&state_path);
+
+        assert_eq!(interrupted, None);
+    }
+
+    #[test]
+    fn journals_of_touched_servers_are_kept() {
+        let state_path = state_path("touched");
+        let journal = Journal::start(&state_path, &started()).unwrap();
+        journal.record(&Step::Extracted).unwrap();
+        journal.record(&Step::Copying).unwrap();
+        drop(journal);
+
+        let interrupted = Journal::interrupted(&state_path).unwrap();
+        let staging = Journal::interrupted_staging(&state_path).unwrap();
+        clean_up(&state_path);
+
+        assert_eq!(
+            interrupted,
+            Some(vec![started(), Step::Extracted, Step::Copying])
+        );
+        assert_eq!(staging, Some(PathBuf::from("update/run-1-00000000")));
+    }
+
+    #[test]
+    fn rolled_back_journals_go_away() {
+        let state_path = state_path("rolled-back");
+        let journal = Journal::start(&state_path, &started()).unwrap();
+        journal.record(&Step::Copying).unwrap();
+        journal.rolled_back();
+        drop(journal);
+
+        let interrupted = Journal::interrupted(&state_path).unwrap();
+        clean_up(&state_path);
+
+        assert_eq!(interrupted, None);
+    }
+
+    #[test]
+    fn resumed_journals_continue_where_they_stopped() {
+        let state_path = state_path("resumed");
+        let journal = Journal::start(&state_path, &started()).unwrap();
+        journal.record(&Step::Copying).unwrap();
+        drop(journal);
+
+        let journal = Journal::resume(&state_path).unwrap();
+        let resumed = journal.resumed();
+        let copied = Step::Copied {
+            path: String::from("bedrock_server"),
+        };
+        journal.record(&copied).unwrap();
+        drop(journal);
+
+        let interrupted = Journal::interrupted(&state_path).unwrap();
+        clean_up(&state_path);
+
+        assert!(resumed);
+        assert_eq!(interrupted, Some(vec![started(), Step::Copying, copied]));
+    }
+
+    #[test]
+    fn steps_cut_off_while_writing_are_ignored() {
+        let state_path = state_path("cut-off");
+        let journal = Journal::start(&state_path, &started()).unwrap();
+        journal.record(&Step::Copying).unwrap();
+        (&journal.file).write_all(b"{\"step\":\"cop").unwrap();
+        drop(journal);
+
+        let interrupted = Journal::interrupted(&state_path).unwrap();
+        Journal::discard(&state_path).unwrap();
+        let discarded = Journal::interrupted(&state_path).unwrap();
+        clean_up(&state_path);
+
+        assert_eq!(interrupted, Some(vec![started(), Step::Copying]));
+        assert_eq!(discarded, None);
+    }
+
+    #[test]
+    fn journals_without_a_start_have_no_staging_directory() {
+        let state_path = state_path("no-start");
+        fs::write(path(&state_path), "{\"step\":\"copying\"}\n").unwrap();
+
+        let staging = Journal::interrupted_staging(&state_path).unwrap();
+        clean_up(&state_path);
+
+        assert_eq!(staging, None);
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use tracing::{debug, info, trace};
+
+use crate::{
+    clean::format_bytes,
+    error::Result,
+    manifest::{self, Manifest},
+    version::BedrockVersion,
+};
+
+/// Every release of the versioned layout is installed into its own directory in here
+pub const VERSIONS_DIR: &str = "versions";
+
+/// Link in the server directory pointing at the version directory the server runs from
+pub const CURRENT_LINK: &str = "current";
+
+/// Manifest of what the release in a version directory shipped, rewritten every time the current link is switched to it
+pub const VERSION_MANIFEST: &str = ".updater-manifest.json";
+
+/// A release still on disk in its own version directory, which can be switched back to right away
+#[derive(Debug)]
+pub struct RetainedVersion {
+    pub version: BedrockVersion,
+    pub path: PathBuf,
+    pub manifest: Manifest,
+    /// When the current link was last switched to it
+    pub last_used: SystemTime,
+}
+
+/// How installs are laid out in the server directory
+#[derive(ValueEnum, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum InstallLayout {
+    /// New files are copied over the server directory
+    #[default]
+    InPlace,
+    /// Each release gets its own directory under versions/, and the current link is switched to it in one step
+    Versioned,
+}
+
+impl InstallLayout {
+    /// The directory the server runs from
+    pub fn live_dir(self, server_dir: &Path) -> PathBuf {
+        match self {
+            InstallLayout::InPlace => server_dir.to_owned(),
+            InstallLayout::Versioned => server_dir.join(CURRENT_LINK),
+        }
+    }
+}
+
+/// The version directory the current link points at, if there is one
+pub fn current_target(server_dir: &Path) -> Option<PathBuf> {
+    let target = fs::read_link(server_dir.join(CURRENT_LINK)).ok()?;
+
+    Some(server_dir.join(target))
+}
+
+/// Lists the complete version directories, the least recently used first
+/// Directories without a manifest never finished installing and are left out
+pub fn retained(server_dir: &Path) -> Result<Vec<RetainedVersion>> {
+    let versions = server_dir.join(VERSIONS_DIR);
+    if !versions.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut retained = Vec::new();
+    for entry in fs::read_dir(versions)? {
+        let path = entry?.path();
+        let manifest_path = path.join(VERSION_MANIFEST);
+        let Some(manifest) = Manifest::read(&manifest_path)? else {
+            continue;
+        };
+
+        retained.push(RetainedVersion {
+            version: manifest.version,
+            last_used: fs::metadata(&manifest_path)?.modified()?,
+            path,
+            manifest,
+        });
+    }
+
+    retained.sort_by_key(|retained| retained.last_used);
+    Ok(retained)
+}
+
+/// Lists the least recently used version directories beyond the retention limit, never the running one
+pub fn excess(server_dir: &Path, keep: usize) -> Result<Vec<RetainedVersion>> {
+    let current = current_target(server_dir);
+    let retained = retained(server_dir)?;
+    let excess = retained.len().saturating_sub(keep.max(1));
+
+    Ok(retained
+        .into_iter()
+        .filter(|retained| Some(&retained.path) != current.as_ref())
+        .take(excess)
+        .collect())
+}
+
+/// Removes the least recently used version directories until at most `keep` remain
+pub fn prune(server_dir: &Path, keep: usize) -> Result<()> {
+    for retained in excess(server_dir, keep)? {
+        info!("Removing old version directory {:?}", retained.path);
+        fs::remove_dir_all(&retained.path)?;
+    }
+
+    Ok(())
+}
+
+/// Replaces files of the version directory with hard links to identical files of the other retained versions
+/// Most files don't change between releases, so keeping several of them costs little more than keeping one
+/// Only files the release shipped are linked, files carried over like worlds change and must stay separate
+pub fn dedup(server_dir: &Path, version_dir: &Path, manifest: &Manifest) -> Result<()> {
+    let mut candidates: HashMap<&str, Vec<PathBuf>> = HashMap::new();
+    let retained = retained(server_dir)?;
+    for other in retained.iter().filter(|other| other.path != version_dir) {
+        for (relative, entry) in &other.manifest.files {
+            candidates
+                .entry(&entry.sha256)
+                .or_default()
+                .push(other.path.join(relative));
+        }
+    }
+
+    let (mut linked, mut saved) = (0, 0);
+    for (relative, entry) in &manifest.files {
+        let Some(candidates) = candidates.get(entry.sha256.as_str()) else {
+            continue;
+        };
+        let path = version_dir.join(relative);
+
+        // Files of older versions can have been edited since their manifest was written
+        let mut found = None;
+        for candidate in candidates {
+            if candidate.is_file() && manifest::hash_file(candidate)? == entry.sha256 {
+                found = Some(candidate);
+                break;
+            }
+        }
+        let Some(original) = found else {
+            continue;
+        };
+
+        // Linking next to the file and renaming it over the file never leaves it missing
+        let link = path.with_file_name(format!(
+            ".{}.link",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        let size = fs::metadata(&path)?.len();
+        fs::hard_link(original, &link)?;
+        fs::rename(&link, &path)?;
+        trace!("Linked {path:?} to {original:?}");
+
+        linked += 1;
+        saved += size;
+    }
+
+    if linked > 0 {
+        info!(
+            "Hard linked {linked} files that other versions share, saving {}",
+            format_bytes(saved)
+        );
+    }
+    Ok(())
+}
+
+/// A directory for the version that isn't in use yet
+/// Reinstalling the running version gets a numbered directory, since the live one can't be replaced in place
+pub fn version_dir(server_dir: &Path, version: &BedrockVersion) -> Result<PathBuf> {
+    let versions = server_dir.join(VERSIONS_DIR);
+    let current = current_target(server_dir);
+    fs::create_dir_all(&versions)?;
+
+    let mut build = 1;
+    loop {
+        let name = match build {
+            1 => version.to_string(),
+            build => format!("{version}-{build}"),
+        };
+        let dir = versions.join(name);
+
+        if current.as_ref() == Some(&dir) {
+            build += 1;
+            continue;
+        }
+        // An older build that isn't running anymore is replaced
+        if dir.exists() {
+            debug!("Replacing unused version directory {dir:?}");
+            fs::remove_dir_all(&dir)?;
+        }
+
+        return Ok(dir);
+    }
+}
+
+/// Points the current link at the version directory
+/// The new link is created next to the old one and renamed over it, so the switch happens in a single step
+#[cfg(unix)]
+pub fn switch(server_dir: &Path, version_dir: &Path) -> Result<()> {
+    // Relative targets keep working when the server directory is moved or mounted somewhere else
+    let target = version_dir.strip_prefix(server_dir).unwrap_or(version_dir);
+    let link = server_dir.join(CURRENT_LINK);
+    let next = server_dir.join(format!(".{CURRENT_LINK}.next"));
+
+    if fs::symlink_metadata(&next).is_ok() {
+        fs::remove_file(&next)?;
+    }
+    std::os::unix::fs::symlink(target, &next)?;
+    fs::rename(&next, &link)?;
+
+    info!("Switched {link:?} to {target:?}");
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn switch(_server_dir: &Path, _version_dir: &Path) -> Result<()> {
+    Err(crate::error::BedrockUpdaterError::LayoutUnsupported)
+}
@@ -1,10 +1,28 @@
-use crate::args::Args;
+use crate::args::{Args, Command};
+use crate::circuit::CircuitBreaker;
+use crate::config::{ClientSettings, Config, Installation, Settings, SourceSettings};
+use crate::journal::Journal;
+use crate::owner::Owner;
+use crate::state::State;
+use crate::status::{Status, StatusReport};
 
 use clap::Parser;
 use error::Result;
-use std::path::Path;
-use tracing::{error, warn, Level};
-use updater::BedrockUpdater;
+use rand::Rng;
+use std::{
+    fs::{self, OpenOptions},
+    path::PathBuf,
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, info_span, warn, Instrument, Level};
+use tracing_subscriber::{fmt::writer::BoxMakeWriter, EnvFilter};
+use updater::{BedrockUpdater, UpdateMode};
 
 mod error;
 
@@ -12,45 +30,626 @@ mod args;
 
 mod updater;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Start by enabling tracing
+mod source;
+
+mod signals;
+
+mod circuit;
+
+mod config;
+
+mod man;
+
+mod prompt;
+
+mod status;
+
+mod history;
+
+mod journal;
+
+mod state;
+
+mod archive;
+
+mod atomic;
+
+mod backup;
+
+mod restore;
+
+mod undo;
+
+mod clean;
+
+mod manifest;
+
+mod verify;
+
+mod diff;
+
+mod doctor;
+
+mod disk;
+
+mod deferred;
+
+mod owner;
+
+mod selinux;
+
+mod detect;
+
+mod compat;
+
+mod ping;
+
+mod properties;
+
+mod players;
+
+mod world;
+
+mod progress;
+
+mod extract;
+
+mod glob;
+
+mod staging;
+
+mod strategy;
+
+mod layout;
+
+mod download;
+
+mod quarantine;
+
+mod retry;
+
+mod version;
+
+mod daemon;
+
+fn main() -> ExitCode {
+    // Startup errors are printed with their message rather than their debug representation
+    start().unwrap_or_else(|err| {
+        eprintln!("Error: {err}");
+        ExitCode::FAILURE
+    })
+}
+
+/// Sets up logging and the runtime, then runs the updater
+fn start() -> Result<ExitCode> {
     // Arguments passed to the program
     let args = Args::parse();
 
-    let subscriber_builder = tracing_subscriber::FmtSubscriber::builder();
+    // Man pages are generated from the argument definitions alone, so nothing else needs to be set up
+    if let Some(Command::Man { output }) = &args.command {
+        man::render(output)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let config = args
+        .config
+        .as_deref()
+        .map(Config::load)
+        .transpose()?
+        .unwrap_or_default();
+
+    let pid_file = args
+        .pid_file
+        .clone()
+        .or(config.pid_file.clone())
+        .unwrap_or_else(|| PathBuf::from(config::DEFAULT_PID_FILE));
+
+    if args.daemon {
+        daemon::detach(&pid_file)?;
+    }
+
+    // Start by enabling tracing
+    // Once detached there is no terminal left, so logs go to the log file instead
+    let writer = if args.daemon {
+        let log_path = args
+            .log_file
+            .clone()
+            .or(config.log_file.clone())
+            .unwrap_or_else(|| PathBuf::from(config::DEFAULT_LOG_FILE));
 
-    let subscriber_with_level = if args.verbose {
-        subscriber_builder.with_max_level(Level::TRACE)
-    } else if args.quiet {
-        subscriber_builder.with_max_level(Level::WARN)
+        let log_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path)?;
+        BoxMakeWriter::new(Mutex::new(log_file))
     } else {
-        subscriber_builder.with_max_level(Level::INFO)
+        BoxMakeWriter::new(std::io::stdout)
+    };
+
+    let subscriber_builder = tracing_subscriber::FmtSubscriber::builder()
+        .with_writer(writer)
+        .with_ansi(!args.daemon);
+
+    let level = match (args.verbose, args.quiet) {
+        (0, 0) => Level::INFO,
+        (1, _) => Level::DEBUG,
+        (_, 1) => Level::WARN,
+        (0, _) => Level::ERROR,
+        (_, _) => Level::TRACE,
+    };
+
+    // RUST_LOG allows filtering per module, the verbosity flags only set the default level
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+
+    tracing::subscriber::set_global_default(subscriber_builder.with_env_filter(filter).finish())?;
+
+    // The log file is already open, everything from here on happens as the unprivileged user
+    if let Some(run_as) = args.run_as.as_deref().or(config.run_as.as_deref()) {
+        owner::drop_privileges(run_as, Owner::parse(run_as)?)?;
+        info!("Running as {run_as}");
+    }
+
+    if args.stop {
+        daemon::stop(&pid_file)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if args.repair_state {
+        State::repair(&Installation::Main.resolve(&args, config.clone())?)?;
+        if config.preview.is_some() {
+            State::repair(&Installation::Preview.resolve(&args, config.clone())?)?;
+        }
+    }
+
+    if let Some(Command::Status { json }) = &args.command {
+        let settings = Settings::resolve(&args, config)?;
+
+        let state = State::load(&settings)?;
+
+        let report = StatusReport {
+            current_version: state.current_version,
+            daemon_pid: daemon::running(&pid_file),
+            status: Status::load(&settings.server_dir)?,
+            pending: state.pending,
+        };
+        report.print(*json)?;
+
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Command::Backup { paths }) = &args.command {
+        let settings = Settings::resolve(&args, config)?;
+
+        let backup_path = backup::create(&settings, paths, settings.backup_format)?;
+        info!("Backup written to {backup_path:?}");
+
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Command::Restore {
+        archive,
+        dry_run,
+        yes,
+    }) = &args.command
+    {
+        let settings = Settings::resolve(&args, config)?;
+
+        restore::restore(&settings, archive, *dry_run, *yes)?;
+
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Command::UndoLastUpdate { dry_run, yes }) = &args.command {
+        let settings = Settings::resolve(&args, config)?;
+
+        undo::undo_last(&settings, *dry_run, *yes)?;
+
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Command::Clean { dry_run }) = &args.command {
+        let settings = Settings::resolve(&args, config)?;
+
+        clean::clean(&settings, daemon::running(&pid_file).is_some(), *dry_run)?;
+
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Command::Approve { version }) = &args.command {
+        let settings = Settings::resolve(&args, config)?;
+
+        let mut state = State::load(&settings)?;
+        let pending = state
+            .pending
+            .as_mut()
+            .ok_or(error::BedrockUpdaterError::NoPendingUpdate)?;
+        if let Some(version) = version.filter(|version| *version != pending.version) {
+            return Err(error::BedrockUpdaterError::PendingVersionMismatch(
+                pending.version.to_string(),
+                version.to_string(),
+            ));
+        }
+
+        pending.approved = true;
+        info!(
+            "Approved the update to {}, it is installed on the next check",
+            pending.version
+        );
+        state.save(&settings.state_path)?;
+
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Command::Verify) = &args.command {
+        let settings = Settings::resolve(&args, config)?;
+
+        return Ok(match verify::verify(&settings)? {
+            true => ExitCode::SUCCESS,
+            false => ExitCode::FAILURE,
+        });
+    }
+
+    if let Some(Command::Doctor) = &args.command {
+        let runtime = tokio::runtime::Runtime::new()?;
+
+        return Ok(match runtime.block_on(doctor::doctor(&args, config))? {
+            true => ExitCode::SUCCESS,
+            false => ExitCode::FAILURE,
+        });
+    }
+
+    if let Some(Command::History { json, limit }) = &args.command {
+        let settings = Settings::resolve(&args, config)?;
+
+        let records = history::load(&settings.state_path)?;
+        let skipped = limit.map_or(0, |limit| records.len().saturating_sub(limit));
+        history::print(&records[skipped..], *json)?;
+
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let exit_code = runtime.block_on(run(&args, config));
+
+    if args.daemon {
+        if let Err(err) = fs::remove_file(&pid_file) {
+            warn!("Could not remove pid file: {err}");
+        }
+    }
+
+    exit_code
+}
+
+/// Runs the update loop until it is stopped or finishes a single run
+async fn run(args: &Args, config: Config) -> Result<ExitCode> {
+    let client = source::client_builder(&ClientSettings::resolve(args, &config)?)?.build()?;
+
+    let shutdown = CancellationToken::new();
+    tokio::spawn(signals::cancel_on_shutdown(shutdown.clone()));
+
+    // Downloading only needs somewhere to put the zip, not a server directory
+    if let Some(Command::Download { output }) = &args.command {
+        let source = SourceSettings::resolve(args, &config)?;
+        let result = BedrockUpdater::download_latest(&client, output, &source, &shutdown).await;
+
+        return Ok(exit_code(
+            result.map(|path| info!("Saved server zip to {path:?}")),
+        ));
+    }
+
+    // The preview table has to be read before the main settings take the config
+    let preview = match config.preview.is_some() {
+        true => Some(Installation::Preview.resolve(args, config.clone())?),
+        false => None,
     };
-    
-    tracing::subscriber::set_global_default(subscriber_with_level.finish())?;
+    let settings = Installation::Main.resolve(args, config)?;
 
+    // An install a crash interrupted is finished before anything else, checks and diffs only look
+    if !matches!(args.command, Some(Command::Check | Command::Diff)) {
+        for settings in std::iter::once(&settings).chain(&preview) {
+            BedrockUpdater::new(&client, settings, None, false, &shutdown)
+                .recover()
+                .await?;
+            // Files that were in use during the last install may be free now
+            deferred::apply(&settings.state_path)?;
+        }
+    }
+
+    // A crash mid-install leaves its staging directory behind, which nothing else would ever remove
+    // Unless recovery was skipped, an install in the journal was already finished or discarded by now
+    for settings in std::iter::once(&settings).chain(&preview) {
+        let removed = Journal::interrupted_staging(&settings.state_path).and_then(|resumable| {
+            staging::remove_stale(
+                &settings.staging_root(),
+                settings.stale_staging_after,
+                resumable.as_deref(),
+            )
+        });
+        if let Err(err) = removed {
+            warn!("Could not remove stale staging directories: {err}");
+        }
+    }
+
+    // Only the run command loops, everything else is a single shot
+    let mode = match args.command.clone().unwrap_or_default() {
+        Command::Run => UpdateMode::Scheduled,
+        Command::Check => UpdateMode::Check,
+        Command::Install {
+            from_zip: Some(zip),
+            ..
+        } => {
+            let updater = BedrockUpdater::new(
+                &client,
+                &settings,
+                args.set_first_version,
+                args.interactive,
+                &shutdown,
+            );
 
-    let client = reqwest::ClientBuilder::new().build()?;
+            return Ok(exit_code(updater.install_zip(&zip).await));
+        }
+        Command::Install {
+            target_version: None,
+            force: false,
+            ..
+        } => UpdateMode::Install,
+        Command::Install {
+            target_version: None,
+            force: true,
+            ..
+        } => UpdateMode::Reinstall,
+        Command::Install {
+            target_version: Some(version),
+            ..
+        } => {
+            let updater = BedrockUpdater::new(
+                &client,
+                &settings,
+                args.set_first_version,
+                args.interactive,
+                &shutdown,
+            );
 
-    let server_dir = Path::new(&args.server_dir);
+            return Ok(exit_code(updater.install_target(version).await));
+        }
+        Command::Rollback { version } => {
+            let updater = BedrockUpdater::new(
+                &client,
+                &settings,
+                args.set_first_version,
+                args.interactive,
+                &shutdown,
+            );
 
-    let update_dir = server_dir.join(&args.update_dir);
-    // The version file should be inside the server directory
-    let version_path = server_dir.join(&args.version_file);
+            return Ok(exit_code(updater.rollback(version).await));
+        }
+        Command::Reinstall { version } => {
+            let updater = BedrockUpdater::new(
+                &client,
+                &settings,
+                args.set_first_version,
+                args.interactive,
+                &shutdown,
+            );
+
+            return Ok(exit_code(updater.reinstall(version).await));
+        }
+        Command::Diff => {
+            let updater = BedrockUpdater::new(
+                &client,
+                &settings,
+                args.set_first_version,
+                args.interactive,
+                &shutdown,
+            );
+
+            return Ok(exit_code(updater.diff().await));
+        }
+        Command::Download { .. } => {
+            unreachable!("downloads are handled before the settings are resolved")
+        }
+        Command::Backup { .. }
+        | Command::Restore { .. }
+        | Command::UndoLastUpdate { .. }
+        | Command::Clean { .. }
+        | Command::Approve { .. }
+        | Command::Verify
+        | Command::Doctor
+        | Command::Status { .. }
+        | Command::History { .. }
+        | Command::Man { .. } => {
+            unreachable!("these commands are handled before the updater starts")
+        }
+    };
+
+    let main = run_loop(args, &client, Installation::Main, settings, mode, &shutdown)
+        .instrument(info_span!("main"));
+    let Some(preview) = preview else {
+        return main.await;
+    };
 
-    let updater = BedrockUpdater::new(
+    // Both servers are updated on their own schedules, side by side
+    let preview = run_loop(
+        args,
         &client,
-        server_dir,
-        &update_dir,
-        &version_path,
-        args.set_first_version.as_deref(),
-    );
+        Installation::Preview,
+        preview,
+        mode,
+        &shutdown,
+    )
+    .instrument(info_span!("preview"));
+    let (main, preview) = tokio::join!(main, preview);
+
+    Ok(
+        match main? == ExitCode::SUCCESS && preview? == ExitCode::SUCCESS {
+            true => ExitCode::SUCCESS,
+            false => ExitCode::FAILURE,
+        },
+    )
+}
+
+/// Keeps a single installation up to date until it is stopped or finishes a single run
+async fn run_loop(
+    args: &Args,
+    client: &reqwest::Client,
+    installation: Installation,
+    mut settings: Settings,
+    mode: UpdateMode,
+    shutdown: &CancellationToken,
+) -> Result<ExitCode> {
+    // Every loop listens for reloads itself, so each of them sees the signal
+    let reload = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    tokio::spawn(signals::flag_on_reload(reload.clone()));
+
+    let once = args.once || mode != UpdateMode::Scheduled;
+    let mut throttles = 0;
+    let mut breaker = CircuitBreaker::new(settings.circuit_threshold);
 
     loop {
-        updater.run_updater().await.unwrap_or_else(|err| match err {
-            error::BedrockUpdaterError::NoCurrentVersion => warn!("{err}"),
-            _ => error!("{err}")
-        });
+        if reload.swap(false, Ordering::Relaxed) {
+            reload_settings(args, installation, &mut settings);
+        }
+
+        // Files that were in use during the last install may be free by now
+        if let Err(err) = deferred::apply(&settings.state_path) {
+            warn!("Could not replace the files that were in use: {err}");
+        }
+
+        let updater = BedrockUpdater::new(
+            client,
+            &settings,
+            args.set_first_version,
+            args.interactive,
+            shutdown,
+        );
+
+        let result = updater.run_updater(mode).await;
+
+        // Single shot runs report the outcome of the check through the exit code
+        if once {
+            return Ok(exit_code(result));
+        }
+
+        if breaker.record(&result, settings.interval) {
+            if let Err(err) = &result {
+                log_error(err);
+            }
+        }
+
+        // Rate limiting is backed off from instead of retrying at the usual rate
+        let backoff = match &result {
+            Err(error::BedrockUpdaterError::Throttled(retry_after)) => {
+                throttles += 1;
+                Some(throttle_backoff(settings.interval, throttles, *retry_after))
+            }
+            _ => {
+                throttles = 0;
+                None
+            }
+        };
+
+        if shutdown.is_cancelled() {
+            info!("Shutting down");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        // The jitter is picked again every cycle so servers started together drift apart
+        let jitter = rand::thread_rng().gen_range(Duration::ZERO..=settings.jitter);
+        let delay = backoff.unwrap_or(breaker.interval(settings.interval)) + jitter;
+
+        let next_check = humantime::format_rfc3339_seconds(SystemTime::now() + delay);
+        info!("Next update check at {next_check}");
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.cancelled() => {
+                info!("Shutting down");
+                return Ok(ExitCode::SUCCESS);
+            }
+        }
+    }
+}
+
+fn log_error(err: &error::BedrockUpdaterError) {
+    match err {
+        error::BedrockUpdaterError::NoCurrentVersion => warn!("{err}"),
+        error::BedrockUpdaterError::Cancelled => info!("{err}"),
+        error::BedrockUpdaterError::Throttled(_) => warn!("{err}"),
+        _ => error!("{err}"),
+    }
+}
+
+/// Doubles the wait after each consecutive throttle up to a day, but never waits less than the server asked for
+fn throttle_backoff(interval: Duration, throttles: u32, retry_after: Option<Duration>) -> Duration {
+    const MAX_BACKOFF: Duration = Duration::from_secs(24 * 60 * 60);
+
+    let backoff = interval
+        .saturating_mul(2u32.saturating_pow(throttles.saturating_sub(1)))
+        .min(MAX_BACKOFF);
+    let backoff = backoff.max(retry_after.unwrap_or_default());
+
+    info!(
+        "Rate limited {throttles} times in a row, backing off for {}",
+        humantime::format_duration(backoff)
+    );
+    backoff
+}
+
+/// Turns the outcome of a single shot command into the exit code, logging the error if there is one
+fn exit_code(result: Result<()>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            log_error(&err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Re-reads the config file, keeping the current settings if it cannot be loaded
+/// Command line flags still take precedence over the reloaded values
+fn reload_settings(args: &Args, installation: Installation, settings: &mut Settings) {
+    let Some(config_path) = args.config.as_deref() else {
+        warn!("Reload requested, but no config file is in use");
+        return;
+    };
+
+    match Config::load(config_path).and_then(|config| installation.resolve(args, config)) {
+        Ok(new_settings) => {
+            info!("Reloaded config file {config_path:?}");
+            settings.log_changes(&new_settings);
+            *settings = new_settings;
+        }
+        Err(err) => error!("Could not reload config file, keeping the current settings: {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn throttle_backoff_doubles() {
+        assert_eq!(throttle_backoff(MINUTE, 1, None), MINUTE);
+        assert_eq!(throttle_backoff(MINUTE, 2, None), 2 * MINUTE);
+        assert_eq!(throttle_backoff(MINUTE, 4, None), 8 * MINUTE);
+    }
+
+    #[test]
+    fn throttle_backoff_stops_at_a_day() {
+        assert_eq!(
+            throttle_backoff(MINUTE, 40, None),
+            Duration::from_secs(24 * 60 * 60)
+        );
+    }
+
+    #[test]
+    fn throttle_backoff_waits_as_long_as_the_server_asks() {
+        assert_eq!(throttle_backoff(MINUTE, 1, Some(10 * MINUTE)), 10 * MINUTE);
+        assert_eq!(throttle_backoff(MINUTE, 3, Some(MINUTE)), 4 * MINUTE);
     }
 }
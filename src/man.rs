@@ -0,0 +1,42 @@
+use std::{fs::File, path::Path};
+
+use clap::CommandFactory;
+use clap_mangen::Man;
+
+use crate::{args::Args, error::Result};
+
+/// Renders a roff man page for the binary and one for each subcommand into the output directory
+/// Subcommand pages are named like `bedrock-updater-check.1`, which is what man expects
+pub fn render(output: &Path) -> Result<()> {
+    std::fs::create_dir_all(output)?;
+
+    let mut command = Args::command();
+    command.build();
+
+    let name = command.get_name().to_owned();
+    render_page(command.clone(), &name, output)?;
+
+    for subcommand in command.get_subcommands() {
+        // The generated help subcommand would only document itself
+        if subcommand.get_name() == "help" {
+            continue;
+        }
+
+        let page_name = format!("{name}-{}", subcommand.get_name());
+        render_page(subcommand.clone(), &page_name, output)?;
+    }
+
+    Ok(())
+}
+
+fn render_page(command: clap::Command, title: &str, output: &Path) -> Result<()> {
+    let man = Man::new(command).title(title);
+    let path = output.join(man.get_filename());
+
+    let mut file = File::create(&path)?;
+    man.render(&mut file)?;
+
+    println!("Wrote {}", path.display());
+
+    Ok(())
+}
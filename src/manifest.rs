@@ -0,0 +1,132 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{atomic, error::Result, version::BedrockVersion};
+
+/// Manifest file name, stored next to the state file
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Every file an install laid down, keyed by its path relative to the server directory
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    pub version: BedrockVersion,
+    pub files: BTreeMap<String, FileEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    pub sha256: String,
+    /// Missing from manifests written by older versions of the updater
+    #[serde(default)]
+    pub size: u64,
+    /// When the install laid the file down, so later changes to it can be noticed without hashing
+    #[serde(default, with = "humantime_serde")]
+    pub modified: Option<SystemTime>,
+}
+
+impl Manifest {
+    pub fn path(state_path: &Path) -> PathBuf {
+        state_path.with_file_name(MANIFEST_FILE)
+    }
+
+    /// Reads the manifest, which only exists once the updater has installed a version itself
+    pub fn load(state_path: &Path) -> Result<Option<Self>> {
+        Self::read(&Self::path(state_path))
+    }
+
+    pub fn save(&self, state_path: &Path) -> Result<()> {
+        self.write(&Self::path(state_path))
+    }
+
+    /// Reads a manifest stored anywhere else, like in a version directory
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        atomic::write(path, &serde_json::to_vec_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    /// The top level entries the release shipped, like behavior_packs for everything inside it
+    pub fn shipped(&self) -> BTreeSet<String> {
+        self.files
+            .keys()
+            .filter_map(|key| key.split('/').next().map(String::from))
+            .collect()
+    }
+
+    /// Hashes every file below the given entries of the root directory
+    /// The root is the extracted update, so the recorded paths are the ones they'll have in the server directory
+    pub fn build(version: &BedrockVersion, root: &Path, entries: &[PathBuf]) -> Result<Self> {
+        let mut files = BTreeMap::new();
+
+        for entry in entries {
+            for file in files_below(entry)? {
+                let relative = file.strip_prefix(root).unwrap_or(&file);
+                let entry = FileEntry {
+                    sha256: hash_file(&file)?,
+                    size: fs::metadata(&file)?.len(),
+                    modified: None,
+                };
+                files.insert(manifest_key(relative), entry);
+            }
+        }
+
+        Ok(Self {
+            version: *version,
+            files,
+        })
+    }
+
+    /// Records the size and modification time of every file as it was laid down in the directory
+    /// Copies get a new modification time, so this runs once the files are in place
+    pub fn record_laid_down(&mut self, dir: &Path) -> Result<()> {
+        for (relative, entry) in &mut self.files {
+            let metadata = fs::metadata(dir.join(relative))?;
+            entry.size = metadata.len();
+            entry.modified = Some(metadata.modified()?);
+        }
+
+        Ok(())
+    }
+}
+
+/// Manifest keys always use forward slashes so the manifest is the same on every platform
+pub fn manifest_key(relative: &Path) -> String {
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Lists the path itself if it is a file, or every file inside it if it is a directory
+pub fn files_below(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_owned()]);
+    }
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path)? {
+        files.extend(files_below(&entry?.path())?);
+    }
+
+    Ok(files)
+}
@@ -0,0 +1,201 @@
+use std::{
+    fs::{self, Metadata},
+    io,
+    path::Path,
+};
+
+use tracing::debug;
+
+use crate::error::{BedrockUpdaterError, Result};
+
+/// The user and group the files an install writes are given, so the server can still write to them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Owner {
+    /// Parses user:group, where both can be names or numeric ids
+    /// Without a group, the user's primary group is used
+    #[cfg(unix)]
+    pub fn parse(spec: &str) -> Result<Self> {
+        let invalid = || BedrockUpdaterError::InvalidOwner(spec.to_owned());
+        let (user, group) = match spec.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (spec, None),
+        };
+        if user.is_empty() || group.is_some_and(str::is_empty) {
+            return Err(invalid());
+        }
+
+        let (uid, primary_gid) = match user.parse::<u32>() {
+            Ok(uid) => (uid, None),
+            Err(_) => {
+                let (uid, gid) = lookup_user(user).ok_or_else(invalid)?;
+                (uid, Some(gid))
+            }
+        };
+        let gid = match group {
+            Some(group) => group
+                .parse::<u32>()
+                .ok()
+                .or_else(|| lookup_group(group))
+                .ok_or_else(invalid)?,
+            None => primary_gid
+                .or_else(|| lookup_uid(uid))
+                .ok_or_else(invalid)?,
+        };
+
+        Ok(Self { uid, gid })
+    }
+
+    /// Files have no unix owner here
+    #[cfg(not(unix))]
+    pub fn parse(spec: &str) -> Result<Self> {
+        Err(BedrockUpdaterError::InvalidOwner(spec.to_owned()))
+    }
+
+    /// Gives the path to the owner, a symlink itself rather than what it points to
+    pub fn apply(&self, path: &Path) -> io::Result<()> {
+        #[cfg(unix)]
+        std::os::unix::fs::lchown(path, Some(self.uid), Some(self.gid))?;
+        #[cfg(not(unix))]
+        let _ = path;
+
+        Ok(())
+    }
+
+    /// Gives the path and everything below it to the owner
+    pub fn apply_all(&self, path: &Path) -> io::Result<()> {
+        self.apply(path)?;
+        if fs::symlink_metadata(path)?.is_dir() {
+            for entry in fs::read_dir(path)? {
+                self.apply_all(&entry?.path())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Switches the process to the user and group for good, so nothing it does after can use root's permissions
+/// Only root can switch, running as the user already is fine too
+#[cfg(unix)]
+pub fn drop_privileges(spec: &str, owner: Owner) -> Result<()> {
+    let failed = |err| BedrockUpdaterError::DropPrivileges(spec.to_owned(), err);
+
+    // SAFETY: these only read and change the ids of the process
+    unsafe {
+        if (libc::getuid(), libc::getgid()) == (owner.uid, owner.gid) {
+            return Ok(());
+        }
+
+        // Supplementary groups are dropped first, since root's would otherwise stay
+        if libc::setgroups(1, &owner.gid) != 0
+            || libc::setgid(owner.gid) != 0
+            || libc::setuid(owner.uid) != 0
+        {
+            return Err(failed(io::Error::last_os_error()));
+        }
+
+        // A process that can get root back did not really drop it
+        if libc::setuid(0) == 0 && owner.uid != 0 {
+            return Err(failed(io::Error::from(io::ErrorKind::PermissionDenied)));
+        }
+    }
+    debug!("Running as uid {} and gid {}", owner.uid, owner.gid);
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(spec: &str, _owner: Owner) -> Result<()> {
+    Err(BedrockUpdaterError::InvalidOwner(spec.to_owned()))
+}
+
+/// Gives a file that replaced another the owner and mode the replaced one had
+/// Only root can hand files to other users, so an owner that can't be kept is left as it is
+pub fn preserve(original: &Metadata, path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let metadata = fs::symlink_metadata(path)?;
+        if metadata.is_symlink() {
+            return Ok(());
+        }
+        if (metadata.uid(), metadata.gid()) != (original.uid(), original.gid()) {
+            match std::os::unix::fs::lchown(path, Some(original.uid()), Some(original.gid())) {
+                Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                    debug!("Could not keep the owner of {path:?}: {err}");
+                }
+                owned => owned?,
+            }
+        }
+        if metadata.mode() & 0o7777 != original.mode() & 0o7777 {
+            fs::set_permissions(path, fs::Permissions::from_mode(original.mode() & 0o7777))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (original, path);
+
+    Ok(())
+}
+
+/// Gives everything below the copy of a directory the owner and mode of what it was copied from
+pub fn preserve_all(original: &Path, path: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(original)?;
+    preserve(&metadata, path)?;
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let name = entry?.file_name();
+            if original.join(&name).exists() {
+                preserve_all(&original.join(&name), &path.join(&name))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The id and primary group of the user with the name
+#[cfg(unix)]
+fn lookup_user(name: &str) -> Option<(u32, u32)> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: the name is a valid C string, and the entry is read before anything else could look up a user
+    let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+    if entry.is_null() {
+        return None;
+    }
+
+    // SAFETY: getpwnam returned a valid entry
+    unsafe { Some(((*entry).pw_uid, (*entry).pw_gid)) }
+}
+
+/// The primary group of the user with the id
+#[cfg(unix)]
+fn lookup_uid(uid: u32) -> Option<u32> {
+    // SAFETY: the entry is read before anything else could look up a user
+    let entry = unsafe { libc::getpwuid(uid) };
+    if entry.is_null() {
+        return None;
+    }
+
+    // SAFETY: getpwuid returned a valid entry
+    unsafe { Some((*entry).pw_gid) }
+}
+
+/// The id of the group with the name
+#[cfg(unix)]
+fn lookup_group(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: the name is a valid C string, and the entry is read before anything else could look up a group
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() {
+        return None;
+    }
+
+    // SAFETY: getgrnam returned a valid entry
+    unsafe { Some((*entry).gr_gid) }
+}
@@ -0,0 +1,108 @@
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::net::UdpSocket;
+
+use crate::{
+    error::{BedrockUpdaterError, Result},
+    properties::{self, PROPERTIES_FILE},
+};
+
+/// Port the server listens on when server.properties does not set one
+const DEFAULT_SERVER_PORT: u16 = 19132;
+
+/// How long to wait for the pong before assuming the server is not running
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Marks RakNet offline messages
+const MAGIC: [u8; 16] = [
+    0x00, 0xff, 0xff, 0x00, 0xfe, 0xfe, 0xfe, 0xfe, 0xfd, 0xfd, 0xfd, 0xfd, 0x12, 0x34, 0x56, 0x78,
+];
+
+const UNCONNECTED_PING: u8 = 0x01;
+const UNCONNECTED_PONG: u8 = 0x1c;
+
+/// What a running server reports about itself
+#[derive(Debug)]
+pub struct Pong {
+    pub motd: String,
+    pub protocol: u32,
+    /// Only the first three parts of the version, e.g. 1.21.44
+    pub version: String,
+    pub players: u32,
+    pub max_players: u32,
+}
+
+/// Address of the server in the server directory, using the port from its server.properties
+pub fn local_address(server_dir: &Path) -> String {
+    let port = properties::load(&server_dir.join(PROPERTIES_FILE))
+        .ok()
+        .and_then(|properties| properties.get("server-port")?.parse().ok())
+        .unwrap_or(DEFAULT_SERVER_PORT);
+
+    format!("127.0.0.1:{port}")
+}
+
+/// Sends a RakNet unconnected ping, returning nothing when the server does not answer in time
+pub async fn ping(address: &str) -> Result<Option<Pong>> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(address).await?;
+
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let mut packet = vec![UNCONNECTED_PING];
+    packet.extend_from_slice(&time.to_be_bytes());
+    packet.extend_from_slice(&MAGIC);
+    // Client guid, the server only echoes it
+    packet.extend_from_slice(&rand::random::<u64>().to_be_bytes());
+    socket.send(&packet).await?;
+
+    let mut buffer = [0; 1500];
+    let length = match tokio::time::timeout(PING_TIMEOUT, socket.recv(&mut buffer)).await {
+        Ok(received) => received?,
+        Err(_) => return Ok(None),
+    };
+
+    parse_pong(&buffer[..length]).map(Some)
+}
+
+/// The pong is the id, time, server guid and magic followed by a length prefixed string
+/// e.g. MCPE;Dedicated Server;748;1.21.44;0;10;13253860892328930865;Bedrock level;Survival;1;19132;19133;
+fn parse_pong(packet: &[u8]) -> Result<Pong> {
+    const HEADER: usize = 1 + 8 + 8 + 16;
+
+    if packet.len() < HEADER + 2 || packet[0] != UNCONNECTED_PONG || packet[17..HEADER] != MAGIC {
+        return Err(BedrockUpdaterError::InvalidPong);
+    }
+
+    let length = u16::from_be_bytes([packet[HEADER], packet[HEADER + 1]]) as usize;
+    let status = packet
+        .get(HEADER + 2..HEADER + 2 + length)
+        .ok_or(BedrockUpdaterError::InvalidPong)?;
+    let status = String::from_utf8_lossy(status);
+
+    let fields: Vec<&str> = status.split(';').collect();
+    let field = |index: usize| {
+        fields
+            .get(index)
+            .copied()
+            .ok_or(BedrockUpdaterError::InvalidPong)
+    };
+    let number = |index: usize| {
+        field(index)?
+            .parse()
+            .map_err(|_| BedrockUpdaterError::InvalidPong)
+    };
+
+    Ok(Pong {
+        motd: field(1)?.to_owned(),
+        protocol: number(2)?,
+        version: field(3)?.to_owned(),
+        players: number(4)?,
+        max_players: number(5)?,
+    })
+}
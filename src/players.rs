@@ -0,0 +1,112 @@
+use std::{fs, io, path::Path};
+
+use serde_json::Value;
+
+/// Permission levels permissions.json can give players
+const PERMISSIONS: [&str; 3] = ["operator", "member", "visitor"];
+
+/// Player lists the server reads on startup, ignoring entries, or the whole file, it can't make sense of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerList {
+    Allowlist,
+    Permissions,
+}
+
+impl PlayerList {
+    pub const ALL: [PlayerList; 2] = [PlayerList::Allowlist, PlayerList::Permissions];
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            PlayerList::Allowlist => "allowlist.json",
+            PlayerList::Permissions => "permissions.json",
+        }
+    }
+
+    /// Everything in the list the server would ignore, as one line each
+    /// A missing list has no problems, the server creates an empty one
+    pub fn problems(self, server_dir: &Path) -> io::Result<Vec<String>> {
+        let contents = match fs::read_to_string(server_dir.join(self.file_name())) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        let entries = match serde_json::from_str::<Value>(&contents) {
+            Ok(Value::Array(entries)) => entries,
+            Ok(_) => {
+                return Ok(vec![String::from(
+                    "the file is not a list, so none of it is used",
+                )])
+            }
+            Err(err) => {
+                return Ok(vec![format!(
+                    "the file is not valid JSON, so none of it is used: {err}"
+                )])
+            }
+        };
+
+        Ok(entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| {
+                let problem = match entry {
+                    Value::Object(_) => self.entry_problem(entry)?,
+                    _ => String::from("is not an object"),
+                };
+                Some(format!(
+                    "entry {} {problem}, so the server skips it",
+                    index + 1
+                ))
+            })
+            .collect())
+    }
+
+    /// What is wrong with a single entry, checked against the format the server reads
+    fn entry_problem(self, entry: &Value) -> Option<String> {
+        let has_xuid = match entry.get("xuid") {
+            None => false,
+            Some(Value::String(xuid))
+                if !xuid.is_empty() && xuid.bytes().all(|byte| byte.is_ascii_digit()) =>
+            {
+                true
+            }
+            Some(xuid) => return Some(format!("has xuid {xuid}, which is not a string of digits")),
+        };
+
+        match self {
+            PlayerList::Allowlist => {
+                match entry.get("name") {
+                    Some(Value::String(name)) if !name.trim().is_empty() => {}
+                    Some(name) => {
+                        return Some(format!("has name {name}, which is not a player name"))
+                    }
+                    None => return Some(String::from("has no name")),
+                }
+                match entry.get("ignoresPlayerLimit") {
+                    None | Some(Value::Bool(_)) => None,
+                    Some(ignores) => Some(format!(
+                        "has ignoresPlayerLimit {ignores}, which is not true or false"
+                    )),
+                }
+            }
+            PlayerList::Permissions => {
+                // Permissions are only ever matched by xuid
+                if !has_xuid {
+                    return Some(String::from("has no xuid"));
+                }
+                match entry.get("permission") {
+                    Some(Value::String(permission))
+                        if PERMISSIONS.contains(&permission.as_str()) =>
+                    {
+                        None
+                    }
+                    Some(permission) => Some(format!(
+                        "has permission {permission}, which is not one of {}",
+                        PERMISSIONS.join(", ")
+                    )),
+                    None => Some(String::from("has no permission")),
+                }
+            }
+        }
+    }
+}
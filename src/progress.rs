@@ -0,0 +1,78 @@
+use std::time::{Duration, Instant};
+
+use tracing::{debug, info};
+
+use crate::clean::format_bytes;
+
+/// How often progress is logged, often enough to show the updater is not stuck without flooding the log
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Logs how far a long running download or extraction got
+pub struct Progress {
+    action: &'static str,
+    total: Option<u64>,
+    done: u64,
+    /// Entries done and in total, for work made of many separate files
+    items: Option<(u64, u64)>,
+    started: Instant,
+    last_report: Instant,
+}
+
+impl Progress {
+    /// The total is left out when it is not known up front, e.g. without a Content-Length
+    pub fn new(action: &'static str, total: Option<u64>) -> Self {
+        let now = Instant::now();
+
+        Self {
+            action,
+            total: total.filter(|total| *total > 0),
+            done: 0,
+            items: None,
+            started: now,
+            last_report: now,
+        }
+    }
+
+    /// Also reports how many of the given number of entries are done
+    pub fn with_items(mut self, total: u64) -> Self {
+        self.items = Some((0, total));
+        self
+    }
+
+    pub fn next_item(&mut self) {
+        if let Some((done, _)) = &mut self.items {
+            *done += 1;
+        }
+    }
+
+    pub fn advance(&mut self, amount: u64) {
+        self.done += amount;
+
+        if self.last_report.elapsed() >= REPORT_INTERVAL {
+            self.last_report = Instant::now();
+            let items = match self.items {
+                Some((done, total)) => format!(", entry {done} of {total}"),
+                None => String::new(),
+            };
+            match self.total {
+                Some(total) => info!(
+                    "{}: {}% ({} of {}{items})",
+                    self.action,
+                    self.done * 100 / total,
+                    format_bytes(self.done),
+                    format_bytes(total)
+                ),
+                None => info!("{}: {}{items}", self.action, format_bytes(self.done)),
+            }
+        }
+    }
+
+    pub fn finish(&self) {
+        debug!(
+            "{} finished, {} in {:.1?}",
+            self.action,
+            format_bytes(self.done),
+            self.started.elapsed()
+        );
+    }
+}
@@ -0,0 +1,19 @@
+use std::io::{self, Write};
+
+use crate::error::Result;
+
+/// Asks a yes/no question on the terminal, anything other than yes counts as no
+pub fn ask(question: &str) -> io::Result<bool> {
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Same as ask, but reading stdin blocks, so it happens on a blocking thread instead of the async runtime
+pub async fn confirm(question: String) -> Result<bool> {
+    Ok(tokio::task::spawn_blocking(move || ask(&question)).await??)
+}
@@ -0,0 +1,43 @@
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+pub const PROPERTIES_FILE: &str = "server.properties";
+
+/// Reads a server.properties style file into its keys and values, skipping comments and blank lines
+pub fn load(path: &Path) -> io::Result<BTreeMap<String, String>> {
+    let contents = fs::read_to_string(path)?;
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().to_owned(), value.trim().to_owned()))
+        .collect())
+}
+
+/// Writes the kept file with the keys only the shipped file has appended, so every kept value stays as it is
+/// Returns the appended keys
+pub fn merge(kept: &Path, shipped: &Path, into: &Path) -> io::Result<BTreeMap<String, String>> {
+    let new_keys = new_keys(kept, shipped)?;
+
+    let mut contents = fs::read_to_string(kept)?;
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    for (key, value) in &new_keys {
+        contents.push_str(&format!("{key}={value}\n"));
+    }
+    fs::write(into, contents)?;
+
+    Ok(new_keys)
+}
+
+/// Keys the shipped file has that the kept file is missing, with their shipped default values
+pub fn new_keys(kept: &Path, shipped: &Path) -> io::Result<BTreeMap<String, String>> {
+    let kept = load(kept)?;
+
+    Ok(load(shipped)?
+        .into_iter()
+        .filter(|(key, _)| !kept.contains_key(key))
+        .collect())
+}
@@ -0,0 +1,86 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::Url;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{
+    error::{BedrockUpdaterError, Result},
+    manifest,
+};
+
+/// Zips that failed verification or extraction, relative to the server directory
+pub const QUARANTINE_DIR: &str = ".updater-quarantine";
+
+/// Written next to a quarantined zip, so it is clear where it came from and what was wrong with it
+#[derive(Serialize, Debug)]
+struct Metadata {
+    url: String,
+    #[serde(with = "humantime_serde")]
+    quarantined: SystemTime,
+    reason: String,
+    sha256: String,
+    bytes: u64,
+}
+
+/// Moves a bad zip into the quarantine directory instead of deleting it, so what was served can be inspected
+pub fn store(dir: &Path, zip: &Path, url: &Url, reason: &BedrockUpdaterError) -> Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let file_name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("download.zip");
+    let path = dir.join(format!("{seconds}-{file_name}"));
+
+    let metadata = Metadata {
+        url: url.to_string(),
+        quarantined: SystemTime::now(),
+        reason: reason.to_string(),
+        sha256: manifest::hash_file(zip)?,
+        bytes: fs::metadata(zip)?.len(),
+    };
+
+    // Renaming fails when the quarantine is on another filesystem
+    if fs::rename(zip, &path).is_err() {
+        fs::copy(zip, &path)?;
+        fs::remove_file(zip)?;
+    }
+    fs::write(
+        path.with_extension("json"),
+        serde_json::to_vec_pretty(&metadata)?,
+    )?;
+
+    warn!("Moved the bad download to {path:?} for inspection");
+    Ok(path)
+}
+
+/// Quarantines the zip when there is a quarantine directory, otherwise or when that fails it is deleted
+pub fn store_or_remove(
+    dir: Option<&Path>,
+    zip: &Path,
+    url: &Url,
+    reason: &BedrockUpdaterError,
+) -> Result<()> {
+    if let Some(dir) = dir {
+        match store(dir, zip, url, reason) {
+            Ok(_) => return Ok(()),
+            Err(err) => warn!("Could not quarantine the bad download, deleting it instead: {err}"),
+        }
+    }
+
+    if zip.exists() {
+        fs::remove_file(zip)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use tar::EntryType;
+use tracing::{debug, info};
+use zip::ZipArchive;
+
+use crate::{
+    backup::BackupFormat,
+    config::Settings,
+    error::{BedrockUpdaterError, Result},
+    prompt,
+};
+
+/// An entry of a backup, with what it holds
+enum Entry<'a> {
+    Dir,
+    File(&'a mut dyn Read),
+    Link(PathBuf),
+}
+
+/// What restoring a file from the backup does to the server directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Create,
+    Overwrite,
+    Unchanged,
+}
+
+/// Unpacks a backup archive into the server directory
+/// Every change is listed first, and overwriting existing files needs confirmation unless `assume_yes` is set
+pub fn restore(settings: &Settings, archive: &Path, dry_run: bool, assume_yes: bool) -> Result<()> {
+    // Backups can be given by name alone, in which case they are looked up in the backup directory
+    let archive = if archive.exists() {
+        archive.to_owned()
+    } else {
+        settings.backup_dir.join(archive)
+    };
+
+    let changes = plan(settings, &archive)?;
+
+    for (path, change) in &changes {
+        match change {
+            Change::Create => println!("  create    {}", path.display()),
+            Change::Overwrite => println!("  overwrite {}", path.display()),
+            Change::Unchanged => debug!("Unchanged {path:?}"),
+        }
+    }
+
+    let count = |kind| changes.iter().filter(|(_, change)| *change == kind).count();
+    let overwrites = count(Change::Overwrite);
+    println!(
+        "{} files created, {overwrites} overwritten, {} unchanged",
+        count(Change::Create),
+        count(Change::Unchanged)
+    );
+
+    if dry_run {
+        return Ok(());
+    }
+
+    if overwrites > 0 && !assume_yes && !prompt::ask(&format!("Overwrite {overwrites} files?"))? {
+        info!("Restore cancelled");
+        return Ok(());
+    }
+
+    info!("Restoring {archive:?} into {:?}", settings.server_dir);
+    for_each_entry(&archive, |relative, entry| {
+        let destination = settings.server_dir.join(relative);
+        if let (false, Some(parent)) = (matches!(entry, Entry::Dir), destination.parent()) {
+            fs::create_dir_all(parent)?;
+        }
+
+        match entry {
+            Entry::Dir => fs::create_dir_all(&destination)?,
+            Entry::File(contents) => {
+                io::copy(contents, &mut File::create(&destination)?)?;
+            }
+            Entry::Link(target) => {
+                // A link or file in its place is replaced, a directory makes creating the link fail
+                if fs::symlink_metadata(&destination).is_ok_and(|metadata| !metadata.is_dir()) {
+                    fs::remove_file(&destination)?;
+                }
+                symlink(&target, &destination)?;
+            }
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+/// Without unix links, the link is restored as a file holding its target
+#[cfg(not(unix))]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    fs::write(path, target.to_string_lossy().as_bytes())
+}
+
+/// Compares every file in the archive with what is currently in the server directory
+fn plan(settings: &Settings, archive: &Path) -> Result<Vec<(PathBuf, Change)>> {
+    let mut changes = Vec::new();
+
+    for_each_entry(archive, |relative, entry| {
+        let destination = settings.server_dir.join(&relative);
+        let contents = match entry {
+            // Directories are created as needed, only files and links are worth listing
+            Entry::Dir => return Ok(()),
+            Entry::File(contents) => contents,
+            Entry::Link(target) => {
+                let change = match fs::read_link(&destination) {
+                    Ok(current) if current == target => Change::Unchanged,
+                    Ok(_) => Change::Overwrite,
+                    Err(_) if fs::symlink_metadata(&destination).is_ok() => Change::Overwrite,
+                    Err(_) => Change::Create,
+                };
+                changes.push((relative, change));
+                return Ok(());
+            }
+        };
+
+        let change = if !destination.is_file() {
+            Change::Create
+        } else {
+            let mut backed_up = Vec::new();
+            contents.read_to_end(&mut backed_up)?;
+
+            if fs::read(&destination)? == backed_up {
+                Change::Unchanged
+            } else {
+                Change::Overwrite
+            }
+        };
+
+        changes.push((relative, change));
+        Ok(())
+    })?;
+
+    Ok(changes)
+}
+
+/// Calls `visit` with the relative path of every entry in the archive
+fn for_each_entry(
+    archive: &Path,
+    mut visit: impl FnMut(PathBuf, Entry) -> Result<()>,
+) -> Result<()> {
+    let file = File::open(archive)?;
+
+    match BackupFormat::from_path(archive)? {
+        BackupFormat::TarGz => {
+            let mut tar = tar::Archive::new(GzDecoder::new(file));
+            for entry in tar.entries()? {
+                let mut entry = entry?;
+                let relative = safe_path(&entry.path()?)?;
+
+                match (entry.header().entry_type(), entry.link_name()?) {
+                    (EntryType::Directory, _) => visit(relative, Entry::Dir)?,
+                    (EntryType::Regular, _) => visit(relative, Entry::File(&mut entry))?,
+                    (EntryType::Symlink, Some(target)) => {
+                        visit(relative, Entry::Link(target.into_owned()))?
+                    }
+                    // Backups only ever contain files, directories and links
+                    (other, _) => debug!("Skipping {relative:?} of type {other:?}"),
+                }
+            }
+        }
+        BackupFormat::Zip => {
+            let mut zip = ZipArchive::new(file)?;
+            for index in 0..zip.len() {
+                let mut entry = zip.by_index(index)?;
+                let relative = safe_path(Path::new(entry.name()))?;
+
+                if entry.is_dir() {
+                    visit(relative, Entry::Dir)?;
+                } else if entry.is_symlink() {
+                    // Zips store the target of a link as its contents
+                    let mut target = String::new();
+                    entry.read_to_string(&mut target)?;
+                    visit(relative, Entry::Link(PathBuf::from(target)))?;
+                } else {
+                    visit(relative, Entry::File(&mut entry))?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes sure an archive entry cannot be written outside of the server directory
+fn safe_path(path: &Path) -> Result<PathBuf> {
+    let mut safe = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => safe.push(part),
+            Component::CurDir => {}
+            _ => return Err(BedrockUpdaterError::UnsafeBackupEntry(path.to_owned())),
+        }
+    }
+
+    Ok(safe)
+}
@@ -0,0 +1,100 @@
+use std::{future::Future, io, thread, time::Duration};
+
+use rand::Rng;
+use tracing::warn;
+
+use crate::error::{BedrockUpdaterError, Result};
+
+pub const DEFAULT_RETRY_ATTEMPTS: u32 = 3;
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How often a network operation is tried before its error is passed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total number of tries, 1 never retries
+    pub attempts: u32,
+    /// Delay before the first retry, doubled for every retry after it
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: DEFAULT_RETRY_ATTEMPTS,
+            base_delay: DEFAULT_RETRY_DELAY,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Runs the operation until it succeeds, fails in a way retrying cannot fix, or runs out of attempts
+    pub async fn run<T, F, Fut>(&self, what: &str, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Err(err) if attempt < self.attempts && is_transient(&err) => {
+                    let delay = self.delay(attempt);
+                    warn!(
+                        "{what} failed, retrying in {delay:.1?} (attempt {attempt} of {}): {err}",
+                        self.attempts
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Runs a blocking file operation until it succeeds, fails with an error it should not retry, or runs out of attempts
+    pub fn run_blocking<T>(
+        &self,
+        what: &str,
+        retry_if: impl Fn(&io::Error) -> bool,
+        mut operation: impl FnMut() -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut attempt = 1;
+        loop {
+            match operation() {
+                Err(err) if attempt < self.attempts && retry_if(&err) => {
+                    let delay = self.delay(attempt);
+                    warn!(
+                        "{what} failed, retrying in {delay:.1?} (attempt {attempt} of {}): {err}",
+                        self.attempts
+                    );
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Exponential backoff with up to half of it added at random, so many updaters don't retry in lockstep
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt - 1));
+        let jitter = delay.mul_f64(rand::thread_rng().gen_range(0.0..0.5));
+
+        delay + jitter
+    }
+}
+
+/// Connection problems, cut off downloads and server errors usually go away on their own
+/// Rate limiting is left to the update loop, which backs off for much longer
+fn is_transient(err: &BedrockUpdaterError) -> bool {
+    match err {
+        BedrockUpdaterError::RequestError(err) => match err.status() {
+            Some(status) => status.is_server_error(),
+            None => !err.is_builder(),
+        },
+        // The rest of a cut off download is resumed
+        BedrockUpdaterError::TruncatedDownload(written, total) => written < total,
+        _ => false,
+    }
+}
@@ -0,0 +1,46 @@
+use std::path::{Path, PathBuf};
+
+use tokio::process::Command;
+use tracing::debug;
+
+use crate::error::{BedrockUpdaterError, Result};
+
+/// Part of selinuxfs, which the kernel only mounts while SELinux is enabled, enforcing or not
+const SELINUX_ENFORCE: &str = "/sys/fs/selinux/enforce";
+
+/// Paths given to a single run of restorecon, so large installs stay below the argument length limit
+const PATHS_PER_RUN: usize = 256;
+
+/// Gives the paths the contexts the policy defines for where they are now, by running restorecon on them
+/// Files keep the context of wherever they were first written, so staged files would otherwise stay labeled as temporary
+/// restorecon comes with policycoreutils, and is waited for without blocking the rest of the updater
+/// Nothing is done on systems without SELinux
+pub async fn restore(paths: &[PathBuf], recursive: bool) -> Result<()> {
+    if !Path::new(SELINUX_ENFORCE).exists() {
+        debug!("SELinux is not enabled, not restoring file contexts");
+        return Ok(());
+    }
+
+    debug!("Restoring the SELinux contexts of {} paths", paths.len());
+    for paths in paths.chunks(PATHS_PER_RUN) {
+        let mut command = Command::new("restorecon");
+        if recursive {
+            command.arg("-R");
+        }
+        let output = command
+            .arg("--")
+            .args(paths)
+            .output()
+            .await
+            .map_err(BedrockUpdaterError::RestoreContexts)?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(BedrockUpdaterError::RestoreContextsFailed(
+                output.status.to_string(),
+                stderr.trim().to_owned(),
+            ));
+        }
+    }
+
+    Ok(())
+}
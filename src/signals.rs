@@ -0,0 +1,52 @@
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+/// Waits for SIGINT or SIGTERM
+/// Windows only has an equivalent for ctrl-c, so that is all that is listened to there
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+/// Cancels the token once a shutdown signal arrives
+/// The updater checks the token between phases so an in-flight install is never left half-done
+pub async fn cancel_on_shutdown(shutdown: CancellationToken) {
+    match shutdown_signal().await {
+        Ok(()) => info!("Shutdown signal received, stopping after the current step"),
+        Err(err) => error!("Unable to listen for shutdown signals: {err}"),
+    }
+
+    shutdown.cancel();
+}
+
+/// Sets the flag every time SIGHUP arrives so settings get reloaded on the next cycle
+#[cfg(unix)]
+pub async fn flag_on_reload(reload: std::sync::Arc<std::sync::atomic::AtomicBool>) {
+    use std::sync::atomic::Ordering;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            error!("Unable to listen for reload signals: {err}");
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("Reload signal received, settings will be reloaded on the next cycle");
+        reload.store(true, Ordering::Relaxed);
+    }
+}
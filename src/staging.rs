@@ -0,0 +1,243 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, SystemTime},
+};
+
+use fs_extra::dir::CopyOptions;
+use tracing::{debug, info, warn};
+
+use crate::{daemon, error::Result};
+
+/// Name of the directory under the system temp directory that staging directories are created in
+pub const TEMP_ROOT: &str = "bedrock-updater";
+
+/// Where staging directories go when they are kept out of the server directory
+pub fn temp_root() -> PathBuf {
+    std::env::temp_dir().join(TEMP_ROOT)
+}
+
+/// Staging directory names start with this, so they are never mistaken for anything else in the root
+pub const PREFIX: &str = "run-";
+
+/// A directory the server zip is extracted into, unique to one install
+/// Overlapping runs each get their own, so they never extract over each other's files
+/// The directory is removed when this is dropped, so failed installs clean up after themselves too
+#[derive(Debug)]
+pub struct StagingDir {
+    path: PathBuf,
+    removed: bool,
+}
+
+impl StagingDir {
+    /// Creates a new directory in the root, which is created as well when it doesn't exist yet
+    pub fn create(root: &Path) -> Result<Self> {
+        fs::create_dir_all(root)?;
+
+        // The start of the process goes into the name too, so the directory isn't kept for a later run with the same pid
+        let owner = match daemon::started(process::id()) {
+            Some(started) => format!("{}-{started}", process::id()),
+            None => process::id().to_string(),
+        };
+        loop {
+            let path = root.join(format!("{PREFIX}{owner}-{:08x}", rand::random::<u32>()));
+            // create_dir fails instead of reusing a directory, so a name that is taken is simply tried again
+            match fs::create_dir(&path) {
+                Ok(()) => {
+                    debug!("Staging the update in {path:?}");
+                    return Ok(Self {
+                        path,
+                        removed: false,
+                    });
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Takes over the directory of a run that was interrupted, to finish its install or remove it
+    pub fn adopt(path: PathBuf) -> Self {
+        Self {
+            path,
+            removed: false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the staged files to their final place, which takes a copy when that is on another filesystem
+    pub fn persist(mut self, destination: &Path) -> Result<()> {
+        if fs::rename(&self.path, destination).is_err() {
+            debug!(
+                "Copying {:?} to {destination:?}, since it can't be renamed there",
+                self.path
+            );
+            fs_extra::dir::copy(
+                &self.path,
+                destination,
+                &CopyOptions::new().content_only(true),
+            )?;
+            fs::remove_dir_all(&self.path)?;
+        }
+
+        self.removed = true;
+        remove_root(&self.path);
+        Ok(())
+    }
+
+    /// Removes the directory, along with the root once no other run is using it
+    /// An adopted directory can already be gone, which is fine
+    pub fn remove(mut self) -> Result<()> {
+        self.removed = true;
+        match fs::remove_dir_all(&self.path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        remove_root(&self.path);
+
+        Ok(())
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        if self.removed {
+            return;
+        }
+
+        debug!("Discarding staged files in {:?}", self.path);
+        if let Err(err) = fs::remove_dir_all(&self.path) {
+            warn!(
+                "Could not remove the staging directory {:?}: {err}",
+                self.path
+            );
+        }
+        remove_root(&self.path);
+    }
+}
+
+/// Removes staging directories that runs which crashed or were killed mid-install left behind
+pub fn remove_stale(root: &Path, stale_after: Duration, resumable: Option<&Path>) -> Result<()> {
+    for (path, age) in stale(root, stale_after, resumable)? {
+        info!(
+            "Removing the staging directory {path:?}, left behind {} ago by a run that did not finish",
+            humantime::format_duration(rounded(age))
+        );
+        fs::remove_dir_all(&path)?;
+    }
+    // Same as after an install, the root goes away once nothing is staged in it anymore
+    let _ = fs::remove_dir(root);
+
+    Ok(())
+}
+
+/// Staging directories left behind by runs that did not finish, with how long ago they were last written
+/// Directories are only stale once they are old enough and the run that created them is gone, so overlapping runs keep theirs
+/// The resumable directory belongs to the install in the journal, which a later run can still finish with it
+pub fn stale(
+    root: &Path,
+    stale_after: Duration,
+    resumable: Option<&Path>,
+) -> Result<Vec<(PathBuf, Duration)>> {
+    if !root.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut stale = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some((pid, started)) = owner(&name) else {
+            continue;
+        };
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let age = SystemTime::now()
+            .duration_since(entry.metadata()?.modified()?)
+            .unwrap_or_default();
+        if resumable == Some(path.as_path()) {
+            debug!("Keeping the staging directory {path:?}, since the interrupted install in the journal needs it");
+        } else if pid != process::id() && daemon::still_running(pid, started) {
+            debug!("Keeping the staging directory {path:?}, since the run that created it is still going");
+        } else if age < stale_after {
+            debug!(
+                "Keeping the staging directory {path:?}, since it is only {} old",
+                humantime::format_duration(rounded(age))
+            );
+        } else {
+            stale.push((path, age));
+        }
+    }
+
+    Ok(stale)
+}
+
+/// The pid and start of the run a staging directory belongs to, from names like run-1234-1a2b3c4d.5678-0badf00d
+/// Runs that can't tell when they started leave the start out, like run-1234-0badf00d
+fn owner(name: &str) -> Option<(u32, Option<&str>)> {
+    let parts: Vec<&str> = name.strip_prefix(PREFIX)?.split('-').collect();
+    let (pid, started) = match parts[..] {
+        [pid, _] => (pid, None),
+        [pid, started, _] => (pid, Some(started)),
+        _ => return None,
+    };
+
+    Some((pid.parse().ok()?, started))
+}
+
+fn rounded(age: Duration) -> Duration {
+    Duration::from_secs(age.as_secs())
+}
+
+/// Removes the root of a staging directory once no other run is using it
+fn remove_root(path: &Path) {
+    if let Some(root) = path.parent() {
+        // Fails while another run still has its own staging directory in there, which is fine
+        let _ = fs::remove_dir(root);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owners_come_from_the_name() {
+        assert_eq!(owner("run-1234-0badf00d"), Some((1234, None)));
+        assert_eq!(
+            owner("run-1234-1a2b3c4d.5678-0badf00d"),
+            Some((1234, Some("1a2b3c4d.5678")))
+        );
+        assert_eq!(owner("run-x-0badf00d"), None);
+        assert_eq!(owner("extracted"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn directories_of_reused_pids_are_stale() {
+        let root =
+            std::env::temp_dir().join(format!("bedrock-updater-staging-test-{}", process::id()));
+        let parent = std::os::unix::process::parent_id();
+        let running = root.join(format!(
+            "{PREFIX}{parent}-{}-00000000",
+            daemon::started(parent).unwrap()
+        ));
+        let reused = root.join(format!("{PREFIX}{parent}-00000000.0-00000000"));
+        fs::create_dir_all(&running).unwrap();
+        fs::create_dir_all(&reused).unwrap();
+
+        let stale = stale(&root, Duration::ZERO, None);
+        fs::remove_dir_all(&root).unwrap();
+
+        let stale: Vec<PathBuf> = stale.unwrap().into_iter().map(|(path, _)| path).collect();
+        assert_eq!(stale, vec![reused]);
+    }
+}
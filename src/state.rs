@@ -0,0 +1,191 @@
+use std::{collections::BTreeSet, fs, io, path::Path, time::SystemTime};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    atomic,
+    config::Settings,
+    error::{BedrockUpdaterError, Result},
+    prompt,
+    source::Channel,
+    version::BedrockVersion,
+};
+
+/// How much of a damaged file is shown in errors
+const FOUND_PREVIEW_CHARS: usize = 60;
+
+/// Everything the updater knows about the installed server
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct State {
+    pub current_version: Option<BedrockVersion>,
+    #[serde(with = "humantime_serde")]
+    pub installed_at: Option<SystemTime>,
+    /// Where the installed zip came from, either a url or an archive path
+    pub source: Option<String>,
+    /// SHA-256 of the installed zip
+    pub checksum: Option<String>,
+    pub channel: Option<Channel>,
+    /// Version a specific install or rollback put in place, which scheduled updates leave alone
+    pub pinned: Option<BedrockVersion>,
+    pub pending: Option<PendingUpdate>,
+    /// Installed version whose worlds are checked again once it is seen running
+    pub worlds_unchecked: Option<BedrockVersion>,
+    /// Versions that failed the smoke test and were rolled back, updates skip them like the skip list
+    pub broken: BTreeSet<BedrockVersion>,
+}
+
+/// An update the update policy held back, waiting for the approve command
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingUpdate {
+    pub version: BedrockVersion,
+    pub download_link: String,
+    #[serde(with = "humantime_serde")]
+    pub discovered: SystemTime,
+    pub approved: bool,
+}
+
+impl State {
+    /// Reads the state file, migrating the plain text version file of older versions of the updater
+    /// The state file itself can also still hold a bare version when it was pointed at the old version file
+    pub fn load(settings: &Settings) -> Result<Self> {
+        match fs::read_to_string(&settings.state_path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(state) => Ok(state),
+                Err(err) => match Self::from_plain_text(&contents) {
+                    Some(state) => {
+                        info!(
+                            "Migrating plain text version in {:?} to a state file",
+                            settings.state_path
+                        );
+                        state.save(&settings.state_path)?;
+                        Ok(state)
+                    }
+                    None => Err(damaged(&settings.state_path, &contents, &err.to_string())),
+                },
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Self::migrate(settings),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Moves the version from the old version file into a new state file
+    fn migrate(settings: &Settings) -> Result<Self> {
+        let contents = match fs::read_to_string(&settings.version_path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let Some(state) = Self::from_plain_text(&contents) else {
+            return Err(damaged(
+                &settings.version_path,
+                &contents,
+                "it does not hold a version",
+            ));
+        };
+
+        info!(
+            "Migrating version file {:?} to state file {:?}",
+            settings.version_path, settings.state_path
+        );
+        state.save(&settings.state_path)?;
+        fs::remove_file(&settings.version_path)?;
+
+        Ok(state)
+    }
+
+    /// Editors like to add byte order marks and trailing newlines, neither of which is worth refusing to run over
+    fn from_plain_text(contents: &str) -> Option<Self> {
+        let version = contents.trim_start_matches('\u{feff}').parse().ok()?;
+
+        Some(Self {
+            current_version: Some(version),
+            ..Self::default()
+        })
+    }
+
+    pub fn save(&self, state_path: &Path) -> Result<()> {
+        atomic::write(state_path, &serde_json::to_vec_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    /// Rewrites a state or version file that cannot be read, keeping only the version found in it
+    /// Nothing is changed without confirmation, and a file that is fine is left alone
+    pub fn repair(settings: &Settings) -> Result<()> {
+        let path = match Self::load(settings) {
+            Ok(_) => {
+                info!("{:?} is fine, nothing to repair", settings.state_path);
+                return Ok(());
+            }
+            Err(BedrockUpdaterError::DamagedState(path, _)) => path,
+            Err(err) => return Err(err),
+        };
+
+        let contents = fs::read_to_string(&path)?;
+        let version = salvage(&contents).map(|(version, _, _)| version);
+        let question = match version {
+            Some(version) => format!("Rewrite {path:?} as a state file with version {version}?"),
+            None => format!(
+                "Replace {path:?} with an empty state file? The version is detected again or has to be set with --set-first-version"
+            ),
+        };
+        if !prompt::ask(&question)? {
+            return Err(BedrockUpdaterError::RepairDeclined);
+        }
+
+        let state = Self {
+            current_version: version,
+            ..Self::default()
+        };
+        state.save(&settings.state_path)?;
+        if path != settings.state_path {
+            fs::remove_file(&path)?;
+        }
+        info!("Repaired {path:?}");
+
+        Ok(())
+    }
+
+    /// Loads the state, applies the change and writes it back
+    pub fn update(settings: &Settings, change: impl FnOnce(&mut State)) -> Result<()> {
+        let mut state = Self::load(settings)?;
+        change(&mut state);
+        state.save(&settings.state_path)
+    }
+}
+
+/// Describes what was found in a file that could not be read, and where a version is hiding in it
+fn damaged(path: &Path, contents: &str, problem: &str) -> BedrockUpdaterError {
+    let mut found: String = contents.chars().take(FOUND_PREVIEW_CHARS).collect();
+    if found.len() < contents.len() {
+        found.push_str("...");
+    }
+
+    let mut description = format!("{problem}, found {found:?}");
+    if let Some((version, line, column)) = salvage(contents) {
+        description.push_str(&format!(
+            " with version {version} at line {line} column {column}"
+        ));
+    }
+
+    BedrockUpdaterError::DamagedState(path.to_owned(), description)
+}
+
+/// Finds the first version in the text, along with its line and column counting from 1
+fn salvage(contents: &str) -> Option<(BedrockVersion, usize, usize)> {
+    let pattern = Regex::new(r"\d+(\.\d+){2,4}").ok()?;
+
+    contents.lines().enumerate().find_map(|(index, line)| {
+        let found = pattern.find(line)?;
+        let version = found.as_str().parse().ok()?;
+        Some((
+            version,
+            index + 1,
+            line[..found.start()].chars().count() + 1,
+        ))
+    })
+}
@@ -0,0 +1,138 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{atomic, error::Result, state::PendingUpdate, version::BedrockVersion};
+
+/// Status file relative to the server directory
+const STATUS_FILE: &str = ".updater-status.json";
+
+/// What the updater last did, persisted so the status command can report it from another process
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Status {
+    pub latest_version: Option<String>,
+    #[serde(with = "humantime_serde")]
+    pub last_check: Option<SystemTime>,
+    #[serde(with = "humantime_serde")]
+    pub last_success: Option<SystemTime>,
+    #[serde(with = "humantime_serde")]
+    pub last_update: Option<SystemTime>,
+    pub last_error: Option<LastError>,
+    /// Checks in a row that were rate limited, reset by any other outcome
+    pub consecutive_throttles: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LastError {
+    #[serde(with = "humantime_serde")]
+    pub time: SystemTime,
+    pub message: String,
+}
+
+impl Status {
+    fn path(server_dir: &Path) -> PathBuf {
+        server_dir.join(STATUS_FILE)
+    }
+
+    /// Reads the status file, a missing file just means the updater has not run yet
+    pub fn load(server_dir: &Path) -> Result<Self> {
+        match fs::read(Self::path(server_dir)) {
+            Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, server_dir: &Path) -> Result<()> {
+        atomic::write(&Self::path(server_dir), &serde_json::to_vec_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    /// Loads the status, applies the change and writes it back
+    pub fn update(server_dir: &Path, change: impl FnOnce(&mut Status)) -> Result<()> {
+        let mut status = Self::load(server_dir)?;
+        change(&mut status);
+        status.save(server_dir)
+    }
+}
+
+/// Everything the status command reports
+#[derive(Serialize, Debug)]
+pub struct StatusReport {
+    pub current_version: Option<BedrockVersion>,
+    pub daemon_pid: Option<u32>,
+    #[serde(flatten)]
+    pub status: Status,
+    pub pending: Option<PendingUpdate>,
+}
+
+impl StatusReport {
+    pub fn print(&self, json: bool) -> Result<()> {
+        if json {
+            println!("{}", serde_json::to_string_pretty(self)?);
+            return Ok(());
+        }
+
+        let time = |time: Option<SystemTime>| {
+            time.map_or_else(
+                || String::from("never"),
+                |time| humantime::format_rfc3339_seconds(time).to_string(),
+            )
+        };
+
+        println!(
+            "Current version:       {}",
+            self.current_version
+                .map_or_else(|| String::from("unknown"), |version| version.to_string())
+        );
+        println!(
+            "Latest known version:  {}",
+            self.status.latest_version.as_deref().unwrap_or("unknown")
+        );
+        println!("Last check:            {}", time(self.status.last_check));
+        println!("Last successful check: {}", time(self.status.last_success));
+        println!("Last update:           {}", time(self.status.last_update));
+
+        match &self.status.last_error {
+            Some(error) => println!(
+                "Last error:            {} ({})",
+                error.message,
+                time(Some(error.time))
+            ),
+            None => println!("Last error:            none"),
+        }
+
+        if let Some(pending) = &self.pending {
+            let state = if pending.approved {
+                "approved"
+            } else {
+                "waiting for approval"
+            };
+            println!(
+                "Pending update:        {} ({state} since {})",
+                pending.version,
+                time(Some(pending.discovered))
+            );
+        }
+
+        if self.status.consecutive_throttles > 0 {
+            println!(
+                "Rate limited:          last {} checks",
+                self.status.consecutive_throttles
+            );
+        }
+
+        match self.daemon_pid {
+            Some(pid) => println!("Daemon:                running (pid {pid})"),
+            None => println!("Daemon:                not running"),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{error::Result, glob::Glob};
+
+/// What an install does with a file that already exists where it would be copied to
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Leave the existing file alone, it is only copied when it does not exist yet
+    Keep,
+    /// Replace it with the new version's file
+    #[default]
+    Overwrite,
+    /// Back it up to the backup directory, then replace it
+    BackupThenOverwrite,
+    /// Keep the existing settings and add the ones the new file introduces
+    /// Only key=value files like server.properties can be merged, anything else is kept
+    Merge,
+}
+
+/// The strategy of every path, as patterns relative to the server directory
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Strategies {
+    rules: Vec<(Glob, Strategy)>,
+}
+
+impl Strategies {
+    /// Preserved patterns are kept, unless the strategies give the same pattern another strategy
+    pub fn new(
+        preserved: &BTreeSet<String>,
+        strategies: &BTreeMap<String, Strategy>,
+        ignore_case: bool,
+    ) -> Result<Self> {
+        let kept = preserved
+            .iter()
+            .filter(|pattern| !strategies.contains_key(*pattern))
+            .map(|pattern| (pattern, Strategy::Keep));
+
+        let rules = strategies
+            .iter()
+            .map(|(pattern, strategy)| (pattern, *strategy))
+            .chain(kept)
+            .map(|(pattern, strategy)| Ok((Glob::new(pattern, ignore_case)?, strategy)))
+            .collect::<Result<_>>()?;
+
+        Ok(Self { rules })
+    }
+
+    /// The most specific matching rule wins, which is the one with the longest pattern
+    /// Paths no rule matches are overwritten
+    pub fn for_path(&self, relative: &Path) -> Strategy {
+        self.rules
+            .iter()
+            .filter(|(glob, _)| glob.matches(relative))
+            .max_by_key(|(glob, _)| glob.pattern().len())
+            .map(|(_, strategy)| *strategy)
+            .unwrap_or_default()
+    }
+}
+
+/// Permissions installed files are given by path pattern, instead of whatever the zip and the umask left them with
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FileModes {
+    rules: Vec<(Glob, u32)>,
+}
+
+impl FileModes {
+    pub fn new(modes: &BTreeMap<String, u32>, ignore_case: bool) -> Result<Self> {
+        let rules = modes
+            .iter()
+            .map(|(pattern, mode)| Ok((Glob::new(pattern, ignore_case)?, *mode)))
+            .collect::<Result<_>>()?;
+
+        Ok(Self { rules })
+    }
+
+    /// Like strategies, the longest matching pattern wins
+    /// Files no pattern matches keep the mode they were installed with
+    pub fn for_path(&self, relative: &Path) -> Option<u32> {
+        self.rules
+            .iter()
+            .filter(|(glob, _)| glob.matches(relative))
+            .max_by_key(|(glob, _)| glob.pattern().len())
+            .map(|(_, mode)| *mode)
+    }
+}
+
+/// Directories the server keeps its own data in, whose contents installs never write to or delete once they exist
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ProtectedDirs {
+    globs: Vec<Glob>,
+}
+
+impl ProtectedDirs {
+    pub fn new(patterns: &[String], ignore_case: bool) -> Result<Self> {
+        Ok(Self {
+            globs: patterns
+                .iter()
+                .map(|pattern| Glob::new(pattern, ignore_case))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    fn is_protected(&self, relative: &Path) -> bool {
+        self.globs.iter().any(|glob| glob.is_match(relative))
+    }
+
+    /// Whether the path is a protected directory or inside of one, whether it exists or not
+    pub fn covers(&self, relative: &Path) -> bool {
+        relative
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .any(|ancestor| self.is_protected(ancestor))
+    }
+
+    /// The outermost protected directory the path is or is in, as long as it already exists in the directory
+    pub fn containing<'p>(&self, dir: &Path, relative: &'p Path) -> Option<&'p Path> {
+        relative
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .filter(|ancestor| self.is_protected(ancestor) && dir.join(ancestor).is_dir())
+            .last()
+    }
+
+    /// Finds the protected directories below a directory of the root, without looking inside them
+    pub fn find(&self, root: &Path, relative: &Path) -> Result<Vec<PathBuf>> {
+        let mut found = Vec::new();
+        for entry in fs::read_dir(root.join(relative))? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            let child = relative.join(entry.file_name());
+            if self.is_protected(&child) {
+                found.push(child);
+            } else {
+                found.extend(self.find(root, &child)?);
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+/// Whether the file is one merging understands
+pub fn can_merge(relative: &str) -> bool {
+    relative.to_ascii_lowercase().ends_with(".properties")
+}
+
+/// Whether preserve, strategy and protected directory patterns care about the case of paths
+#[derive(ValueEnum, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PathCase {
+    /// Ignore case when the filesystem of the server directory does
+    #[default]
+    Auto,
+    /// Paths only match patterns of the same case
+    Sensitive,
+    /// Paths match patterns that only differ in case
+    Insensitive,
+}
+
+impl PathCase {
+    pub fn ignores_case(self, server_dir: &Path) -> bool {
+        match self {
+            PathCase::Auto => filesystem_ignores_case(server_dir),
+            PathCase::Sensitive => false,
+            PathCase::Insensitive => true,
+        }
+    }
+}
+
+/// Looks up an entry of the directory by its name in another case, which only finds it when the filesystem ignores case
+/// Without an entry to look up, the usual behavior of the platform is assumed
+fn filesystem_ignores_case(dir: &Path) -> bool {
+    let entries = fs::read_dir(dir).into_iter().flatten().flatten();
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let swapped: String = name
+            .chars()
+            .map(|char| match char.is_ascii_lowercase() {
+                true => char.to_ascii_uppercase(),
+                false => char.to_ascii_lowercase(),
+            })
+            .collect();
+        if swapped == name {
+            continue;
+        }
+
+        return match (
+            fs::symlink_metadata(entry.path()),
+            fs::symlink_metadata(dir.join(&swapped)),
+        ) {
+            (Ok(original), Ok(swapped)) => same_file(&original, &swapped),
+            _ => false,
+        };
+    }
+
+    cfg!(any(windows, target_os = "macos"))
+}
+
+/// Two names for the same file, rather than two files whose names only differ in case
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    (a.dev(), a.ino()) == (b.dev(), b.ino())
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &fs::Metadata, _b: &fs::Metadata) -> bool {
+    true
+}
@@ -0,0 +1,413 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::{
+    atomic,
+    config::Settings,
+    error::{BedrockUpdaterError, Result},
+    manifest::{self, Manifest},
+    prompt,
+    state::State,
+    version::BedrockVersion,
+};
+
+/// Update backups are directories in the backup directory named after both versions, like update-1.21.44.1-to-1.21.50.7
+const PREFIX: &str = "update-";
+
+/// Which update the backup belongs to, stored inside it
+const RECORD_FILE: &str = "update.json";
+
+/// The replaced files are kept below this directory of the backup, by their path in the server directory
+const REPLACED_DIR: &str = "replaced";
+
+/// Every file the update created has an empty marker below this directory, by its path in the server directory
+const CREATED_DIR: &str = "created";
+
+/// The manifest of the install the update replaced
+const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct UpdateRecord {
+    from: BedrockVersion,
+    to: BedrockVersion,
+    #[serde(with = "humantime_serde")]
+    started: SystemTime,
+}
+
+/// The old contents of every file an in-place update replaced, so the update can be undone
+/// Only the backup of the last update is kept, starting a new one removes the one before
+pub struct UpdateBackup {
+    dir: PathBuf,
+    replaced: usize,
+}
+
+impl UpdateBackup {
+    /// Starts the backup of an update, or continues it when an interrupted install is resumed
+    pub fn start(
+        settings: &Settings,
+        from: &BedrockVersion,
+        to: &BedrockVersion,
+        resume: bool,
+    ) -> Result<Self> {
+        let dir = settings.backup_dir.join(format!("{PREFIX}{from}-to-{to}"));
+
+        for (other, record) in list(&settings.backup_dir)? {
+            if !(resume && other == dir && record.is_some()) {
+                debug!("Removing the backup of an earlier update {other:?}");
+                fs::remove_dir_all(&other)?;
+            }
+        }
+
+        if !dir.exists() {
+            // It is put together under another name first, so a backup never goes without its record
+            let temporary = settings
+                .backup_dir
+                .join(format!(".{PREFIX}{from}-to-{to}.tmp"));
+            if temporary.exists() {
+                fs::remove_dir_all(&temporary)?;
+            }
+            fs::create_dir_all(&temporary)?;
+            let manifest = Manifest::path(&settings.state_path);
+            if manifest.exists() {
+                fs::copy(&manifest, temporary.join(MANIFEST_FILE))?;
+            }
+
+            let record = UpdateRecord {
+                from: *from,
+                to: *to,
+                started: SystemTime::now(),
+            };
+            atomic::write(
+                &temporary.join(RECORD_FILE),
+                &serde_json::to_vec_pretty(&record)?,
+            )?;
+            fs::rename(&temporary, &dir)?;
+        }
+
+        Ok(Self { dir, replaced: 0 })
+    }
+
+    /// Keeps the file that is about to be written to, or remembers that it did not exist yet
+    /// Files already in the backup were saved before an interruption, so they still hold the old contents
+    pub fn save(&mut self, relative: &str, destination: &Path) -> Result<()> {
+        let replaced = self.dir.join(REPLACED_DIR).join(relative);
+        let created = self.dir.join(CREATED_DIR).join(relative);
+        if replaced.exists() || created.exists() {
+            return Ok(());
+        }
+
+        let (path, exists) = match destination.exists() {
+            true => (replaced, true),
+            false => (created, false),
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        match exists {
+            true => {
+                fs::copy(destination, &path)?;
+                self.replaced += 1;
+            }
+            false => fs::write(&path, [])?,
+        }
+
+        Ok(())
+    }
+
+    /// Puts the server directory back the way it was before the update started writing to it
+    /// The backup is removed too, since there is nothing left to undo
+    pub fn roll_back(self, server_dir: &Path) -> Result<()> {
+        let replaced = files(&self.dir.join(REPLACED_DIR))?;
+        let created = files(&self.dir.join(CREATED_DIR))?;
+        restore(&self.dir, server_dir, &replaced, &created)?;
+        info!(
+            "Rolled back {} replaced and {} created files",
+            replaced.len(),
+            created.len()
+        );
+
+        fs::remove_dir_all(&self.dir)?;
+
+        Ok(())
+    }
+
+    pub fn finish(self) {
+        if self.replaced > 0 {
+            info!(
+                "Kept the {} replaced files in {:?}, undo-last-update puts them back",
+                self.replaced, self.dir
+            );
+        }
+    }
+}
+
+/// Lists the update backups in the backup directory along with the update they belong to
+/// Directories without a record are listed so they can be removed, but there is nothing in them to undo
+fn list(backup_dir: &Path) -> Result<Vec<(PathBuf, Option<UpdateRecord>)>> {
+    if !backup_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(backup_dir)? {
+        let path = entry?.path();
+        let is_update = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(PREFIX));
+        if !is_update || !path.is_dir() {
+            continue;
+        }
+
+        let record = match fs::read(path.join(RECORD_FILE)) {
+            Ok(contents) => Some(serde_json::from_slice(&contents)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        backups.push((path, record));
+    }
+
+    Ok(backups)
+}
+
+/// Lists the files below one of the directories of the backup, by their path in the server directory
+fn files(dir: &Path) -> Result<Vec<String>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    Ok(manifest::files_below(dir)?
+        .iter()
+        .map(|file| manifest::manifest_key(file.strip_prefix(dir).unwrap_or(file)))
+        .collect())
+}
+
+/// Removes the created files and copies the replaced ones back over the server directory
+fn restore(dir: &Path, server_dir: &Path, replaced: &[String], created: &[String]) -> Result<()> {
+    for relative in created {
+        let path = server_dir.join(relative);
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+
+        // Directories the update created go too, once nothing is left in them
+        for parent in path
+            .ancestors()
+            .skip(1)
+            .take_while(|parent| *parent != server_dir)
+        {
+            if fs::remove_dir(parent).is_err() {
+                break;
+            }
+        }
+    }
+    for relative in replaced {
+        let destination = server_dir.join(relative);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(dir.join(REPLACED_DIR).join(relative), &destination)?;
+    }
+
+    Ok(())
+}
+
+/// The backup of the last update, which has to be of the update to the installed version
+fn last(settings: &Settings) -> Result<(PathBuf, UpdateRecord)> {
+    let (dir, record) = list(&settings.backup_dir)?
+        .into_iter()
+        .filter_map(|(dir, record)| Some((dir, record?)))
+        .max_by_key(|(_, record)| record.started)
+        .ok_or(BedrockUpdaterError::NoUpdateBackup)?;
+
+    let current = State::load(settings)?.current_version;
+    if current != Some(record.to) {
+        let installed =
+            current.map_or_else(|| String::from("no version"), |version| version.to_string());
+        return Err(BedrockUpdaterError::UndoVersionMismatch(
+            record.to.to_string(),
+            installed,
+        ));
+    }
+
+    Ok((dir, record))
+}
+
+/// Puts back the files the last in-place update replaced and removes the ones it created
+/// The server goes back to the old version and is pinned to it, like after a rollback
+pub fn undo_last(settings: &Settings, dry_run: bool, assume_yes: bool) -> Result<()> {
+    let (dir, record) = last(settings)?;
+    let replaced = files(&dir.join(REPLACED_DIR))?;
+    let created = files(&dir.join(CREATED_DIR))?;
+    for relative in &replaced {
+        println!("  restore   {relative}");
+    }
+    for relative in &created {
+        println!("  remove    {relative}");
+    }
+    println!(
+        "Undoing the update from {} to {}: {} files restored, {} removed",
+        record.from,
+        record.to,
+        replaced.len(),
+        created.len()
+    );
+
+    if dry_run {
+        return Ok(());
+    }
+    if !assume_yes && !prompt::ask(&format!("Go back to {}?", record.from))? {
+        info!("Undo cancelled");
+        return Ok(());
+    }
+
+    put_back(settings, &dir, &record, &replaced, &created, true)?;
+    info!(
+        "Went back to {}, scheduled updates resume after the next install",
+        record.from
+    );
+
+    Ok(())
+}
+
+/// Undoes the last in-place update without listing anything or asking first, for updates that turned out broken
+/// The server is not pinned to the old version, so scheduled updates carry on
+pub fn roll_back_last(settings: &Settings) -> Result<()> {
+    let (dir, record) = last(settings)?;
+    let replaced = files(&dir.join(REPLACED_DIR))?;
+    let created = files(&dir.join(CREATED_DIR))?;
+
+    put_back(settings, &dir, &record, &replaced, &created, false)?;
+    info!(
+        "Went back to {}, {} files restored and {} removed",
+        record.from,
+        replaced.len(),
+        created.len()
+    );
+
+    Ok(())
+}
+
+/// Restores the files and manifest of the version before the update and records it as installed, removing the backup
+fn put_back(
+    settings: &Settings,
+    dir: &Path,
+    record: &UpdateRecord,
+    replaced: &[String],
+    created: &[String],
+    pin: bool,
+) -> Result<()> {
+    restore(dir, &settings.server_dir, replaced, created)?;
+
+    match Manifest::read(&dir.join(MANIFEST_FILE))? {
+        Some(manifest) => manifest.save(&settings.state_path)?,
+        None => {
+            let manifest = Manifest::path(&settings.state_path);
+            if manifest.exists() {
+                fs::remove_file(manifest)?;
+            }
+        }
+    }
+    State::update(settings, |state| {
+        state.current_version = Some(record.from);
+        state.installed_at = Some(SystemTime::now());
+        // The files did not come from a zip this time
+        state.source = None;
+        state.checksum = None;
+        if pin {
+            state.pinned = Some(record.from);
+        }
+        state.pending = None;
+    })?;
+
+    fs::remove_dir_all(dir)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{args::Args, config::Config};
+
+    fn settings(test: &str) -> Settings {
+        let server_dir = std::env::temp_dir().join(format!(
+            "bedrock-updater-undo-{test}-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&server_dir).unwrap();
+        let args = Args {
+            server_dir: Some(server_dir),
+            ..Default::default()
+        };
+
+        Settings::merge(&args, Config::default()).unwrap()
+    }
+
+    fn versions() -> (BedrockVersion, BedrockVersion) {
+        ("1.21.44.1".parse().unwrap(), "1.21.50.7".parse().unwrap())
+    }
+
+    #[test]
+    fn rolling_back_restores_replaced_files_and_removes_created_ones() {
+        let settings = settings("roll-back");
+        let (from, to) = versions();
+        let server_dir = &settings.server_dir;
+        fs::write(server_dir.join("bedrock_server"), "old").unwrap();
+
+        let mut backup = UpdateBackup::start(&settings, &from, &to, false).unwrap();
+        backup
+            .save("bedrock_server", &server_dir.join("bedrock_server"))
+            .unwrap();
+        fs::write(server_dir.join("bedrock_server"), "new").unwrap();
+        backup
+            .save(
+                "behavior_packs/new/manifest.json",
+                &server_dir.join("behavior_packs/new/manifest.json"),
+            )
+            .unwrap();
+        fs::create_dir_all(server_dir.join("behavior_packs/new")).unwrap();
+        fs::write(server_dir.join("behavior_packs/new/manifest.json"), "{}").unwrap();
+        backup.roll_back(server_dir).unwrap();
+
+        let binary = fs::read_to_string(server_dir.join("bedrock_server")).unwrap();
+        let created = server_dir.join("behavior_packs").exists();
+        let backups = list(&settings.backup_dir).unwrap().len();
+        fs::remove_dir_all(server_dir).unwrap();
+
+        assert_eq!(binary, "old");
+        assert!(!created);
+        assert_eq!(backups, 0);
+    }
+
+    #[test]
+    fn backups_without_a_record_are_replaced() {
+        let settings = settings("no-record");
+        let (from, to) = versions();
+        let dir = settings.backup_dir.join(format!("{PREFIX}{from}-to-{to}"));
+        fs::create_dir_all(dir.join(REPLACED_DIR)).unwrap();
+        let other = settings
+            .backup_dir
+            .join(format!("{PREFIX}1.0.0.0-to-{from}"));
+        fs::create_dir_all(&other).unwrap();
+
+        let missing = last(&settings);
+        UpdateBackup::start(&settings, &from, &to, true).unwrap();
+        let record: UpdateRecord =
+            serde_json::from_slice(&fs::read(dir.join(RECORD_FILE)).unwrap()).unwrap();
+        let emptied = !dir.join(REPLACED_DIR).exists();
+        let removed = !other.exists();
+        fs::remove_dir_all(&settings.server_dir).unwrap();
+
+        assert!(matches!(missing, Err(BedrockUpdaterError::NoUpdateBackup)));
+        assert_eq!((record.from, record.to), (from, to));
+        assert!(emptied && removed);
+    }
+}
@@ -1,60 +1,355 @@
-use std::{collections::HashSet, fs, io::Cursor, path::Path};
+use std::{
+    collections::{BTreeSet, HashMap},
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use bytes::Bytes;
+use clap::ValueEnum;
 use fs_extra::dir::CopyOptions;
-use regex::Regex;
-use reqwest::{
-    header::{ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE, CONNECTION},
-    Client, RequestBuilder, Url,
-};
-use scraper::{Html, Selector};
-use tracing::{debug, info, info_span, trace};
-use version_compare::Version;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, info_span, trace, warn};
 
+use crate::archive::{self, ArchivedVersion};
+use crate::backup;
+use crate::compat;
+use crate::config::{Settings, SourceSettings};
+use crate::daemon;
+use crate::deferred::{self, Replacement};
+use crate::detect;
+use crate::diff;
+use crate::disk;
+use crate::download;
 use crate::error::BedrockUpdaterError;
+use crate::extract;
+use crate::history::{self, Outcome, UpdateRecord};
+use crate::journal::{Journal, Step};
+use crate::layout::{self, InstallLayout, RetainedVersion};
+use crate::manifest::{self, Manifest};
+use crate::owner::{self, Owner};
+use crate::ping;
+use crate::players::PlayerList;
+use crate::progress::Progress;
+use crate::prompt;
+use crate::properties::{self, PROPERTIES_FILE};
+use crate::quarantine::{self, QUARANTINE_DIR};
+use crate::retry::RetryPolicy;
+use crate::selinux;
+use crate::source::{self, Channel, Release};
+use crate::staging::{self, StagingDir};
+use crate::state::{PendingUpdate, State};
+use crate::status::{LastError, Status};
+use crate::strategy::{self, Strategy};
+use crate::undo::{self, UpdateBackup};
+use crate::version::BedrockVersion;
+use crate::world;
 
 use crate::error::Result;
 
-const BEDROCK_SERVER_PAGE: &str = "https://www.minecraft.net/en-us/download/server/bedrock";
+/// Server zips are downloaded to this file relative to the server directory, and removed once installed
+pub const DOWNLOAD_FILE: &str = ".updater-download.zip";
 
-macro_rules! data_platform {
-    () => {
-        "serverBedrockLinux"
-    };
+/// Records when each version was first seen as the latest version, relative to the server directory
+const FIRST_SEEN_FILE: &str = ".updater-first-seen";
+
+/// Finds the staged files that differ from what the last install laid down
+struct Delta<'a> {
+    staged: &'a Path,
+    previous: Option<&'a Manifest>,
+    manifest: &'a Manifest,
+    existing: &'a Existing,
+    journal: &'a Journal,
+    backup: &'a mut UpdateBackup,
+    owner: Option<Owner>,
+    /// Files to copy once every destination directory exists and the old files are backed up
+    files: Vec<FileCopy>,
+    skipped: u64,
 }
 
-macro_rules! selector {
-    () => {
-        concat!("a.downloadlink[data-platform=", data_platform!(), "]")
-    };
+/// A staged file and where it is copied to, with its key in the manifest
+struct FileCopy {
+    source: PathBuf,
+    destination: PathBuf,
+    key: String,
+    size: u64,
+}
+
+/// What happens to the files that already exist where the update copies to
+#[derive(Default)]
+struct Existing {
+    /// Left alone because of their strategy
+    kept: BTreeSet<String>,
+    /// Merged into instead of overwritten
+    merged: BTreeSet<String>,
+    /// Left alone because they were changed since they were laid down
+    modified: BTreeSet<String>,
+    /// Directories nothing is written into
+    protected: BTreeSet<String>,
+}
+
+impl Delta<'_> {
+    fn plan(&mut self, source: &Path, destination: &Path) -> Result<()> {
+        let key = manifest::manifest_key(source.strip_prefix(self.staged).unwrap_or(source));
+        if source.is_dir() {
+            if self.existing.protected.contains(&key) {
+                debug!("Leaving the protected {destination:?} alone");
+                return Ok(());
+            }
+
+            if !destination.exists() {
+                fs::create_dir_all(destination)?;
+                if let Some(owner) = self.owner {
+                    owner.apply(destination)?;
+                }
+            }
+            for entry in fs::read_dir(source)? {
+                let name = entry?.file_name();
+                self.plan(&source.join(&name), &destination.join(&name))?;
+            }
+            return Ok(());
+        }
+
+        if self.existing.kept.contains(&key) || self.existing.modified.contains(&key) {
+            debug!("Keeping {destination:?}");
+            return Ok(());
+        }
+        if self.existing.merged.contains(&key) {
+            self.backup.save(&key, destination)?;
+            let added = properties::merge(destination, source, destination)?;
+            if let Some(owner) = self.owner {
+                owner.apply(destination)?;
+            }
+            if !added.is_empty() {
+                let listed: Vec<&str> = added.keys().map(String::as_str).collect();
+                info!(
+                    "Added the new settings {} to {destination:?}",
+                    listed.join(", ")
+                );
+            }
+            return self.journal.record(&Step::Copied { path: key });
+        }
+
+        if self.unchanged(&key, destination)? {
+            trace!("Skipping unchanged {destination:?}");
+            // It is still one of the install's files, so it belongs to the owner too
+            if let Some(owner) = self.owner {
+                owner.apply(destination)?;
+            }
+            self.skipped += 1;
+            return Ok(());
+        }
+
+        self.backup.save(&key, destination)?;
+        self.files.push(FileCopy {
+            source: source.to_owned(),
+            destination: destination.to_owned(),
+            size: self.manifest.files.get(&key).map_or(0, |entry| entry.size),
+            key,
+        });
+
+        Ok(())
+    }
+
+    /// Whether both releases ship the same contents and the destination still looks like what was laid down
+    fn unchanged(&self, key: &str, destination: &Path) -> Result<bool> {
+        let (Some(previous), Some(new)) = (
+            self.previous.and_then(|previous| previous.files.get(key)),
+            self.manifest.files.get(key),
+        ) else {
+            return Ok(false);
+        };
+        if previous.sha256 != new.sha256 || !destination.is_file() {
+            return Ok(false);
+        }
+
+        // A different modification time means the file was touched since it was laid down
+        let metadata = fs::metadata(destination)?;
+        Ok(metadata.len() == new.size
+            && previous
+                .modified
+                .is_none_or(|modified| metadata.modified().ok() == Some(modified)))
+    }
 }
 
-macro_rules! hashset {
-    ($($val:expr),* ) => {{
-        let mut set = HashSet::new();
-        $(
-            set.insert($val);
-        )*
-        set
-    }};
+/// How long a locked file is waited for, the server or a virus scanner usually lets go within seconds
+const LOCKED_FILE_RETRY: RetryPolicy = RetryPolicy {
+    attempts: 5,
+    base_delay: Duration::from_millis(500),
+};
+
+/// What happened to a staged file
+enum Placed {
+    /// It was written over the destination, with this many bytes
+    Written(u64),
+    /// The destination stayed locked, the file waits next to it to replace it later
+    Deferred(Replacement),
 }
 
-/// Defines some common headers used for the requests to the bedrock server download page
-trait CommonHeaders {
-    fn add_common_headers(self) -> Self;
+/// Puts a staged file where it goes and gives it its owner
+/// Without an owner to give it, it keeps the owner and mode of the file it replaces
+fn place(
+    file: &FileCopy,
+    link: bool,
+    owner: Option<Owner>,
+    defer_locked: bool,
+) -> io::Result<Placed> {
+    let original = fs::symlink_metadata(&file.destination).ok();
+    let what = format!("Writing {:?}", file.destination);
+    let (placed, path) = match LOCKED_FILE_RETRY
+        .run_blocking(&what, deferred::is_locked, || write_file(file, link))
+    {
+        Err(err) if defer_locked && deferred::is_locked(&err) => {
+            let pending = deferred::pending_path(&file.destination);
+            fs::copy(&file.source, &pending)?;
+            let replacement = Replacement {
+                destination: file.destination.clone(),
+                pending: pending.clone(),
+            };
+            (Placed::Deferred(replacement), pending)
+        }
+        written => {
+            let written = written?;
+            // A replacement left from an earlier install would otherwise put its older file back over this one later
+            deferred::discard_pending(&file.destination)?;
+            (Placed::Written(written), file.destination.clone())
+        }
+    };
+
+    match (owner, original) {
+        (Some(owner), _) => owner.apply(&path)?,
+        (None, Some(original)) => owner::preserve(&original, &path)?,
+        (None, None) => {}
+    }
+
+    Ok(placed)
 }
 
-impl CommonHeaders for RequestBuilder {
-    fn add_common_headers(self) -> Self {
-        self.header(ACCEPT, "text/html")
-            .header(ACCEPT_LANGUAGE, "en-US,en;q=0.5")
-            .header(ACCEPT_ENCODING, "gzip")
-            .header(CONNECTION, "keep-alive")
+/// Linking the staged file next to the destination and renaming the link over it moves no data, when both are on one filesystem
+/// The staged file stays either way, so an interrupted install can still be resumed from the staging directory
+fn write_file(file: &FileCopy, link: bool) -> io::Result<u64> {
+    if link {
+        let name = file
+            .destination
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let temporary = file.destination.with_file_name(format!(".{name}.link"));
+        let _ = fs::remove_file(&temporary);
+        let linked = fs::hard_link(&file.source, &temporary)
+            .and_then(|()| fs::rename(&temporary, &file.destination));
+        match linked {
+            Ok(()) => return Ok(file.size),
+            Err(err) => {
+                let _ = fs::remove_file(&temporary);
+                trace!(
+                    "Could not link {:?}, copying it instead: {err}",
+                    file.source
+                );
+            }
+        }
     }
+
+    // On linux this uses copy_file_range, which shares the blocks on filesystems that support reflinks
+    fs::copy(&file.source, &file.destination)
+}
+
+/// Copies the files on a few threads at once, recording each one in the journal once it is done
+/// The first failure stops the copy, the files already copied stay
+/// Returns the replacements of files that stayed locked, which still have to be scheduled
+fn copy_files(
+    files: &[FileCopy],
+    link: bool,
+    settings: &Settings,
+    journal: &Journal,
+) -> Result<Vec<Replacement>> {
+    let (jobs, owner, defer_locked) = (settings.copy_jobs, settings.owner, settings.defer_locked);
+    let total = files.iter().map(|file| file.size).sum();
+    let mut progress = Progress::new("Copying", Some(total)).with_items(files.len() as u64);
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+    // Logs from the threads stay in the span of the install
+    let span = tracing::Span::current();
+
+    let (next, failed, span) = (&next, &failed, &span);
+    let result = thread::scope(|scope| {
+        for _ in 0..jobs.min(files.len()) {
+            let sender = sender.clone();
+            scope.spawn(move || {
+                span.in_scope(|| {
+                    while !failed.load(Ordering::Relaxed) {
+                        let Some(file) = files.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                            break;
+                        };
+
+                        trace!("Copying {:?} to {:?}", file.source, file.destination);
+                        let copied = place(file, link, owner, defer_locked);
+                        if copied.is_err() {
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                        if sender.send((file, copied)).is_err() {
+                            break;
+                        }
+                    }
+                })
+            });
+        }
+        drop(sender);
+
+        // The journal is only written from here, the threads just copy
+        let mut result = Ok(Vec::new());
+        for (file, copied) in receiver {
+            let recorded = copied
+                .map_err(BedrockUpdaterError::from)
+                .and_then(|placed| {
+                    progress.next_item();
+                    match placed {
+                        Placed::Written(size) => progress.advance(size),
+                        Placed::Deferred(replacement) => {
+                            if let Ok(deferred) = &mut result {
+                                deferred.push(replacement);
+                            }
+                        }
+                    }
+                    journal.record(&Step::Copied {
+                        path: file.key.clone(),
+                    })
+                });
+            if let Err(err) = recorded {
+                failed.store(true, Ordering::Relaxed);
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+
+        result
+    });
+    progress.finish();
+
+    result
+}
+
+/// Every file the release ships, relative to the directory it was extracted to
+fn released_files(staged: &Path) -> Result<Vec<String>> {
+    Ok(manifest::files_below(staged)?
+        .iter()
+        .filter_map(|file| file.strip_prefix(staged).ok())
+        .map(manifest::manifest_key)
+        .collect())
 }
 
 /// An idiomatic way to throw an error
-trait ElseErr {
+pub(crate) trait ElseErr {
     fn else_err<E>(self, err: E) -> std::result::Result<(), E>;
 }
 
@@ -68,79 +363,113 @@ impl ElseErr for bool {
     }
 }
 
+/// Decides what happens once the current and latest versions are known
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateMode {
+    /// Install updates unless they are paused or still within the stability delay
+    Scheduled,
+    /// Only report whether an update is available
+    Check,
+    /// Install updates right away, ignoring the pause file and stability delay
+    Install,
+    /// Same as install, but installs the latest version again when it is already installed
+    /// Preserved files are still kept, so this only restores the files an update would replace
+    Reinstall,
+}
+
+/// Which updates are installed automatically, compared against the installed version
+/// Anything the policy does not allow is only reported and has to be installed by hand
+#[derive(ValueEnum, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpdatePolicy {
+    /// Only updates within the same minor version, e.g. 1.21.43.1 to 1.21.44.1
+    Patch,
+    /// Only updates within the same major version, e.g. 1.21.44.1 to 1.22.0.3
+    Minor,
+    /// Every update
+    #[default]
+    All,
+    /// No updates
+    None,
+}
+
+/// What happens to files the operator changed since the updater laid them down, when an update replaces them
+#[derive(ValueEnum, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ModifiedFiles {
+    /// Replace them with the new version's files
+    #[default]
+    Overwrite,
+    /// Leave them as they are, so they keep the old version's contents with the changes
+    Skip,
+    /// Back them up to the backup directory, then replace them
+    Backup,
+}
+
+impl UpdatePolicy {
+    fn allows(self, current: &BedrockVersion, latest: &BedrockVersion) -> bool {
+        match self {
+            UpdatePolicy::Patch => (current.major, current.minor) == (latest.major, latest.minor),
+            UpdatePolicy::Minor => current.major == latest.major,
+            UpdatePolicy::All => true,
+            UpdatePolicy::None => false,
+        }
+    }
+}
+
 pub struct BedrockUpdater<'a> {
     client: &'a Client,
-    server_dir: &'a Path,
-    update_dir: &'a Path,
-    version_path: &'a Path,
-    set_first_version: Option<&'a str>,
+    settings: &'a Settings,
+    set_first_version: Option<BedrockVersion>,
+    interactive: bool,
+    first_seen_path: PathBuf,
+    shutdown: &'a CancellationToken,
+    /// Size of the zip used by the current install attempt, recorded in the history
+    zip_size: AtomicU64,
+    /// Where that zip came from
+    zip_source: Mutex<Option<String>>,
 }
 
 impl<'a> BedrockUpdater<'a> {
     pub fn new(
         client: &'a Client,
-        server_dir: &'a Path,
-        update_dir: &'a Path,
-        version_path: &'a Path,
-        set_first_version: Option<&'a str>,
+        settings: &'a Settings,
+        set_first_version: Option<BedrockVersion>,
+        interactive: bool,
+        shutdown: &'a CancellationToken,
     ) -> Self {
         Self {
             client,
-            server_dir,
-            update_dir,
-            version_path,
+            settings,
             set_first_version,
+            interactive,
+            first_seen_path: settings.server_dir.join(FIRST_SEEN_FILE),
+            shutdown,
+            zip_size: AtomicU64::new(0),
+            zip_source: Mutex::new(None),
         }
     }
 
-    /// Gets the download link from the minecraft bedrock server download page
-    /// This function's selector should be updated as the document changes
-    #[tracing::instrument(skip_all)]
-    async fn get_latest_download_link(document: &Html) -> Result<Url> {
-        let unparsed_selector = selector!();
-
-        let download_selector = Selector::parse(&unparsed_selector)?;
-
-        let mut select = document.select(&download_selector);
-
-        trace!("Looking for download element");
-        let download_element = select
-            .next()
-            .ok_or(BedrockUpdaterError::NoDownloadElement)?;
-
-        // This is to safeguard incorrect element fetching if the page changes for any reason
-        // As of now the minecraft bedrock server download page should only have one download link for each "data platform"
-        trace!("Checking for extra download elements");
-        if select.next().is_some() {
-            return Err(BedrockUpdaterError::TooManyDownloadElements);
-        }
-
-        trace!("No other matching download buttons found, attempting to get link from button");
-        // No href element means that the element is most likely incorrect or the page has updated
-        let link = download_element
-            .attr("href")
-            .ok_or(BedrockUpdaterError::NoDownloadLinkAttr)?;
-
-        trace!("Successfully got link from element");
-        Ok(Url::parse(link)?)
+    /// Stops the update between phases once a shutdown was requested
+    fn check_cancelled(&self) -> Result<()> {
+        (!self.shutdown.is_cancelled()).else_err(BedrockUpdaterError::Cancelled)
     }
 
     /// Gets the current version of the server
-    /// For now, it does not seem like there is an easy way to check this, so it will check a version file
-    /// For setup, the user must set the version once manually
-    /// As new versions are downloaded, the version file will be updated
+    /// This is read from the state file, which is updated as new versions are installed
+    /// For setup, the version is detected from the server or set once manually
     #[tracing::instrument(skip_all)]
-    async fn get_current_version<'b>(&self, contents: Option<&'b str>) -> Result<&'b str>
-    where
-        'a: 'b,
-    {
+    async fn get_current_version(
+        &self,
+        recorded: Option<BedrockVersion>,
+    ) -> Result<BedrockVersion> {
         trace!("Getting current version");
-        let version_res = match (self.set_first_version, contents) {
+        let version_res = match (self.set_first_version, recorded) {
             (None, None) => Err(BedrockUpdaterError::NoCurrentVersion),
-            (None, Some(contents)) => Ok(contents),
+            (None, Some(recorded)) => Ok(recorded),
             (Some(version), None) | (Some(version), Some(_)) => {
-                info!("Writing to version file");
-                std::fs::write(self.version_path, &version)?;
+                info!("Writing version to state file");
+                State::update(self.settings, |state| state.current_version = Some(version))?;
 
                 Ok(version)
             }
@@ -149,195 +478,1937 @@ impl<'a> BedrockUpdater<'a> {
         version_res
     }
 
+    /// Asks the server itself which version it is, for installs the updater has not seen before
+    /// Detection failing is only logged, since the version can still be set by hand
+    async fn detect_current_version(&self) -> Result<Option<BedrockVersion>> {
+        info!("No installed version is recorded, detecting it from the server");
+        let version = match detect::server_version(
+            &self.settings.live_dir(),
+            self.settings.emulator.as_deref(),
+        )
+        .await
+        {
+            Ok(Some(version)) => version,
+            Ok(None) => {
+                warn!("The server did not report its version");
+                return Ok(None);
+            }
+            Err(err) => {
+                warn!("Could not start the server to detect its version: {err}");
+                return Ok(None);
+            }
+        };
+
+        info!("Detected installed version {version}");
+        State::update(self.settings, |state| state.current_version = Some(version))?;
+
+        Ok(Some(version))
+    }
+
+    /// Pings the running server and warns when it runs something other than the recorded version
+    /// A server that is not running, or not answering, is not a problem
+    /// Returns whether the server is running the recorded version
+    async fn check_running_version(&self, current: &BedrockVersion) -> bool {
+        let pong = match ping::ping(&self.settings.ping_address).await {
+            Ok(Some(pong)) => pong,
+            Ok(None) => {
+                debug!(
+                    "No server answered the ping at {}",
+                    self.settings.ping_address
+                );
+                return false;
+            }
+            Err(err) => {
+                debug!(
+                    "Could not ping the server at {}: {err}",
+                    self.settings.ping_address
+                );
+                return false;
+            }
+        };
+        debug!(
+            "{:?} is running {} (protocol {}) with {}/{} players",
+            pong.motd, pong.version, pong.protocol, pong.players, pong.max_players
+        );
+
+        // The pong leaves out the build number, so only the release is compared
+        let running = match pong.version.parse::<BedrockVersion>() {
+            Ok(running) => running,
+            Err(err) => {
+                debug!("Could not compare the running version: {err}");
+                return false;
+            }
+        };
+        if !running.same_release(current) {
+            warn!(
+                "The running server reports version {} but {current} is recorded, the state file may be out of date or the server needs a restart",
+                pong.version
+            );
+            return false;
+        }
+
+        true
+    }
+
+    /// Refuses to install over corrupt worlds, which the new version would be blamed for or make worse
+    fn check_worlds(&self) -> Result<()> {
+        info!("Checking the worlds");
+        let problems = world::check_all(&self.settings.live_dir())?;
+        if !problems.is_empty() {
+            return Err(BedrockUpdaterError::CorruptWorlds(problems.join("; ")));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the worlds again the first time the installed version is seen running, recording the outcome in the history
+    /// The version has opened them by then, so this catches damage done by the update itself
+    fn check_started_worlds(
+        &self,
+        current: &BedrockVersion,
+        unchecked: Option<BedrockVersion>,
+    ) -> Result<()> {
+        if unchecked != Some(*current) {
+            return Ok(());
+        }
+
+        info!("Version {current} has started, checking the worlds");
+        let started = SystemTime::now();
+        let timer = Instant::now();
+        let problems = world::check_all(&self.settings.live_dir())?;
+        for problem in &problems {
+            error!("Since version {current} started, {problem}");
+        }
+
+        let record = UpdateRecord {
+            old_version: current.to_string(),
+            new_version: current.to_string(),
+            started,
+            duration: timer.elapsed(),
+            bytes: None,
+            source: None,
+            outcome: Outcome::Checked,
+            error: (!problems.is_empty()).then(|| problems.join("; ")),
+        };
+        if let Err(err) = history::append(&self.settings.state_path, &record) {
+            warn!("Could not record the world check in the history: {err}");
+        }
+
+        State::update(self.settings, |state| state.worlds_unchecked = None)
+    }
+
     /// Gets the latest version of the server
     /// This is fetched from the download link of the file, which contains the version string
     #[tracing::instrument(skip_all)]
-    async fn get_latest_version(file_name: &str) -> Result<&str> {
+    async fn get_latest_version(file_name: &str) -> Result<BedrockVersion> {
         trace!("Getting latest version");
 
-        // Regex for a version string with exactly 4 parts
-        // It seems unlikely that the minecraft bedrock versioning scheme will change
-        // In the event that it does, this should be changed
-        let pattern = Regex::new(r"\d+(\.\d+){3}")?;
-
-        let version_str = pattern
-            .find(file_name)
-            .ok_or(BedrockUpdaterError::NoVersionString)?
-            .as_str();
-
-        Ok(version_str)
+        BedrockVersion::from_file_name(file_name)
     }
 
     /// Gets the current and latest versions in a tuple respectively
     #[tracing::instrument(skip_all)]
-    async fn get_versions<'b>(
+    async fn get_versions(
         &self,
-        download_link_file: &'b str,
-        contents: Option<&'b str>,
-    ) -> Result<(Version<'b>, Version<'b>)>
-    where
-        'a: 'b,
-    {
+        download_link_file: &str,
+        recorded: Option<BedrockVersion>,
+    ) -> Result<(BedrockVersion, BedrockVersion)> {
         trace!("Getting versions");
-        let latest_version_string = Self::get_latest_version(download_link_file);
-
-        let current_version_string = Self::get_current_version(self, contents);
-
-        let current_version = Version::from(current_version_string.await?)
-            .ok_or(BedrockUpdaterError::UnparseableVersion)?;
-        let latest_version = Version::from(latest_version_string.await?)
-            .ok_or(BedrockUpdaterError::UnparseableVersion)?;
+        let current_version = Self::get_current_version(self, recorded).await?;
+        let latest_version = Self::get_latest_version(download_link_file).await?;
 
         Ok((current_version, latest_version))
     }
 
-    /// Attempt to get the html of the bedrock server page from an http request
+    /// Checks whether the latest version has been the latest for at least the stability delay
+    /// The time each version was first seen is persisted so the delay survives restarts
     #[tracing::instrument(skip_all)]
-    async fn fetch_document(client: &Client) -> Result<Html> {
-        trace!("Attempting to fetch html document");
-        let page_request = client.get(BEDROCK_SERVER_PAGE).add_common_headers();
+    fn is_stable(&self, latest: &BedrockVersion) -> Result<bool> {
+        if self.settings.stability_delay.is_zero() {
+            return Ok(true);
+        }
 
-        let html = page_request.send().await?.text().await?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
 
-        let document = Html::parse_document(&html);
-        trace!("Found document!");
+        // Each line is a version followed by the unix timestamp it was first seen at
+        let contents = fs::read_to_string(&self.first_seen_path).unwrap_or_default();
+        let mut first_seen: HashMap<&str, u64> = contents
+            .lines()
+            .filter_map(|line| {
+                let (version, timestamp) = line.split_once(' ')?;
+                Some((version, timestamp.parse().ok()?))
+            })
+            .collect();
 
-        Ok(document)
-    }
+        let latest_key = latest.to_string();
+        let seen = match first_seen.get(latest_key.as_str()) {
+            Some(seen) => *seen,
+            None => {
+                info!("Version {latest} seen for the first time");
+                first_seen.insert(&latest_key, now);
 
-    /// Extracts and copies the new server files to the server directory 
-    #[tracing::instrument(skip_all)]
-    async fn install_server<'b>(
-        &self,
-        bedrock_server_zip: &'b Bytes,
-        new_version: &'b Version<'b>,
-        blacklist: &'b HashSet<&str>,
-    ) -> Result<()> {
-        info!("Creating updater directory");
-        std::fs::create_dir_all(self.update_dir)?;
+                let lines: String = first_seen
+                    .iter()
+                    .map(|(version, timestamp)| format!("{version} {timestamp}\n"))
+                    .collect();
+                fs::write(&self.first_seen_path, lines)?;
 
-        info!("Extracting updated server zip");
-        zip_extract::extract(Cursor::new(bedrock_server_zip), self.update_dir, true)?;
+                now
+            }
+        };
 
-        let entries = std::fs::read_dir(self.update_dir)?;
+        let observed = Duration::from_secs(now.saturating_sub(seen));
+        if observed < self.settings.stability_delay {
+            let remaining = humantime::format_duration(self.settings.stability_delay - observed);
+            info!("Waiting {remaining} before installing version {latest}");
+            return Ok(false);
+        }
 
-        info!("Copying files");
-        // Start by looping through each of the files in the update dir
-        for entry in entries {
-            let path = entry?.path();
+        Ok(true)
+    }
+
+    /// Finds the extracted entries that should be copied, along with where they are copied to
+    /// Files that are kept are only copied when they don't exist in the server directory yet
+    fn plan_copies(&self, staged: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let mut copies = Vec::new();
+
+        for entry in std::fs::read_dir(staged)? {
+            let source = entry?.path();
 
-            // file_name is taken from the path to compare to file names from the blacklist
-            let file_name = path
+            // file_name is taken from the path to look up its strategy
+            let file_name = source
                 .file_name()
                 .ok_or(BedrockUpdaterError::NoFileName)?
                 .to_str()
                 .ok_or(BedrockUpdaterError::NoFileName)?;
 
             // The destination is always the server's directory
-            let destination = self.server_dir.join(&file_name);
-
-            // Prevent overwrites of the files in the blacklist
-            // Don't prevent blacklisted files from being copied from update dir if they don't exist in the server dir
-            if !blacklist.contains(file_name) || !destination.exists() {
-                // The source is always the update directory
-                let source = self.update_dir.join(&path);
-                debug!("Copying {source:?} to {destination:?}");
-                if source.is_file() {
-                    debug!("Copying file");
-                    // When it is a file, just do a simple copy
-                    fs::copy(&source, &destination)?;
-                } else {
-                    debug!("Copying dir");
-                    // Recursive copy requires that all directories being copied to exist
-                    // fs_extra copy copies inside the destination directory instead of overwriting
-                    // The server directory makes more sense here
-                    fs_extra::dir::copy(
-                        &source,
-                        self.server_dir,
-                        &CopyOptions::new().overwrite(true),
-                    )?;
+            let destination = self.settings.server_dir.join(file_name);
+
+            let kept = self.settings.strategies.for_path(Path::new(file_name)) == Strategy::Keep;
+            let protected = self
+                .settings
+                .protected_dirs
+                .containing(&self.settings.server_dir, Path::new(file_name))
+                .is_some();
+            let skipped = protected || (source.is_file() && kept && destination.exists());
+            if !skipped {
+                copies.push((source, destination));
+            }
+        }
+
+        Ok(copies)
+    }
+
+    /// Where kept files are taken from, which is the server directory itself the first time the versioned layout is used
+    fn preserved_dir(&self) -> PathBuf {
+        let live = self.settings.live_dir();
+
+        match live.exists() {
+            true => live,
+            false => self.settings.server_dir.clone(),
+        }
+    }
+
+    /// Finds the entries of the running version to carry over, along with where they go in the other version directory
+    /// That is the kept and merged files, the protected directories and everything neither release shipped
+    fn plan_preserved(
+        &self,
+        into: &Path,
+        ships: impl Fn(&str) -> bool,
+    ) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let preserved_dir = self.preserved_dir();
+        let settings = self.settings;
+
+        // Files only the running version shipped would otherwise outlive it
+        let shipped = Manifest::load(&settings.state_path)?
+            .map(|manifest| manifest.shipped())
+            .unwrap_or_default();
+
+        // Coming from the server directory, the updater's own files stay where they are
+        let migrating = preserved_dir == settings.server_dir;
+        let owned: Vec<&OsStr> = [
+            &settings.update_dir,
+            &settings.archive_dir,
+            &settings.backup_dir,
+            &settings.state_path,
+            &settings.pause_path,
+            &settings.version_path,
+        ]
+        .into_iter()
+        .filter_map(|path| path.strip_prefix(&settings.server_dir).ok()?.iter().next())
+        .chain([
+            OsStr::new(layout::VERSIONS_DIR),
+            OsStr::new(layout::CURRENT_LINK),
+        ])
+        .collect();
+
+        let mut preserved = Vec::new();
+        for entry in fs::read_dir(&preserved_dir)? {
+            let name = entry?.file_name();
+            if migrating
+                && (name.to_string_lossy().starts_with('.') || owned.contains(&name.as_os_str()))
+            {
+                continue;
+            }
+            if name == layout::VERSION_MANIFEST {
+                continue;
+            }
+
+            let name_str = name.to_string_lossy();
+            let strategy = settings.strategies.for_path(Path::new(name_str.as_ref()));
+            let protected = settings
+                .protected_dirs
+                .containing(&preserved_dir, Path::new(&name))
+                .is_some();
+            if protected
+                || matches!(strategy, Strategy::Keep | Strategy::Merge)
+                || (!ships(&name_str) && !shipped.contains(name_str.as_ref()))
+            {
+                preserved.push((preserved_dir.join(&name), into.join(&name)));
+            } else if preserved_dir.join(&name).is_dir() {
+                // Protected directories inside shipped ones, like a custom pack, replace what the update has there
+                for nested in settings
+                    .protected_dirs
+                    .find(&preserved_dir, Path::new(&name))?
+                {
+                    preserved.push((preserved_dir.join(&nested), into.join(&nested)));
                 }
             }
         }
 
-        // Finally, write the updated version in the version file
-        // At this point, the server is completely updated
-        fs::write(self.version_path, new_version.as_str())?;
+        Ok(preserved)
+    }
+
+    /// Copies the entries of the running version into another version directory, replacing what is there
+    fn carry_over(&self, preserved: Vec<(PathBuf, PathBuf)>, into: &Path) -> Result<()> {
+        info!("Copying kept files from {:?}", self.preserved_dir());
 
-        // Cleanup the update directory
-        info!("Cleaning up");
-        std::fs::remove_dir_all(self.update_dir)?;
+        for (source, destination) in preserved {
+            debug!("Copying {source:?} to {destination:?}");
+            if source.is_file() {
+                fs::copy(&source, &destination)?;
+                owner::preserve(&fs::symlink_metadata(&source)?, &destination)?;
+            } else {
+                // What the other version has under the same name is replaced entirely
+                if destination.exists() {
+                    fs::remove_dir_all(&destination)?;
+                }
+                // Protected directories can be nested inside the ones the update ships
+                let parent = destination.parent().unwrap_or(into);
+                fs::create_dir_all(parent)?;
+                fs_extra::dir::copy(&source, parent, &CopyOptions::new())?;
+                owner::preserve_all(&source, &destination)?;
+            }
+        }
 
         Ok(())
     }
 
-    async fn try_update<'b>(
+    /// Points the current link at the version directory, recording it as the most recently used one
+    fn switch_to(&self, version_dir: &Path, manifest: &Manifest) -> Result<()> {
+        manifest.write(&version_dir.join(layout::VERSION_MANIFEST))?;
+        layout::switch(&self.settings.server_dir, version_dir)?;
+        if let Some(owner) = self.settings.owner {
+            owner.apply(&self.settings.server_dir.join(layout::CURRENT_LINK))?;
+        }
+
+        layout::prune(&self.settings.server_dir, self.settings.keep_versions)
+    }
+
+    /// Warns about settings the new server.properties has that the kept one is missing
+    /// Without this, keeping server.properties hides new options forever
+    fn report_new_properties(&self, staged: &Path) {
+        if self
+            .settings
+            .strategies
+            .for_path(Path::new(PROPERTIES_FILE))
+            != Strategy::Keep
+        {
+            return;
+        }
+
+        let kept = self.preserved_dir().join(PROPERTIES_FILE);
+        let shipped = staged.join(PROPERTIES_FILE);
+        if !kept.exists() || !shipped.exists() {
+            return;
+        }
+
+        match properties::new_keys(&kept, &shipped) {
+            Ok(new_keys) if new_keys.is_empty() => trace!("No new keys in {PROPERTIES_FILE}"),
+            Ok(new_keys) => {
+                let listed: Vec<String> = new_keys
+                    .iter()
+                    .map(|(key, value)| format!("{key}={value}"))
+                    .collect();
+                warn!(
+                    "The new {PROPERTIES_FILE} has settings the kept one is missing, add them to {kept:?} to change their defaults: {}",
+                    listed.join(", ")
+                );
+            }
+            Err(err) => warn!("Could not compare {PROPERTIES_FILE} files: {err}"),
+        }
+    }
+
+    /// Shows what the update is going to change and asks whether to go through with it
+    async fn confirm_install(
         &self,
-        current: &Version<'b>,
-        latest: &Version<'b>,
-        download_link: Url,
-    ) -> Result<()> {
-        let version_span = info_span!("version_check");
-        let version_guard = version_span.enter();
-        trace!("Found server version: {current}");
-        trace!("Found latest version: {latest}");
+        current_version: &BedrockVersion,
+        new_version: &BedrockVersion,
+        copies: &[(PathBuf, PathBuf)],
+    ) -> Result<bool> {
+        println!("Updating server from {current_version} to {new_version}");
+        println!("The following files will be written:");
+        for (_, destination) in copies {
+            let action = if destination.exists() {
+                "overwrite"
+            } else {
+                "create"
+            };
+            println!("  {action:<9} {}", destination.display());
+        }
 
-        // The program will only try to install the server if it is not up to date
-        if current == latest {
-            trace!("Server is up to date");
-            drop(version_guard);
-        } else if current > latest {
-            info!("Server is most likely a preview version, make sure you set the correct version");
-            drop(version_guard);
-        } else {
-            info!("Server is not up to date");
-            drop(version_guard);
-            let install_span = info_span!("install_phase");
-            let install_guard = install_span.enter();
+        prompt::confirm(String::from("Install the update?")).await
+    }
+
+    /// Shows which version directory the update goes into and asks whether to switch to it
+    async fn confirm_switch(
+        &self,
+        current_version: &BedrockVersion,
+        new_version: &BedrockVersion,
+        version_dir: &Path,
+        preserved: &[(PathBuf, PathBuf)],
+    ) -> Result<bool> {
+        println!("Updating server from {current_version} to {new_version}");
+        println!(
+            "The update is installed into {} with these files kept:",
+            version_dir.display()
+        );
+        for (source, _) in preserved {
+            println!("  keep      {}", source.display());
+        }
 
-            // This will eventually be turned into an option in the struct, but for now it is hardcoded
-            debug!("Reading blacklist");
-            let overwrite_blacklist =
-                hashset!["permissions.json", "allowlist.json", "server.properties"];
+        prompt::confirm(format!("Switch {} to the update?", layout::CURRENT_LINK)).await
+    }
+
+    /// Extracts and copies the new server files to the server directory
+    #[tracing::instrument(skip_all)]
+    async fn install_server<'b>(
+        &self,
+        bedrock_server_zip: &'b Path,
+        source: &str,
+        current_version: &BedrockVersion,
+        new_version: &BedrockVersion,
+    ) -> Result<bool> {
+        self.zip_size
+            .store(fs::metadata(bedrock_server_zip)?.len(), Ordering::Relaxed);
+        *self.zip_source.lock().unwrap() = Some(source.to_owned());
+        if self.settings.check_worlds {
+            self.check_worlds()?;
+        }
 
-            let download_request = self.client.get(download_link);
+        // The files exist twice until the install is cleaned up, once staged and once copied
+        let unpacked = extract::unpacked_size(bedrock_server_zip, &self.settings.filter).await?;
+        disk::ensure_space(
+            &[
+                (&self.settings.staging_root(), unpacked),
+                (&self.settings.server_dir, unpacked),
+            ],
+            self.settings.source.free_space_margin,
+        )?;
 
-            info!("Downloading new server version");
-            let bedrock_server_zip: Bytes = download_request.send().await?.bytes().await?;
+        info!("Creating updater directory");
+        // Dropping it removes it again, so every early return below discards the extracted files
+        let staging = StagingDir::create(&self.settings.staging_root())?;
+        let staged = staging.path();
 
-            Self::install_server(self, &bedrock_server_zip, &latest, &overwrite_blacklist).await?;
-            drop(install_guard);
+        // A crash from here on is picked up by the next run, which finishes or discards the install
+        let checksum = manifest::hash_file(bedrock_server_zip)?;
+        let started = Step::Started {
+            pid: process::id(),
+            started: daemon::started(process::id()),
+            from: *current_version,
+            to: *new_version,
+            source: source.to_owned(),
+            checksum: checksum.clone(),
+            staging: staged.to_owned(),
+        };
+        let journal = Journal::start(&self.settings.state_path, &started)?;
+
+        info!("Extracting updated server zip");
+        extract::extract(bedrock_server_zip, staged, &self.settings.filter).await?;
+        if let Err(err) = extract::validate(staged, &self.settings.filter) {
+            info!("Discarding extracted files");
+            return Err(err);
         }
 
-        Ok(())
+        // A binary this system can't run would only fail once the server is restarted
+        if let Err(err) = compat::check(
+            &staged.join(detect::SERVER_BINARY),
+            self.settings.emulator.as_deref(),
+        ) {
+            info!("Discarding extracted files");
+            return Err(err);
+        }
+        journal.record(&Step::Extracted)?;
+
+        // This is the last point where stopping leaves the server untouched
+        // Once copying starts, it is finished even if a shutdown is requested
+        if let Err(err) = self.check_cancelled() {
+            info!("Discarding extracted files");
+            return Err(err);
+        }
+
+        self.report_new_properties(staged);
+        // The staged files stay until the state is written, so an interrupted copy can be resumed from them
+        let (installed, staging) = match self.settings.layout {
+            InstallLayout::InPlace => {
+                let installed = self
+                    .copy_in_place(
+                        &staging,
+                        &journal,
+                        current_version,
+                        new_version,
+                        self.interactive,
+                    )
+                    .await?;
+                (installed, Some(staging))
+            }
+            InstallLayout::Versioned => {
+                let installed = self
+                    .switch_version(staging, &journal, current_version, new_version)
+                    .await?;
+                (installed, None)
+            }
+        };
+        let Some(manifest) = installed else {
+            return Ok(false);
+        };
+
+        self.record_installed(new_version, source, checksum, &manifest)?;
+        journal.record(&Step::StateWritten)?;
+
+        if let Some(staging) = staging {
+            info!("Cleaning up");
+            staging.remove()?;
+        }
+        journal.finish()?;
+
+        if self.settings.smoke_test {
+            if let Err(reason) = self.smoke_test(new_version).await {
+                error!("Version {new_version} does not run, {reason}, rolling back to {current_version}");
+                // Recorded first, so a rollback that fails doesn't lead to installing it again and again
+                State::update(self.settings, |state| {
+                    state.broken.insert(*new_version);
+                })?;
+                self.roll_back_broken(current_version, new_version)?;
+                return Err(BedrockUpdaterError::SmokeTestFailed(
+                    new_version.to_string(),
+                    current_version.to_string(),
+                    reason,
+                ));
+            }
+        }
+        self.check_player_lists();
+
+        Ok(true)
     }
 
-    pub async fn run_updater(&self) -> Result<()> {
-        // First get the minecraft download page html
-        let document = Self::fetch_document(self.client).await?;
+    /// Warns about kept player lists the new server can't fully read, since a changed format can lock everyone out
+    fn check_player_lists(&self) {
+        let live_dir = self.settings.live_dir();
+        for list in PlayerList::ALL {
+            match list.problems(&live_dir) {
+                Ok(problems) => {
+                    for problem in problems {
+                        warn!("{}: {problem}", list.file_name());
+                    }
+                }
+                Err(err) => warn!("Could not read {}: {err}", list.file_name()),
+            }
+        }
+    }
 
-        // The path part of the Url is necessary to get the filename
-        // This is so to prevent version strings from being parsed in the url if they are ever added
-        let download_link = Self::get_latest_download_link(&document).await?;
+    /// Starts the new server until it prints its version, which it only does once its libraries loaded
+    /// It is stopped before it loads the world, so it doesn't fight a running server over the port
+    async fn smoke_test(&self, new_version: &BedrockVersion) -> std::result::Result<(), String> {
+        info!("Starting {} to check that it runs", detect::SERVER_BINARY);
+        match detect::server_version(&self.settings.live_dir(), self.settings.emulator.as_deref())
+            .await
+        {
+            Ok(Some(version)) if version == *new_version => {
+                info!("Version {version} started fine");
+                Ok(())
+            }
+            Ok(Some(version)) => Err(format!("it reports version {version} instead")),
+            Ok(None) => Err(String::from(
+                "it exited or hung without printing its version",
+            )),
+            Err(BedrockUpdaterError::FileNotFound(err)) => {
+                Err(format!("it could not be started: {err}"))
+            }
+            Err(err) => Err(format!("it could not be started: {err}")),
+        }
+    }
 
-        // The clone is necessary
-        // The function will not be able to move download_link since it gets borrowed when calling .path()
-        let cloned_download_link = download_link.clone();
+    /// Puts the version that ran before back, from its version directory or from the files the update replaced
+    /// Scheduled updates carry on, the broken version is skipped since it was recorded as broken
+    fn roll_back_broken(&self, current: &BedrockVersion, broken: &BedrockVersion) -> Result<()> {
+        match self.settings.layout {
+            InstallLayout::Versioned => {
+                let running = layout::current_target(&self.settings.server_dir);
+                let retained = layout::retained(&self.settings.server_dir)?
+                    .into_iter()
+                    .filter(|retained| Some(&retained.path) != running.as_ref())
+                    .find(|retained| retained.version == *current)
+                    .ok_or(BedrockUpdaterError::NoRollbackTarget)?;
+                self.switch_retained(broken, retained)
+            }
+            InstallLayout::InPlace => undo::roll_back_last(self.settings),
+        }
+    }
 
-        self.server_dir
-            .exists()
-            .else_err(BedrockUpdaterError::NoServerPath)?;
+    /// Writes the updated version in the state file
+    /// At this point, the server is completely updated
+    fn record_installed(
+        &self,
+        version: &BedrockVersion,
+        source: &str,
+        checksum: String,
+        manifest: &Manifest,
+    ) -> Result<()> {
+        State::update(self.settings, |state| {
+            state.current_version = Some(*version);
+            state.installed_at = Some(SystemTime::now());
+            state.source = Some(source.to_owned());
+            state.checksum = Some(checksum);
+            state.channel = Some(self.settings.source.channel);
+            state.worlds_unchecked = self.settings.check_worlds.then_some(*version);
+        })?;
+        manifest.save(&self.settings.state_path)?;
+        Status::update(&self.settings.server_dir, |status| {
+            status.last_update = Some(SystemTime::now())
+        })
+    }
 
-        trace!("Attempting to get version file version");
-        let contents = std::fs::read(self.version_path)
-            .map_or(None,|contents| Some(String::from_utf8(contents)))
-            .transpose()?;
+    /// Finishes or discards an install an earlier run was interrupted in, going by its journal
+    pub async fn recover(&self) -> Result<()> {
+        let Some(steps) = Journal::interrupted(&self.settings.state_path)? else {
+            return Ok(());
+        };
+        let Some(Step::Started {
+            pid,
+            started,
+            from,
+            to,
+            source,
+            checksum,
+            staging,
+        }) = steps.first().cloned()
+        else {
+            warn!("The install journal is empty, discarding it");
+            return Journal::discard(&self.settings.state_path);
+        };
+        if pid != process::id() && daemon::still_running(pid, started.as_deref()) {
+            debug!("The install in the journal is still being done by process {pid}");
+            return Ok(());
+        }
 
-        let (current, latest) =
-            Self::get_versions(self, cloned_download_link.path(), contents.as_deref()).await?;
+        // Dropping it removes whatever is left of the staged files
+        let staging = StagingDir::adopt(staging);
+        let switching = steps.iter().find_map(|step| match step {
+            Step::Switching { version_dir } => Some(version_dir),
+            _ => None,
+        });
 
-        Self::try_update(self, &current, &latest, download_link).await?;
+        if steps.contains(&Step::StateWritten) {
+            info!("The install of {to} was interrupted while cleaning up, finishing it");
+        } else if let Some(version_dir) = switching {
+            if layout::current_target(&self.settings.server_dir).as_ref() == Some(version_dir) {
+                info!("The install of {to} was interrupted right after switching to it, recording it as installed");
+                let manifest = Manifest::read(&version_dir.join(layout::VERSION_MANIFEST))?
+                    .ok_or(BedrockUpdaterError::NoManifest)?;
+                self.record_installed(&to, &source, checksum, &manifest)?;
+            } else {
+                info!("The install of {to} was interrupted before switching to it, {from} is still running");
+            }
+        } else if steps.contains(&Step::Copying) {
+            if !steps.contains(&Step::Extracted) || !staging.path().is_dir() {
+                Journal::discard(&self.settings.state_path)?;
+                return Err(BedrockUpdaterError::InterruptedInstall(
+                    to.to_string(),
+                    staging.path().to_owned(),
+                ));
+            }
 
-        Ok(())
+            info!(
+                "The install of {to} over {from} was interrupted while copying, resuming it from {:?}",
+                staging.path()
+            );
+            let journal = Journal::resume(&self.settings.state_path)?;
+            let manifest = self
+                .copy_in_place(&staging, &journal, &from, &to, false)
+                .await?
+                .ok_or(BedrockUpdaterError::NoManifest)?;
+            self.record_installed(&to, &source, checksum, &manifest)?;
+            info!("Installed version {to}");
+        } else {
+            info!(
+                "The install of {to} was interrupted before it touched the server, discarding it"
+            );
+        }
+
+        staging.remove()?;
+        Journal::discard(&self.settings.state_path)
+    }
+
+    /// Copies the staged files over the server directory, returning the manifest of what was copied
+    /// Returns nothing when the update is declined
+    async fn copy_in_place(
+        &self,
+        staging: &StagingDir,
+        journal: &Journal,
+        current_version: &BedrockVersion,
+        new_version: &BedrockVersion,
+        confirm: bool,
+    ) -> Result<Option<Manifest>> {
+        let staged = staging.path();
+
+        // Prevent overwrites of the files that are kept
+        let copies = self.plan_copies(staged)?;
+
+        if confirm
+            && !self
+                .confirm_install(current_version, new_version, &copies)
+                .await?
+        {
+            info!("Update declined, discarding extracted files");
+            return Ok(None);
+        }
+
+        // The manifest is built from the extracted files, so it describes exactly what gets copied
+        let sources: Vec<PathBuf> = copies.iter().map(|(source, _)| source.clone()).collect();
+        let mut manifest = Manifest::build(new_version, staged, &sources)?;
+
+        // Only what changed since the last install is written, most of the packs are the same between releases
+        let previous = Manifest::load(&self.settings.state_path)?;
+        let existing = self.plan_existing(previous.as_ref(), &manifest)?;
+        let mut backup = UpdateBackup::start(
+            self.settings,
+            current_version,
+            new_version,
+            journal.resumed(),
+        )?;
+        let mut delta = Delta {
+            staged,
+            previous: previous.as_ref(),
+            manifest: &manifest,
+            existing: &existing,
+            journal,
+            backup: &mut backup,
+            owner: self.settings.owner,
+            files: Vec::new(),
+            skipped: 0,
+        };
+
+        info!("Copying files");
+        journal.record(&Step::Copying)?;
+        let planned = copies.iter().try_for_each(|(source, destination)| {
+            debug!("Copying {source:?} to {destination:?}");
+            delta.plan(source, destination)
+        });
+        let (files, skipped) = (delta.files, delta.skipped);
+        let link = disk::same_filesystem(staged, &self.settings.server_dir);
+        if link {
+            debug!("The staged files are on the filesystem of the server directory, linking them instead of copying");
+        }
+        let copied = planned.and_then(|()| copy_files(&files, link, self.settings, journal));
+        let deferred = match copied {
+            Ok(deferred) => deferred,
+            Err(err) => {
+                warn!("Copying failed, putting back the files this install already wrote: {err}");
+                match backup.roll_back(&self.settings.server_dir) {
+                    Ok(()) => journal.rolled_back(),
+                    // The journal stays, so the next run resumes the install instead
+                    Err(rollback_err) => {
+                        warn!("Could not roll back the partial install: {rollback_err}")
+                    }
+                }
+                return Err(err);
+            }
+        };
+        let written = files.iter().map(|file| file.destination.as_path());
+        deferred::schedule(&self.settings.state_path, written, deferred)?;
+        info!(
+            "Copied {} changed files, {skipped} were unchanged",
+            files.len()
+        );
+        backup.finish();
+
+        // Files the operator manages are left out, like the blacklisted ones always were
+        for key in existing.kept.iter().chain(&existing.merged) {
+            manifest.files.remove(key);
+        }
+        manifest.record_laid_down(&self.settings.server_dir)?;
+        // Modified files are still the ones laid down before, so they keep showing up as modified
+        if let Some(previous) = &previous {
+            for key in &existing.modified {
+                if let Some(entry) = previous.files.get(key) {
+                    manifest.files.insert(key.clone(), entry.clone());
+                }
+            }
+        }
+
+        if self.shutdown.is_cancelled() {
+            info!("Finishing install before shutting down");
+        }
+
+        let released = released_files(staged)?;
+        self.set_modes(&self.settings.server_dir, &released)?;
+        if self.settings.restore_contexts {
+            // The directories new files were created in count too, since they were made alongside them
+            let touched: BTreeSet<PathBuf> = released
+                .iter()
+                .flat_map(|key| {
+                    Path::new(key)
+                        .ancestors()
+                        .filter(|path| !path.as_os_str().is_empty())
+                })
+                .map(|relative| self.settings.server_dir.join(relative))
+                .collect();
+            selinux::restore(&touched.into_iter().collect::<Vec<_>>(), false).await?;
+        }
+
+        Ok(Some(manifest))
+    }
+
+    /// Decides what happens to every file the update would write over, backing up the ones that need it
+    fn plan_existing(&self, previous: Option<&Manifest>, manifest: &Manifest) -> Result<Existing> {
+        let mut existing = Existing::default();
+        let mut backups = Vec::new();
+
+        for (relative, entry) in &manifest.files {
+            let protected_dir = self
+                .settings
+                .protected_dirs
+                .containing(&self.settings.server_dir, Path::new(relative));
+            if let Some(protected_dir) = protected_dir {
+                existing
+                    .protected
+                    .insert(manifest::manifest_key(protected_dir));
+                existing.kept.insert(relative.clone());
+                continue;
+            }
+
+            let destination = self.settings.server_dir.join(relative);
+            if !destination.is_file() {
+                continue;
+            }
+
+            match self.settings.strategies.for_path(Path::new(relative)) {
+                Strategy::Keep => {
+                    existing.kept.insert(relative.clone());
+                }
+                Strategy::Merge if strategy::can_merge(relative) => {
+                    existing.merged.insert(relative.clone());
+                }
+                Strategy::Merge => {
+                    warn!("{relative} cannot be merged since it is not a key=value file, keeping it instead");
+                    existing.kept.insert(relative.clone());
+                }
+                Strategy::BackupThenOverwrite => {
+                    if manifest::hash_file(&destination)? != entry.sha256 {
+                        backups.push(PathBuf::from(relative));
+                    }
+                }
+                Strategy::Overwrite => {}
+            }
+        }
+
+        for dir in &existing.protected {
+            info!("Leaving {dir} as it is, what the update has in it is not installed since it is protected");
+        }
+
+        let modified = self.locally_modified(previous, manifest)?;
+        if !modified.is_empty() {
+            warn!(
+                "{} files were changed since they were installed: {}",
+                modified.len(),
+                modified.join(", ")
+            );
+
+            match self.settings.modified_files {
+                ModifiedFiles::Overwrite => {
+                    warn!("Overwriting them, use --modified-files to keep or back them up");
+                }
+                ModifiedFiles::Skip => {
+                    info!("Leaving them as they are, they are not updated");
+                    existing.modified.extend(modified);
+                }
+                ModifiedFiles::Backup => backups.extend(modified.iter().map(PathBuf::from)),
+            }
+        }
+
+        if !backups.is_empty() {
+            let backup = backup::create(self.settings, &backups, self.settings.backup_format)?;
+            info!(
+                "Backed up {} files to {backup:?} before overwriting them",
+                backups.len()
+            );
+        }
+
+        Ok(existing)
+    }
+
+    /// Files the last install laid down that were changed since and that the update would replace with other contents
+    /// Only files that are overwritten are checked, the other strategies already spare them
+    fn locally_modified(
+        &self,
+        previous: Option<&Manifest>,
+        manifest: &Manifest,
+    ) -> Result<Vec<String>> {
+        let Some(previous) = previous else {
+            return Ok(Vec::new());
+        };
+
+        let mut modified = Vec::new();
+        for (relative, entry) in &manifest.files {
+            if self.settings.strategies.for_path(Path::new(relative)) != Strategy::Overwrite {
+                continue;
+            }
+            let Some(laid_down) = previous.files.get(relative) else {
+                continue;
+            };
+            let path = self.settings.server_dir.join(relative);
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+
+            // Files with the size and modification time they were laid down with are not hashed again
+            let untouched = metadata.len() == laid_down.size
+                && laid_down.modified.is_some()
+                && metadata.modified().ok() == laid_down.modified;
+            if !metadata.is_file() || untouched {
+                continue;
+            }
+
+            let sha256 = manifest::hash_file(&path)?;
+            if sha256 != laid_down.sha256 && sha256 != entry.sha256 {
+                modified.push(relative.clone());
+            }
+        }
+
+        Ok(modified)
+    }
+
+    /// Turns the staged files into a version directory of their own and switches the current link to it
+    /// The running version is never written to, so a failure at any point leaves it as it was
+    /// Returns nothing when the update is declined
+    async fn switch_version(
+        &self,
+        staging: StagingDir,
+        journal: &Journal,
+        current_version: &BedrockVersion,
+        new_version: &BedrockVersion,
+    ) -> Result<Option<Manifest>> {
+        let staged = staging.path();
+
+        let preserved_dir = self.preserved_dir();
+        let preserved = self.plan_preserved(staged, |name| staged.join(name).exists())?;
+        let version_dir = layout::version_dir(&self.settings.server_dir, new_version)?;
+
+        if self.interactive
+            && !self
+                .confirm_switch(current_version, new_version, &version_dir, &preserved)
+                .await?
+        {
+            info!("Update declined, discarding extracted files");
+            staging.remove()?;
+            return Ok(None);
+        }
+
+        // The manifest only describes what the update shipped, not the files kept from the running version
+        let mut shipped = Vec::new();
+        for entry in fs::read_dir(staged)? {
+            let path = entry?.path();
+            if !preserved
+                .iter()
+                .any(|(_, destination)| *destination == path)
+            {
+                shipped.push(path);
+            }
+        }
+        let mut manifest = Manifest::build(new_version, staged, &shipped)?;
+        // Nested protected directories replace what the update ships inside them
+        for (_, destination) in &preserved {
+            if let Ok(relative) = destination.strip_prefix(staged) {
+                let prefix = format!("{}/", manifest::manifest_key(relative));
+                manifest.files.retain(|key, _| !key.starts_with(&prefix));
+            }
+        }
+
+        // Merged files get the new settings added to the running version's instead of being carried over as they are
+        let (merged, preserved): (Vec<_>, Vec<_>) =
+            preserved.into_iter().partition(|(source, destination)| {
+                let name = source.file_name().unwrap_or_default().to_string_lossy();
+                self.settings.strategies.for_path(Path::new(name.as_ref())) == Strategy::Merge
+                    && strategy::can_merge(&name)
+                    && source.is_file()
+                    && destination.is_file()
+            });
+        for (source, destination) in merged {
+            let added = properties::merge(&source, &destination, &destination)?;
+            debug!("Merged {} new settings into {destination:?}", added.len());
+        }
+
+        // Once the running version's files are carried over, the staged files aren't all the release's anymore
+        let released = released_files(staged)?;
+        self.carry_over(preserved, staged)?;
+        self.set_modes(staged, &released)?;
+        if let Some(owner) = self.settings.owner {
+            owner.apply_all(staged)?;
+        }
+
+        debug!("Moving the new version to {version_dir:?}");
+        staging.persist(&version_dir)?;
+        layout::dedup(&self.settings.server_dir, &version_dir, &manifest)?;
+        if self.settings.restore_contexts {
+            selinux::restore(std::slice::from_ref(&version_dir), true).await?;
+        }
+        manifest.record_laid_down(&version_dir)?;
+        journal.record(&Step::Switching {
+            version_dir: version_dir.clone(),
+        })?;
+        self.switch_to(&version_dir, &manifest)?;
+        if preserved_dir == self.settings.server_dir {
+            info!(
+                "The server now runs from {:?}, the copies of its files left in {:?} are no longer used",
+                self.settings.live_dir(),
+                self.settings.server_dir
+            );
+        }
+
+        Ok(Some(manifest))
+    }
+
+    /// Gives the files of the release in the directory the modes configured for them, then makes the server binary executable
+    /// Kept files like server.properties are set too, only the contents of protected directories belong to the operator
+    fn set_modes(&self, dir: &Path, released: &[String]) -> Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            for key in released {
+                let relative = Path::new(key);
+                let Some(mode) = self.settings.modes.for_path(relative) else {
+                    continue;
+                };
+                if self
+                    .settings
+                    .protected_dirs
+                    .containing(dir, relative)
+                    .is_some()
+                {
+                    continue;
+                }
+                let path = dir.join(relative);
+                let metadata = match fs::symlink_metadata(&path) {
+                    Ok(metadata) if metadata.is_file() => metadata,
+                    _ => continue,
+                };
+                if metadata.permissions().mode() & 0o7777 != mode {
+                    trace!("Setting the mode of {path:?} to {mode:o}");
+                    fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = released;
+
+        self.set_binary_mode(dir)
+    }
+
+    /// Makes the server binary in the directory executable
+    fn set_binary_mode(&self, dir: &Path) -> Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let binary = dir.join(detect::SERVER_BINARY);
+            if binary.is_file() {
+                debug!(
+                    "Setting the mode of {binary:?} to {:o}",
+                    self.settings.binary_mode
+                );
+                fs::set_permissions(
+                    &binary,
+                    fs::Permissions::from_mode(self.settings.binary_mode),
+                )?;
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = dir;
+
+        Ok(())
+    }
+
+    /// Records an update the policy does not allow, returning whether it still has to be approved
+    fn hold_for_approval(
+        &self,
+        current: &BedrockVersion,
+        latest: &BedrockVersion,
+        download_link: &Url,
+    ) -> Result<bool> {
+        let mut state = State::load(self.settings)?;
+
+        match &state.pending {
+            Some(pending) if pending.version == *latest && pending.approved => {
+                info!("Update to {latest} was approved");
+                return Ok(false);
+            }
+            // The discovery time of an update that is already waiting is kept
+            Some(pending) if pending.version == *latest => {}
+            _ => {
+                state.pending = Some(PendingUpdate {
+                    version: *latest,
+                    download_link: download_link.to_string(),
+                    discovered: SystemTime::now(),
+                    approved: false,
+                });
+                state.save(&self.settings.state_path)?;
+            }
+        }
+
+        info!(
+            "Update {current} -> {latest} is not allowed by the {:?} update policy, run the approve command to install it",
+            self.settings.update_policy
+        );
+        Ok(true)
+    }
+
+    async fn try_update(
+        &self,
+        current: &BedrockVersion,
+        latest: &BedrockVersion,
+        release: Release,
+        mode: UpdateMode,
+        state: &State,
+    ) -> Result<()> {
+        let version_span = info_span!("version_check");
+        let version_guard = version_span.enter();
+        trace!("Found server version: {current}");
+        trace!("Found latest version: {latest}");
+
+        // The program will only try to install the server if it is not up to date
+        if current == latest && mode != UpdateMode::Reinstall {
+            match mode {
+                UpdateMode::Scheduled => trace!("Server is up to date"),
+                UpdateMode::Check | UpdateMode::Install | UpdateMode::Reinstall => {
+                    info!("Server is up to date")
+                }
+            }
+            drop(version_guard);
+        } else if current > latest {
+            match self.settings.source.channel {
+                Channel::Release => info!("Server is most likely a preview version, make sure you set the correct version or use --channel preview"),
+                Channel::Preview => info!("Server is newer than the latest preview, make sure you set the correct version"),
+            }
+            drop(version_guard);
+        } else if self.settings.skip_versions.contains(latest) {
+            info!("Version {latest} is in the skip list, waiting for the next release");
+            drop(version_guard);
+        } else if state.broken.contains(latest) {
+            info!("Version {latest} failed the smoke test before, waiting for the next release");
+            drop(version_guard);
+        } else if mode == UpdateMode::Check {
+            info!("Update available: {current} -> {latest}");
+            drop(version_guard);
+        } else if let Some(pinned) = state.pinned.filter(|_| mode == UpdateMode::Scheduled) {
+            // A version installed on purpose stays until someone installs another one
+            info!("Server is pinned to {pinned}, run the install command to update to {latest}");
+            drop(version_guard);
+        } else if mode == UpdateMode::Scheduled
+            && !self.settings.update_policy.allows(current, latest)
+            && self.hold_for_approval(current, latest, &release.link)?
+        {
+            trace!("Update held for approval");
+            drop(version_guard);
+        } else if mode == UpdateMode::Scheduled && !self.is_stable(latest)? {
+            drop(version_guard);
+        } else if mode == UpdateMode::Scheduled && self.settings.pause_path.exists() {
+            // Admins can freeze updates without stopping the updater by creating the pause file
+            info!("Server is not up to date, but updates are paused");
+            drop(version_guard);
+        } else {
+            match current == latest {
+                true => info!("Reinstalling version {latest}"),
+                false => info!("Server is not up to date"),
+            }
+            drop(version_guard);
+            let install_span = info_span!("install_phase");
+            let install_guard = install_span.enter();
+
+            let started = SystemTime::now();
+            let timer = Instant::now();
+            let result = self.download_and_install(current, latest, release).await;
+            self.record_attempt(current, latest, started, timer, &result);
+
+            if result? {
+                State::update(self.settings, |state| {
+                    state.pending = None;
+                    state.pinned = None;
+                })?;
+            }
+            drop(install_guard);
+        }
+
+        Ok(())
+    }
+
+    /// Adds an install attempt to the update history
+    /// Failing to record it is only logged, since the install itself already happened
+    fn record_attempt(
+        &self,
+        old_version: &BedrockVersion,
+        new_version: &BedrockVersion,
+        started: SystemTime,
+        timer: Instant,
+        result: &Result<bool>,
+    ) {
+        let (outcome, error) = match result {
+            Ok(true) => (Outcome::Installed, None),
+            Ok(false) => (Outcome::Declined, None),
+            Err(err) => (Outcome::Failed, Some(err.to_string())),
+        };
+
+        let record = UpdateRecord {
+            old_version: old_version.to_string(),
+            new_version: new_version.to_string(),
+            started,
+            duration: timer.elapsed(),
+            bytes: Some(self.zip_size.swap(0, Ordering::Relaxed)).filter(|bytes| *bytes > 0),
+            source: self.zip_source.lock().unwrap().take(),
+            outcome,
+            error,
+        };
+
+        if let Err(err) = history::append(&self.settings.state_path, &record) {
+            warn!("Could not record the update in the history: {err}");
+        }
+    }
+
+    /// Reads the installed version for commands that install a chosen version instead of the latest
+    fn read_current_version(&self) -> Result<BedrockVersion> {
+        State::load(self.settings)?
+            .current_version
+            .ok_or(BedrockUpdaterError::NoCurrentVersion)
+    }
+
+    /// Reinstalls an archived version through the normal install pipeline
+    /// Without a target version, the newest archived version older than the current one is used
+    #[tracing::instrument(skip_all)]
+    pub async fn rollback(&self, target: Option<BedrockVersion>) -> Result<()> {
+        let current = self.read_current_version()?;
+
+        // A version that is still on disk only needs the current link switched back to it
+        if self.settings.layout == InstallLayout::Versioned {
+            let running = layout::current_target(&self.settings.server_dir);
+            let mut retained: Vec<RetainedVersion> = layout::retained(&self.settings.server_dir)?
+                .into_iter()
+                .filter(|retained| Some(&retained.path) != running.as_ref())
+                .collect();
+            retained.sort_by_key(|retained| retained.version);
+
+            let found = match target {
+                Some(target) => retained
+                    .into_iter()
+                    .rev()
+                    .find(|retained| retained.version == target),
+                None => retained
+                    .into_iter()
+                    .rev()
+                    .find(|retained| retained.version < current),
+            };
+            if let Some(retained) = found {
+                info!(
+                    "Rolling back from {current} to {}, which is still in {:?}",
+                    retained.version, retained.path
+                );
+                let version = retained.version;
+                self.switch_retained(&current, retained)?;
+                return self.pin(&version);
+            }
+        }
+
+        let mut archives = archive::list(&self.settings.archive_dir)?;
+        let index = match target {
+            Some(target) => archives
+                .iter()
+                .position(|archived| archived.version == target)
+                .ok_or_else(|| BedrockUpdaterError::NotArchived(target.to_string()))?,
+            None => archives
+                .iter()
+                .rposition(|archived| archived.version < current)
+                .ok_or(BedrockUpdaterError::NoRollbackTarget)?,
+        };
+        let archived = archives.swap_remove(index);
+
+        info!("Rolling back from {current} to {}", archived.version);
+        self.install_archived(&current, archived).await
+    }
+
+    /// Switches back to a version directory, carrying over the files of the running version first
+    fn switch_retained(&self, current: &BedrockVersion, retained: RetainedVersion) -> Result<()> {
+        let version = retained.version;
+        let started = SystemTime::now();
+        let timer = Instant::now();
+
+        let result = (|| {
+            let shipped = retained.manifest.shipped();
+            let preserved = self.plan_preserved(&retained.path, |name| shipped.contains(name))?;
+            self.carry_over(preserved, &retained.path)?;
+            self.switch_to(&retained.path, &retained.manifest)?;
+
+            State::update(self.settings, |state| {
+                state.current_version = Some(version);
+                state.installed_at = Some(SystemTime::now());
+                state.source = Some(retained.path.display().to_string());
+            })?;
+            retained.manifest.save(&self.settings.state_path)?;
+            Status::update(&self.settings.server_dir, |status| {
+                status.last_update = Some(SystemTime::now())
+            })?;
+
+            Ok(true)
+        })();
+        self.record_attempt(current, &version, started, timer, &result);
+
+        result?;
+        info!("Installed version {version}");
+        Ok(())
+    }
+
+    /// Installs a cached build of any version, newer and older ones included, without downloading anything
+    #[tracing::instrument(skip_all)]
+    pub async fn reinstall(&self, version: BedrockVersion) -> Result<()> {
+        let current = self.read_current_version()?;
+
+        let archived = archive::find(&self.settings.archive_dir, &version, None)?
+            .ok_or_else(|| BedrockUpdaterError::NotArchived(version.to_string()))?;
+
+        info!("Reinstalling cached version {version} over {current}");
+        self.install_archived(&current, archived).await
+    }
+
+    /// Installs an archived zip on purpose, pinning the server to it afterwards
+    async fn install_archived(
+        &self,
+        current: &BedrockVersion,
+        archived: ArchivedVersion,
+    ) -> Result<()> {
+        let version = archived.version;
+        let started = SystemTime::now();
+        let timer = Instant::now();
+
+        let source = archived.path.display().to_string();
+        let result = self
+            .install_server(&archived.path, &source, current, &version)
+            .await;
+        self.record_attempt(current, &version, started, timer, &result);
+
+        if result? {
+            self.pin(&version)?;
+            info!("Installed version {version}");
+        }
+
+        Ok(())
+    }
+
+    /// Installs a specific version, older or the same as the current one included
+    /// The zip comes from the archive when it is there, otherwise from the version url
+    #[tracing::instrument(skip_all)]
+    pub async fn install_target(&self, version: BedrockVersion) -> Result<()> {
+        let current = self.read_current_version()?;
+
+        info!("Installing version {version} over {current}");
+        let started = SystemTime::now();
+        let timer = Instant::now();
+
+        let archived = archive::list(&self.settings.archive_dir)?
+            .into_iter()
+            .find(|archived| archived.version == version);
+
+        let result = match archived {
+            Some(archived) => {
+                info!("Using archived zip {:?}", archived.path);
+                let source = archived.path.display().to_string();
+                self.install_server(&archived.path, &source, &current, &version)
+                    .await
+            }
+            None => match source::version_download_link(&self.settings.source, &version) {
+                Ok(download_link) => {
+                    self.download_and_install(&current, &version, download_link.into())
+                        .await
+                }
+                Err(err) => Err(err),
+            },
+        };
+        self.record_attempt(&current, &version, started, timer, &result);
+
+        if result? {
+            self.pin(&version)?;
+            info!("Installed version {version}");
+        }
+
+        Ok(())
+    }
+
+    /// Installs a zip from disk, for servers that cannot reach any download server
+    /// The zip is copied rather than moved into the archive, so the original stays where it is
+    #[tracing::instrument(skip_all)]
+    pub async fn install_zip(&self, zip: &Path) -> Result<()> {
+        let current = self.read_current_version()?;
+        zip.is_file()
+            .else_err(BedrockUpdaterError::NotAFile(zip.to_owned()))?;
+
+        let file_name = zip
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or(BedrockUpdaterError::NoFileName)?;
+        let version = BedrockVersion::from_file_name(file_name)?;
+        let link = Url::from_file_path(std::path::absolute(zip)?)
+            .map_err(|_| BedrockUpdaterError::NotAFile(zip.to_owned()))?;
+
+        info!("Installing version {version} over {current} from {zip:?}");
+        let started = SystemTime::now();
+        let timer = Instant::now();
+
+        // With the checksum known, only an identical build from the archive is used in place of the zip
+        let release = Release {
+            link,
+            sha256: Some(manifest::hash_file(zip)?),
+            version: Some(version),
+        };
+        let result = self.download_and_install(&current, &version, release).await;
+        self.record_attempt(&current, &version, started, timer, &result);
+
+        if result? {
+            self.pin(&version)?;
+            info!("Installed version {version}");
+        }
+
+        Ok(())
+    }
+
+    /// Keeps scheduled updates from replacing a version that was installed on purpose
+    fn pin(&self, version: &BedrockVersion) -> Result<()> {
+        info!("Pinning the server to {version}, scheduled updates resume after the next install");
+        State::update(self.settings, |state| {
+            state.pinned = Some(*version);
+            state.pending = None;
+        })
+    }
+
+    /// Downloads the new server and installs it, returning whether it was actually installed
+    async fn download_and_install(
+        &self,
+        current: &BedrockVersion,
+        latest: &BedrockVersion,
+        release: Release,
+    ) -> Result<bool> {
+        let Release {
+            link: download_link,
+            sha256,
+            ..
+        } = release;
+        compat::check_host(self.settings)?;
+
+        // A build that was installed before is already in the archive
+        if let Some(archived) =
+            archive::find(&self.settings.archive_dir, latest, sha256.as_deref())?
+        {
+            info!(
+                "Using cached zip {:?} instead of downloading it again",
+                archived.path
+            );
+            let source = archived.path.display().to_string();
+            return self
+                .install_server(&archived.path, &source, current, latest)
+                .await;
+        }
+
+        match download_link.scheme() {
+            "file" => info!("Copying server zip from disk"),
+            _ => info!("Downloading new server version"),
+        }
+        let bedrock_server_zip = self.settings.server_dir.join(DOWNLOAD_FILE);
+        let fetched = download::fetch(
+            self.client,
+            &self.settings.source,
+            &download_link,
+            sha256.as_deref(),
+            &bedrock_server_zip,
+            Some(&self.settings.server_dir.join(QUARANTINE_DIR)),
+            self.shutdown,
+        )
+        .await;
+
+        // A corrupted download is thrown away below without touching the server
+        let (installed, served_by) = match fetched {
+            Ok(served_by) => {
+                let source = served_by.to_string();
+                let installed =
+                    Self::install_server(self, &bedrock_server_zip, &source, current, latest).await;
+                (installed, Some(served_by))
+            }
+            Err(err) => (Err(err), None),
+        };
+
+        // A zip that doesn't extract or isn't a server is kept, since it shows what the server actually sent
+        if let (
+            Err(
+                err @ (BedrockUpdaterError::ServerZipExtractFailed(_)
+                | BedrockUpdaterError::ServerTarExtractFailed(_)
+                | BedrockUpdaterError::CorruptArchiveEntry(..)
+                | BedrockUpdaterError::UnexpectedArchive(_)),
+            ),
+            Some(served_by),
+        ) = (&installed, &served_by)
+        {
+            let quarantine_dir = self.settings.server_dir.join(QUARANTINE_DIR);
+            quarantine::store_or_remove(
+                Some(&quarantine_dir),
+                &bedrock_server_zip,
+                served_by,
+                err,
+            )?;
+        }
+
+        // Keeping the zip around is what makes rolling back to this version possible later
+        let archived = match installed {
+            Ok(true) => archive::store(
+                &self.settings.archive_dir,
+                latest,
+                &bedrock_server_zip,
+                self.settings.keep_archives,
+            ),
+            _ => Ok(()),
+        };
+
+        if bedrock_server_zip.exists() {
+            fs::remove_file(&bedrock_server_zip)?;
+        }
+        archived?;
+
+        installed
+    }
+
+    /// Saves the latest server zip into the output directory without touching any server directory
+    #[tracing::instrument(skip_all)]
+    pub async fn download_latest(
+        client: &Client,
+        output: &Path,
+        source: &SourceSettings,
+        shutdown: &CancellationToken,
+    ) -> Result<PathBuf> {
+        let Release {
+            link: download_link,
+            sha256,
+            version,
+        } = source::latest_release(client, source, None).await?;
+
+        // The download link already names the file after the version, e.g. bedrock-server-1.21.0.3.zip
+        let file_name = download_link
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|name| !name.is_empty())
+            .ok_or(BedrockUpdaterError::NoFileName)?
+            .to_owned();
+        let version = match version {
+            Some(version) => version,
+            None => Self::get_latest_version(&file_name).await?,
+        };
+
+        info!("Downloading version {version}");
+        fs::create_dir_all(output)?;
+        let path = output.join(&file_name);
+        // The zip only gets its real name once it is complete
+        let partial = output.join(format!("{file_name}.part"));
+        if let Err(err) = download::fetch(
+            client,
+            source,
+            &download_link,
+            sha256.as_deref(),
+            &partial,
+            None,
+            shutdown,
+        )
+        .await
+        {
+            if partial.exists() && !matches!(err, BedrockUpdaterError::Cancelled) {
+                fs::remove_file(&partial)?;
+            }
+            return Err(err);
+        }
+        fs::rename(&partial, &path)?;
+
+        Ok(path)
+    }
+
+    /// Shows what installing the latest release would change, downloading it only when the archive doesn't have it
+    /// Nothing is kept, the zip and the extracted files are removed again
+    #[tracing::instrument(skip_all)]
+    pub async fn diff(&self) -> Result<()> {
+        let settings = self.settings;
+        let Release {
+            link,
+            sha256,
+            version,
+        } = source::latest_release(
+            self.client,
+            &settings.source,
+            Some(&settings.server_dir.join(source::HTTP_CACHE_FILE)),
+        )
+        .await?;
+        let latest = match settings.source.simulate_latest.or(version) {
+            Some(version) => version,
+            None => Self::get_latest_version(link.path()).await?,
+        };
+
+        // Dropping it removes the download and the extracted files
+        // Nothing is installed from it, so it stays out of the server directory, which may not be writable
+        let staging = StagingDir::create(&staging::temp_root())?;
+        let zip = match archive::find(&settings.archive_dir, &latest, sha256.as_deref())? {
+            Some(archived) => {
+                info!("Using cached zip {:?}", archived.path);
+                archived.path
+            }
+            None => {
+                info!("Downloading version {latest} to compare it");
+                let zip = staging.path().join(DOWNLOAD_FILE);
+                download::fetch(
+                    self.client,
+                    &settings.source,
+                    &link,
+                    sha256.as_deref(),
+                    &zip,
+                    None,
+                    self.shutdown,
+                )
+                .await?;
+                zip
+            }
+        };
+
+        let extracted = staging.path().join("extracted");
+        fs::create_dir(&extracted)?;
+        extract::extract(&zip, &extracted, &settings.filter).await?;
+        diff::report(settings, &extracted, &latest)?;
+
+        staging.remove()
+    }
+
+    /// Runs a single update cycle and records its outcome in the status file
+    pub async fn run_updater(&self, mode: UpdateMode) -> Result<()> {
+        let result = self.update_once(mode).await;
+
+        let now = SystemTime::now();
+        let recorded = Status::update(&self.settings.server_dir, |status| {
+            status.last_check = Some(now);
+            // Only rate limiting in a row is counted, anything else means the server answered normally
+            match &result {
+                Err(BedrockUpdaterError::Throttled(_)) => status.consecutive_throttles += 1,
+                _ => status.consecutive_throttles = 0,
+            }
+
+            match &result {
+                Ok(()) => status.last_success = Some(now),
+                // Shutting down in the middle of a check is not a failure worth reporting
+                Err(BedrockUpdaterError::Cancelled) => {}
+                Err(err) => {
+                    status.last_error = Some(LastError {
+                        time: now,
+                        message: err.to_string(),
+                    })
+                }
+            }
+        });
+
+        if let Err(err) = recorded {
+            warn!("Could not record the updater status: {err}");
+        }
+
+        result
+    }
+
+    async fn update_once(&self, mode: UpdateMode) -> Result<()> {
+        // The path part of the Url is necessary to get the filename
+        // This is so to prevent version strings from being parsed in the url if they are ever added
+        let release = source::latest_release(
+            self.client,
+            &self.settings.source,
+            Some(&self.settings.server_dir.join(source::HTTP_CACHE_FILE)),
+        )
+        .await?;
+
+        // The clone is necessary
+        // The function will not be able to move download_link since it gets borrowed when calling .path()
+        let cloned_download_link = release.link.clone();
+
+        self.settings
+            .server_dir
+            .exists()
+            .else_err(BedrockUpdaterError::NoServerPath)?;
+
+        trace!("Attempting to get state file version");
+        let mut state = State::load(self.settings)?;
+        if state.current_version.is_none() && self.set_first_version.is_none() {
+            state.current_version = self.detect_current_version().await?;
+        }
+
+        // A simulated version wins over whatever the simulated zip is called, and a published one over the zip's name
+        let latest_file = match self.settings.source.simulate_latest.or(release.version) {
+            Some(version) => version.to_string(),
+            None => cloned_download_link.path().to_owned(),
+        };
+        let (current, latest) =
+            Self::get_versions(self, &latest_file, state.current_version).await?;
+
+        // Checks also run without write access to the server directory
+        let recorded = Status::update(&self.settings.server_dir, |status| {
+            status.latest_version = Some(latest.to_string())
+        });
+        if let Err(err) = recorded {
+            warn!("Could not record the latest version: {err}");
+        }
+
+        if self.check_running_version(&current).await {
+            self.check_started_worlds(&current, state.worlds_unchecked)?;
+        }
+
+        self.check_cancelled()?;
+
+        Self::try_update(self, &current, &latest, release, mode, &state).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{args::Args, config::Config};
+
+    fn settings(test: &str) -> Settings {
+        let server_dir = std::env::temp_dir().join(format!(
+            "bedrock-updater-recover-{test}-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&server_dir).unwrap();
+        let args = Args {
+            server_dir: Some(server_dir),
+            ..Default::default()
+        };
+
+        Settings::merge(&args, Config::default()).unwrap()
+    }
+
+    fn to() -> BedrockVersion {
+        "1.21.50.7".parse().unwrap()
+    }
+
+    fn staging(settings: &Settings) -> PathBuf {
+        settings.update_dir.join("run-1-00000000")
+    }
+
+    /// Leaves the journal of an install by the process that was killed after the steps
+    fn interrupted(settings: &Settings, pid: u32, started: Option<String>, steps: &[Step]) {
+        let first = Step::Started {
+            pid,
+            started,
+            from: "1.21.44.1".parse().unwrap(),
+            to: to(),
+            source: String::from("https://www.minecraft.net/bedrock-server-1.21.50.7.zip"),
+            checksum: String::new(),
+            staging: staging(settings),
+        };
+        let journal = Journal::start(&settings.state_path, &first).unwrap();
+        for step in steps {
+            journal.record(step).unwrap();
+        }
+        // A killed run never gets to drop its journal
+        std::mem::forget(journal);
+    }
+
+    /// Same, but this process stands in for the one that is gone
+    fn interrupted_after(settings: &Settings, steps: &[Step]) {
+        interrupted(settings, process::id(), None, steps);
+    }
+
+    async fn recover(settings: &Settings) -> Result<()> {
+        let client = Client::new();
+        let shutdown = CancellationToken::new();
+
+        BedrockUpdater::new(&client, settings, None, false, &shutdown)
+            .recover()
+            .await
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn journals_of_running_installs_are_left_alone() {
+        let settings = settings("running");
+        let parent = std::os::unix::process::parent_id();
+        interrupted(&settings, parent, daemon::started(parent), &[Step::Copying]);
+
+        let recovered = recover(&settings).await;
+        let kept = Journal::interrupted(&settings.state_path).unwrap();
+        fs::remove_dir_all(&settings.server_dir).unwrap();
+
+        recovered.unwrap();
+        assert!(kept.is_some());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn journals_of_reused_pids_are_recovered() {
+        let settings = settings("reused");
+        // The pid is running, but it is a different process than the one that was installing
+        interrupted(
+            &settings,
+            std::os::unix::process::parent_id(),
+            Some(String::from("00000000.0")),
+            &[Step::Copying],
+        );
+
+        let recovered = recover(&settings).await;
+        let kept = Journal::interrupted(&settings.state_path).unwrap();
+        fs::remove_dir_all(&settings.server_dir).unwrap();
+
+        assert!(matches!(
+            recovered,
+            Err(BedrockUpdaterError::InterruptedInstall(..))
+        ));
+        assert!(kept.is_none());
+    }
+
+    #[tokio::test]
+    async fn installs_that_did_not_touch_the_server_are_discarded() {
+        let settings = settings("untouched");
+        fs::create_dir_all(staging(&settings)).unwrap();
+        interrupted_after(&settings, &[Step::Extracted]);
+
+        let recovered = recover(&settings).await;
+        let kept = Journal::interrupted(&settings.state_path).unwrap();
+        let staged = staging(&settings).exists();
+        let state = State::load(&settings).unwrap();
+        fs::remove_dir_all(&settings.server_dir).unwrap();
+
+        recovered.unwrap();
+        assert!(kept.is_none());
+        assert!(!staged);
+        assert_eq!(state.current_version, None);
+    }
+
+    #[tokio::test]
+    async fn installs_interrupted_while_copying_are_resumed() {
+        let settings = settings("copying");
+        let staging = staging(&settings);
+        fs::create_dir_all(staging.join("behavior_packs/vanilla")).unwrap();
+        fs::write(staging.join("bedrock_server"), "new").unwrap();
+        fs::write(staging.join("behavior_packs/vanilla/manifest.json"), "{}").unwrap();
+        fs::write(settings.server_dir.join("bedrock_server"), "old").unwrap();
+        interrupted_after(&settings, &[Step::Extracted, Step::Copying]);
+
+        let recovered = recover(&settings).await;
+        let kept = Journal::interrupted(&settings.state_path).unwrap();
+        let binary = fs::read_to_string(settings.server_dir.join("bedrock_server")).unwrap();
+        let state = State::load(&settings).unwrap();
+        fs::remove_dir_all(&settings.server_dir).unwrap();
+
+        recovered.unwrap();
+        assert!(kept.is_none());
+        assert_eq!(binary, "new");
+        assert_eq!(state.current_version, Some(to()));
+    }
+
+    #[tokio::test]
+    async fn installs_without_their_staged_files_can_not_be_resumed() {
+        let settings = settings("no-staging");
+        interrupted_after(&settings, &[Step::Extracted, Step::Copying]);
+
+        let recovered = recover(&settings).await;
+        let kept = Journal::interrupted(&settings.state_path).unwrap();
+        fs::remove_dir_all(&settings.server_dir).unwrap();
+
+        assert!(matches!(
+            recovered,
+            Err(BedrockUpdaterError::InterruptedInstall(..))
+        ));
+        assert!(kept.is_none());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn installs_interrupted_while_switching_are_kept_if_the_link_moved() {
+        for switched in [false, true] {
+            let settings = settings(&format!("switching-{switched}"));
+            let version_dir = settings
+                .server_dir
+                .join(layout::VERSIONS_DIR)
+                .join("1.21.50.7");
+            fs::create_dir_all(&version_dir).unwrap();
+            let manifest = Manifest {
+                version: to(),
+                files: Default::default(),
+            };
+            fs::write(
+                version_dir.join(layout::VERSION_MANIFEST),
+                serde_json::to_vec(&manifest).unwrap(),
+            )
+            .unwrap();
+            if switched {
+                std::os::unix::fs::symlink(
+                    &version_dir,
+                    settings.server_dir.join(layout::CURRENT_LINK),
+                )
+                .unwrap();
+            }
+            let switching = Step::Switching {
+                version_dir: version_dir.clone(),
+            };
+            interrupted_after(&settings, &[Step::Extracted, switching]);
+
+            let recovered = recover(&settings).await;
+            let kept = Journal::interrupted(&settings.state_path).unwrap();
+            let state = State::load(&settings).unwrap();
+            fs::remove_dir_all(&settings.server_dir).unwrap();
+
+            recovered.unwrap();
+            assert!(kept.is_none());
+            assert_eq!(state.current_version, switched.then(to));
+        }
+    }
+
+    #[tokio::test]
+    async fn installs_interrupted_while_cleaning_up_are_finished() {
+        let settings = settings("state-written");
+        fs::create_dir_all(staging(&settings)).unwrap();
+        interrupted_after(
+            &settings,
+            &[Step::Extracted, Step::Copying, Step::StateWritten],
+        );
+
+        let recovered = recover(&settings).await;
+        let kept = Journal::interrupted(&settings.state_path).unwrap();
+        let staged = staging(&settings).exists();
+        fs::remove_dir_all(&settings.server_dir).unwrap();
+
+        recovered.unwrap();
+        assert!(kept.is_none());
+        assert!(!staged);
     }
 }
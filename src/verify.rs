@@ -0,0 +1,82 @@
+use std::{collections::BTreeSet, path::Path};
+
+use tracing::info;
+
+use crate::{
+    config::Settings,
+    error::{BedrockUpdaterError, Result},
+    manifest::{self, Manifest},
+};
+
+/// Compares the server directory with the manifest written by the last install
+/// Returns whether everything matched
+pub fn verify(settings: &Settings) -> Result<bool> {
+    let manifest = Manifest::load(&settings.state_path)?.ok_or(BedrockUpdaterError::NoManifest)?;
+
+    info!(
+        "Verifying {} files of version {}",
+        manifest.files.len(),
+        manifest.version
+    );
+
+    let mut missing = Vec::new();
+    let mut modified = Vec::new();
+    for (relative, entry) in &manifest.files {
+        let path = settings.live_dir().join(relative);
+
+        if !path.is_file() {
+            missing.push(relative);
+        } else if manifest::hash_file(&path)? != entry.sha256 {
+            modified.push(relative);
+        }
+    }
+
+    // Only directories the install laid down are checked for extra files
+    // Anything else at the top level, like worlds, belongs to the operator
+    let installed_dirs: BTreeSet<&str> = manifest
+        .files
+        .keys()
+        .filter_map(|relative| relative.split_once('/').map(|(dir, _)| dir))
+        .collect();
+
+    let live_dir = settings.live_dir();
+    let mut extra = Vec::new();
+    for dir in installed_dirs {
+        let dir = live_dir.join(dir);
+        if !dir.is_dir() {
+            continue;
+        }
+
+        for file in manifest::files_below(&dir)? {
+            let relative =
+                manifest::manifest_key(file.strip_prefix(&live_dir).unwrap_or(Path::new(&file)));
+            // The operator's data in protected directories was never the install's
+            let protected = settings
+                .protected_dirs
+                .containing(&live_dir, Path::new(&relative))
+                .is_some();
+            if !manifest.files.contains_key(&relative) && !protected {
+                extra.push(relative);
+            }
+        }
+    }
+
+    for relative in &missing {
+        println!("  missing  {relative}");
+    }
+    for relative in &modified {
+        println!("  modified {relative}");
+    }
+    for relative in &extra {
+        println!("  extra    {relative}");
+    }
+
+    println!(
+        "{} missing, {} modified, {} extra",
+        missing.len(),
+        modified.len(),
+        extra.len()
+    );
+
+    Ok(missing.is_empty() && modified.is_empty() && extra.is_empty())
+}
@@ -0,0 +1,221 @@
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::{BedrockUpdaterError, Result};
+
+/// A bedrock server version like 1.21.44.1
+/// Preview builds carry an extra build number, e.g. 1.21.50.24.1
+/// Versions are compared by their numbers, so 1.21.44.01 and 1.21.44.1 are the same version
+#[derive(Debug, Clone, Copy)]
+pub struct BedrockVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    pub build: u32,
+    pub preview: Option<u32>,
+    /// Digits each part was written with, download urls and file names use the padded form
+    widths: [u8; 5],
+}
+
+impl BedrockVersion {
+    /// Finds the version in the name of a server zip, e.g. bedrock-server-1.21.44.1.zip
+    pub fn from_file_name(file_name: &str) -> Result<Self> {
+        let pattern = Regex::new(r"\d+(\.\d+){2,4}")?;
+
+        pattern
+            .find(file_name)
+            .ok_or(BedrockUpdaterError::NoVersionString)?
+            .as_str()
+            .parse()
+    }
+
+    /// Whether both are builds of the same release, e.g. 1.21.44.1 and 1.21.44.3
+    pub fn same_release(&self, other: &Self) -> bool {
+        (self.major, self.minor, self.patch) == (other.major, other.minor, other.patch)
+    }
+
+    /// The numbers from most to least significant, which is the order versions compare in
+    fn key(&self) -> (u32, u32, u32, u32, Option<u32>) {
+        (self.major, self.minor, self.patch, self.build, self.preview)
+    }
+}
+
+impl PartialEq for BedrockVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for BedrockVersion {}
+
+impl PartialOrd for BedrockVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BedrockVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl Hash for BedrockVersion {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// Accepts 3 to 5 numeric parts, padded parts like 1.21.44.01 included
+/// A missing build number counts as 0
+impl FromStr for BedrockVersion {
+    type Err = BedrockUpdaterError;
+
+    fn from_str(version: &str) -> Result<Self> {
+        let unparseable = || BedrockUpdaterError::UnparseableVersion(version.to_owned());
+
+        let parts = version
+            .trim()
+            .split('.')
+            .map(
+                |part| match part.bytes().all(|byte| byte.is_ascii_digit()) {
+                    true => part.parse::<u32>().map_err(|_| unparseable()),
+                    false => Err(unparseable()),
+                },
+            )
+            .collect::<Result<Vec<u32>>>()?;
+
+        let mut widths = [1; 5];
+        for (width, part) in widths.iter_mut().zip(version.trim().split('.')) {
+            *width = u8::try_from(part.len()).unwrap_or(u8::MAX);
+        }
+
+        match parts[..] {
+            [major, minor, patch] => Ok(Self {
+                major,
+                minor,
+                patch,
+                build: 0,
+                preview: None,
+                widths,
+            }),
+            [major, minor, patch, build] => Ok(Self {
+                major,
+                minor,
+                patch,
+                build,
+                preview: None,
+                widths,
+            }),
+            [major, minor, patch, build, preview] => Ok(Self {
+                major,
+                minor,
+                patch,
+                build,
+                preview: Some(preview),
+                widths,
+            }),
+            _ => Err(unparseable()),
+        }
+    }
+}
+
+impl fmt::Display for BedrockVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [major, minor, patch, build, preview] = self.widths.map(usize::from);
+        write!(
+            f,
+            "{:0major$}.{:0minor$}.{:0patch$}.{:0build$}",
+            self.major, self.minor, self.patch, self.build
+        )?;
+        if let Some(number) = self.preview {
+            write!(f, ".{number:0preview$}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Stored as the usual dotted string, so files written before this type existed still load
+impl Serialize for BedrockVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for BedrockVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(version: &str) -> BedrockVersion {
+        version.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_releases_and_previews() {
+        let release = version("1.21.44.1");
+        assert_eq!(
+            (release.major, release.minor, release.patch, release.build),
+            (1, 21, 44, 1)
+        );
+        assert_eq!(release.preview, None);
+        assert_eq!(version("1.21.50.24.1").preview, Some(1));
+        assert_eq!(version("1.21.44"), version("1.21.44.0"));
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        for invalid in [
+            "",
+            "1.21",
+            "1.21.44.1.2.3",
+            "1.21.x.1",
+            "1.-21.44",
+            "1..44.1",
+        ] {
+            assert!(invalid.parse::<BedrockVersion>().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn padding_is_kept_but_does_not_change_the_version() {
+        for padded in ["1.21.44.01", "1.21.50.24.01", "01.21.044.1"] {
+            assert_eq!(version(padded).to_string(), padded);
+        }
+        assert_eq!(version("1.21.44").to_string(), "1.21.44.0");
+        assert_eq!(version("1.21.44.01"), version("1.21.44.1"));
+    }
+
+    #[test]
+    fn compares_by_numbers() {
+        assert!(version("1.21.44.1") < version("1.21.44.10"));
+        assert!(version("1.21.44.9") < version("1.21.50.1"));
+        assert!(version("1.21.50.24") < version("1.21.50.24.1"));
+    }
+
+    #[test]
+    fn finds_the_version_in_file_names() {
+        assert_eq!(
+            BedrockVersion::from_file_name("bedrock-server-1.21.44.01.zip")
+                .unwrap()
+                .to_string(),
+            "1.21.44.01"
+        );
+        assert!(BedrockVersion::from_file_name("bedrock-server.zip").is_err());
+    }
+}
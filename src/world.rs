@@ -0,0 +1,416 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use tracing::debug;
+
+/// Directory of the server directory the worlds are saved in, one directory each
+pub const WORLDS_DIR: &str = "worlds";
+
+/// LevelDB logs, the manifest among them, are written in blocks of this size
+const LOG_BLOCK_SIZE: usize = 32 * 1024;
+
+/// Checksum, length and type in front of every log record
+const LOG_HEADER_SIZE: usize = 7;
+
+/// Last eight bytes of every LevelDB table file
+const TABLE_MAGIC: u64 = 0xdb47_7524_8b80_fb57;
+
+/// NBT nests compounds and lists, a real level.dat is nowhere near this deep
+const MAX_NBT_DEPTH: usize = 512;
+
+/// Checks every world in the server directory without writing to any of them
+/// Returns one line for each corrupt world, saying what is wrong with it
+pub fn check_all(server_dir: &Path) -> io::Result<Vec<String>> {
+    let worlds_dir = server_dir.join(WORLDS_DIR);
+    if !worlds_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut problems = Vec::new();
+    for entry in fs::read_dir(&worlds_dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let name = entry.file_name().to_string_lossy().into_owned();
+        match check_world(&entry.path()) {
+            Ok(true) => debug!("World {name:?} is intact"),
+            Ok(false) => debug!("{:?} is not a world, skipping it", entry.path()),
+            Err(problem) => problems.push(format!("world {name:?} {problem}")),
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Returns whether the directory is a world at all, a directory with neither a level.dat nor a database isn't one
+fn check_world(dir: &Path) -> Result<bool, String> {
+    let level_dat = dir.join("level.dat");
+    let db = dir.join("db");
+    match (level_dat.is_file(), db.is_dir()) {
+        (false, false) => return Ok(false),
+        (false, true) => return Err(String::from("has no level.dat")),
+        (true, _) => check_level_dat(&level_dat)?,
+    }
+    if db.is_dir() {
+        check_db(&db)?;
+    }
+
+    Ok(true)
+}
+
+/// level.dat is a little endian NBT compound behind a header with its length
+fn check_level_dat(path: &Path) -> Result<(), String> {
+    let data = fs::read(path).map_err(|err| format!("has an unreadable level.dat: {err}"))?;
+    let damaged = |what: &str| format!("has a damaged level.dat, {what}");
+
+    let length = data
+        .get(4..8)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap_or_default()) as usize)
+        .ok_or_else(|| damaged("its header is cut off"))?;
+    if length != data.len() - 8 {
+        return Err(damaged(&format!(
+            "its header says {length} bytes but {} follow",
+            data.len() - 8
+        )));
+    }
+
+    let mut nbt = Nbt {
+        data: &data[8..],
+        offset: 0,
+    };
+    match nbt.byte() {
+        Some(10) => {}
+        _ => return Err(damaged("it does not start with a compound tag")),
+    }
+    nbt.string()
+        .and_then(|()| nbt.payload(10, 0))
+        .ok_or_else(|| {
+            damaged(&format!(
+                "its tags stop making sense at byte {}",
+                nbt.offset + 8
+            ))
+        })?;
+    if nbt.offset != nbt.data.len() {
+        return Err(damaged("there is data after its root tag"));
+    }
+
+    Ok(())
+}
+
+/// Walks NBT tags without keeping them, only to see that they are well formed
+struct Nbt<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl Nbt<'_> {
+    fn skip(&mut self, len: usize) -> Option<()> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())?;
+        self.offset = end;
+
+        Some(())
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + N)?
+            .try_into()
+            .ok()?;
+        self.offset += N;
+
+        Some(bytes)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        self.bytes::<1>().map(|[byte]| byte)
+    }
+
+    /// Lengths of arrays and lists, which are never negative in a valid file
+    fn len(&mut self) -> Option<usize> {
+        usize::try_from(i32::from_le_bytes(self.bytes()?)).ok()
+    }
+
+    fn string(&mut self) -> Option<()> {
+        let len = u16::from_le_bytes(self.bytes()?);
+        self.skip(usize::from(len))
+    }
+
+    fn payload(&mut self, tag: u8, depth: usize) -> Option<()> {
+        if depth > MAX_NBT_DEPTH {
+            return None;
+        }
+
+        match tag {
+            1 => self.skip(1),
+            2 => self.skip(2),
+            3 | 5 => self.skip(4),
+            4 | 6 => self.skip(8),
+            7 => {
+                let len = self.len()?;
+                self.skip(len)
+            }
+            8 => self.string(),
+            9 => {
+                let element = self.byte()?;
+                for _ in 0..self.len()? {
+                    self.payload(element, depth + 1)?;
+                }
+                Some(())
+            }
+            10 => loop {
+                match self.byte()? {
+                    0 => return Some(()),
+                    tag => {
+                        self.string()?;
+                        self.payload(tag, depth + 1)?;
+                    }
+                }
+            },
+            11 => {
+                let len = self.len()?;
+                self.skip(len.checked_mul(4)?)
+            }
+            12 => {
+                let len = self.len()?;
+                self.skip(len.checked_mul(8)?)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Checks the manifest CURRENT points at, and that every table it lists is there and complete
+fn check_db(db: &Path) -> Result<(), String> {
+    let current = fs::read_to_string(db.join("CURRENT")).map_err(|err| {
+        format!("has an unreadable database, its CURRENT file can't be read: {err}")
+    })?;
+    let manifest_name = current.trim_end_matches('\n');
+    if !manifest_name.starts_with("MANIFEST-") || manifest_name.contains(['/', '\\']) {
+        return Err(format!(
+            "has a damaged database, CURRENT names {manifest_name:?}"
+        ));
+    }
+    let manifest = fs::read(db.join(manifest_name))
+        .map_err(|err| format!("has a damaged database, {manifest_name} can't be read: {err}"))?;
+
+    let records = log_records(&manifest).map_err(|offset| {
+        format!("has a damaged database, {manifest_name} has a bad record at byte {offset}")
+    })?;
+    let Some(tables) = live_tables(&records) else {
+        debug!("{manifest_name} has records this check doesn't know, not checking its tables");
+        return Ok(());
+    };
+
+    for (number, size) in tables {
+        // Older versions named tables .sst
+        let table = ["ldb", "sst"]
+            .iter()
+            .map(|extension| db.join(format!("{number:06}.{extension}")))
+            .find(|path| path.is_file())
+            .ok_or_else(|| format!("has a damaged database, table {number:06}.ldb is missing"))?;
+        check_table(&table, size).map_err(|problem| {
+            let name = table.file_name().unwrap_or_default().to_string_lossy();
+            format!("has a damaged database, table {name} {problem}")
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Whether the table has the size the manifest recorded for it, and ends like a table does
+fn check_table(path: &Path, size: u64) -> Result<(), String> {
+    let read = || -> io::Result<(u64, u64)> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        if len < 8 {
+            return Ok((len, 0));
+        }
+        let mut magic = [0; 8];
+        file.seek(SeekFrom::End(-8))?;
+        file.read_exact(&mut magic)?;
+
+        Ok((len, u64::from_le_bytes(magic)))
+    };
+    let (len, magic) = read().map_err(|err| format!("can't be read: {err}"))?;
+
+    if len != size {
+        return Err(format!("is {len} bytes instead of {size}"));
+    }
+    if magic != TABLE_MAGIC {
+        return Err(String::from("does not end like a table"));
+    }
+
+    Ok(())
+}
+
+/// The records of a LevelDB log, with records split across blocks put back together
+/// A record cut off at the end is what a crash or a running server leaves behind, and is not counted as damage
+/// Fails with the offset of the first record whose checksum does not match
+fn log_records(data: &[u8]) -> Result<Vec<Vec<u8>>, usize> {
+    let mut records = Vec::new();
+    let mut partial: Option<Vec<u8>> = None;
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let block_left = LOG_BLOCK_SIZE - offset % LOG_BLOCK_SIZE;
+        // Blocks end in padding when not even a header fits
+        if block_left < LOG_HEADER_SIZE {
+            offset += block_left;
+            continue;
+        }
+        let Some(header) = data.get(offset..offset + LOG_HEADER_SIZE) else {
+            break;
+        };
+        let checksum = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let len = usize::from(u16::from_le_bytes([header[4], header[5]]));
+        let kind = header[6];
+
+        // Space the writer set aside but never used
+        if kind == 0 && len == 0 {
+            offset += block_left;
+            continue;
+        }
+        let start = offset + LOG_HEADER_SIZE;
+        let Some(payload) = data.get(start..start + len) else {
+            break;
+        };
+        if unmask(checksum) != crc32c(&header[6..7], payload) {
+            return Err(offset);
+        }
+
+        match kind {
+            // A whole record
+            1 => records.push(payload.to_vec()),
+            // The first, a middle or the last part of one
+            2 => partial = Some(payload.to_vec()),
+            3 => partial.as_mut().ok_or(offset)?.extend_from_slice(payload),
+            4 => {
+                let mut record = partial.take().ok_or(offset)?;
+                record.extend_from_slice(payload);
+                records.push(record);
+            }
+            _ => return Err(offset),
+        }
+        offset = start + len;
+    }
+
+    Ok(records)
+}
+
+/// The table files the manifest's edits leave in the database, with their sizes
+/// Returns nothing when an edit has a field this check doesn't know, rather than guessing
+fn live_tables(records: &[Vec<u8>]) -> Option<BTreeMap<u64, u64>> {
+    let mut tables = BTreeMap::new();
+    for record in records {
+        let mut edit = Edit {
+            data: record,
+            offset: 0,
+        };
+        while edit.offset < record.len() {
+            match edit.varint()? {
+                // Comparator name
+                1 => edit.slice()?,
+                // Log number, next file number, last sequence and previous log number
+                2 | 3 | 4 | 9 => edit.varint().map(drop)?,
+                // Compaction pointer
+                5 => {
+                    edit.varint()?;
+                    edit.slice()?;
+                }
+                // Deleted table
+                6 => {
+                    edit.varint()?;
+                    tables.remove(&edit.varint()?);
+                }
+                // New table, with its smallest and largest keys
+                7 => {
+                    edit.varint()?;
+                    let number = edit.varint()?;
+                    let size = edit.varint()?;
+                    edit.slice()?;
+                    edit.slice()?;
+                    tables.insert(number, size);
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    Some(tables)
+}
+
+/// Reads the fields of a manifest edit
+struct Edit<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl Edit<'_> {
+    fn varint(&mut self) -> Option<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = *self.data.get(self.offset)?;
+            self.offset += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+
+        None
+    }
+
+    /// Skips a length prefixed byte string
+    fn slice(&mut self) -> Option<()> {
+        let len = usize::try_from(self.varint()?).ok()?;
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())?;
+        self.offset = end;
+
+        Some(())
+    }
+}
+
+/// LevelDB stores its checksums masked, so checksums of data that contains checksums stay useful
+fn unmask(masked: u32) -> u32 {
+    masked.wrapping_sub(0xa282_ead8).rotate_left(15)
+}
+
+/// CRC-32C of the parts one after the other
+fn crc32c(first: &[u8], rest: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut index = 0;
+        while index < 256 {
+            let mut crc = index as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0x82f6_3b78
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[index] = crc;
+            index += 1;
+        }
+        table
+    };
+
+    !first.iter().chain(rest).fold(!0, |crc: u32, byte| {
+        TABLE[usize::from((crc as u8) ^ byte)] ^ (crc >> 8)
+    })
+}